@@ -10,13 +10,13 @@ fn calc_total_fuel(m: i32) -> i32 {
 pub fn part1(input: &str) -> crate::Result<i32> {
     input
         .lines()
-        .map(|l| l.parse::<i32>().and_then(|m| Ok(m / 3 - 2)))
+        .map(|l| l.parse::<i32>().map(|m| m / 3 - 2))
         .try_fold(0, |acc, r| Ok(acc + r?))
 }
 
 pub fn part2(input: &str) -> crate::Result<i32> {
     input
         .lines()
-        .map(|l| l.parse::<i32>().and_then(|m| Ok(calc_total_fuel(m))))
+        .map(|l| l.parse::<i32>().map(calc_total_fuel))
         .try_fold(0, |acc, r| Ok(acc + r?))
 }
@@ -1,70 +1,61 @@
+use crate::intcode::symbolic::{End, Machine};
+use crate::intcode::{Config, Iss, StopReason, Value};
+
 #[derive(Debug)]
 enum Error {
-    IllegalInstruction,
-    AddressOutOfRange,
     OutputNotProduced,
+    NotLinear,
+    UnexpectedIssResult,
 }
 
-fn access(mem: &mut [usize], addr: usize) -> crate::Result<&mut usize> {
-    Ok(mem
-        .get_mut(addr)
-        .ok_or_else(|| crate::Error::boxed(Error::AddressOutOfRange))?)
-}
-
-fn run(mem: &mut [usize]) -> crate::Result<()> {
-    let mut pc = 0;
+fn run(mem: Vec<Value>, noun: Value, verb: Value) -> crate::Result<Value> {
+    let mut iss = Iss::with_config(mem, Config::DAY02);
 
-    while *access(mem, pc)? != 99 {
-        let (a1, a2, a3) = (
-            *access(mem, pc + 1)?,
-            *access(mem, pc + 2)?,
-            *access(mem, pc + 3)?,
-        );
+    *iss.access(1)? = noun;
+    *iss.access(2)? = verb;
 
-        match *access(mem, pc)? {
-            1 => *access(mem, a3)? = *access(mem, a1)? + *access(mem, a2)?,
-            2 => *access(mem, a3)? = *access(mem, a1)? * *access(mem, a2)?,
-            _ => return Err(crate::Error::boxed(Error::IllegalInstruction)),
-        }
-
-        pc += 4
+    match iss.run()? {
+        StopReason::Halted => Ok(*iss.access(0)?),
+        _ => Err(crate::Error::boxed(Error::UnexpectedIssResult)),
     }
-
-    Ok(())
 }
 
-pub fn part1(input: &str) -> crate::Result<usize> {
-    let mut mem: Vec<usize> = input
+pub fn part1(input: &str) -> crate::Result<Value> {
+    let mem: Vec<Value> = input
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
 
-    *access(&mut mem, 1)? = 12;
-    *access(&mut mem, 2)? = 2;
-
-    run(&mut mem)?;
-
-    Ok(*access(&mut mem, 0)?)
+    run(mem, 12, 2)
 }
 
-pub fn part2(input: &str) -> crate::Result<usize> {
-    let mem: Vec<usize> = input
+pub fn part2(input: &str) -> crate::Result<Value> {
+    let mem: Vec<Value> = input
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
     let output = 19_690_720;
 
-    for i1 in 0..100 {
-        for i2 in 0..100 {
-            let mut mem = mem.clone();
-
-            *access(&mut mem, 1)? = i1;
-            *access(&mut mem, 2)? = i2;
-
-            run(&mut mem)?;
-
-            if *access(&mut mem, 0)? == output {
-                return Ok(100 * i1 + i2);
+    // solve for the noun and verb instead of trying them all
+    let mut machine = Machine::new(mem, Config::DAY02);
+    machine.symbolize(1, "noun")?;
+    machine.symbolize(2, "verb")?;
+    for path in machine.explore() {
+        if path.end != End::Halted {
+            return Err(crate::Error::boxed(Error::UnexpectedIssResult));
+        }
+        let linear = path.mem[0]
+            .linear()
+            .ok_or_else(|| crate::Error::boxed(Error::NotLinear))?;
+        let bounds = [("noun", 0..100), ("verb", 0..100)];
+        for solution in linear.solutions(output, &bounds) {
+            let env = |name: &str| match name {
+                "noun" => Some(solution[0]),
+                "verb" => Some(solution[1]),
+                _ => None,
+            };
+            if path.admits(&env) == Some(true) {
+                return Ok(100 * solution[0] + solution[1]);
             }
         }
     }
@@ -18,7 +18,7 @@ fn create_wire(line: &str) -> crate::Result<Wire> {
             |(mut p, mut w), s| -> crate::Result<(Point, Wire)> {
                 let dir = s
                     .chars()
-                    .nth(0)
+                    .next()
                     .ok_or_else(|| crate::Error::boxed(Error::InvalidInput))?;
                 let count = s
                     .get(1..)
@@ -61,8 +61,8 @@ pub fn part1(input: &str) -> crate::Result<i32> {
             .ok_or_else(|| crate::Error::boxed(Error::InvalidInput))?,
     )?;
 
-    let points1 = HashSet::<Point>::from_iter(wire1.into_iter());
-    let points2 = HashSet::<Point>::from_iter(wire2.into_iter());
+    let points1 = HashSet::<Point>::from_iter(wire1);
+    let points2 = HashSet::<Point>::from_iter(wire2);
 
     Ok(points1
         .intersection(&points2)
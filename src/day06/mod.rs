@@ -1,5 +1,4 @@
 use std::collections::{HashMap, HashSet};
-use std::default::Default;
 
 #[derive(Debug)]
 enum Error {
@@ -8,21 +7,12 @@ enum Error {
 
 type Id = usize;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct Node {
     parent: Option<Id>,
     children: Vec<Id>,
 }
 
-impl Default for Node {
-    fn default() -> Self {
-        Node {
-            parent: None,
-            children: Vec::new(),
-        }
-    }
-}
-
 type Tree = HashMap<Id, Node>;
 
 fn build_tree(input: &str) -> crate::Result<(HashMap<&str, Id>, Tree)> {
@@ -58,9 +48,9 @@ fn build_tree(input: &str) -> crate::Result<(HashMap<&str, Id>, Tree)> {
 pub fn part1(input: &str) -> crate::Result<u32> {
     let (_, nodes) = build_tree(input)?;
 
-    Ok(nodes.iter().try_fold(
-        0,
-        |mut acc, (_, mut node)| -> crate::Result<u32> {
+    nodes
+        .iter()
+        .try_fold(0, |mut acc, (_, mut node)| -> crate::Result<u32> {
             while let Some(n) = node.parent {
                 acc += 1;
                 node = nodes
@@ -68,8 +58,7 @@ pub fn part1(input: &str) -> crate::Result<u32> {
                     .ok_or_else(|| crate::Error::boxed(Error::InvalidInput))?;
             }
             Ok(acc)
-        },
-    )?)
+        })
 }
 
 pub fn part2(input: &str) -> crate::Result<u32> {
@@ -81,7 +70,7 @@ pub fn part2(input: &str) -> crate::Result<u32> {
         .get("YOU")
         .ok_or_else(|| crate::Error::boxed(Error::InvalidInput))?;
 
-    let mut dist = 0;
+    let mut dist: u32 = 0;
     let mut frontier = vec![me];
     let mut visited: HashSet<_> = frontier.iter().cloned().collect();
     while !visited.contains(&santa) {
@@ -105,5 +94,5 @@ pub fn part2(input: &str) -> crate::Result<u32> {
         visited.extend(&frontier);
     }
 
-    Ok(if dist <= 2 { 0 } else { dist - 2 })
+    Ok(dist.saturating_sub(2))
 }
@@ -1,4 +1,5 @@
-use crate::day09::{Iss, StopReason, Value};
+use crate::intcode::device::{Framed, Framer};
+use crate::intcode::{Iss, Value};
 
 use std::cmp::{max, min};
 use std::collections::HashMap;
@@ -73,6 +74,26 @@ fn step(p: Pos, d: Dir) -> Pos {
     }
 }
 
+struct Robot<'a> {
+    grid: &'a mut Grid,
+    p: Pos,
+    d: Dir,
+}
+
+impl Framed for Robot<'_> {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        let c = self.grid.entry(self.p).or_insert(Color::Black);
+        Ok(Some((*c).into()))
+    }
+
+    fn frame(&mut self, frame: &[Value]) -> crate::Result<()> {
+        self.grid.insert(self.p, frame[0].try_into()?);
+        self.d = self.d.turn(frame[1])?;
+        self.p = step(self.p, self.d);
+        Ok(())
+    }
+}
+
 fn run(input: &str, grid: &mut Grid) -> crate::Result<()> {
     let mem: Vec<Value> = input
         .split(',')
@@ -80,22 +101,12 @@ fn run(input: &str, grid: &mut Grid) -> crate::Result<()> {
         .collect::<Result<_, _>>()?;
     let mut iss = Iss::new(mem);
 
-    let mut p = (0, 0);
-    let mut d = Dir::Up;
-    loop {
-        let c = grid.entry(p).or_insert(Color::Black);
-        iss.feed_input((*c).into());
-
-        if let (StopReason::Output(o1), StopReason::Output(o2)) =
-            (iss.run()?, iss.run()?)
-        {
-            *c = o1.try_into()?;
-            d = d.turn(o2)?;
-            p = step(p, d);
-        } else {
-            break;
-        }
-    }
+    let robot = Robot {
+        grid,
+        p: (0, 0),
+        d: Dir::Up,
+    };
+    iss.drive(&mut Framer::new(2, robot))?;
 
     Ok(())
 }
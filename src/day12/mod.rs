@@ -53,8 +53,8 @@ where
     I: Iterator<Item = char>,
     T: FromStr,
 {
-    Ok(i.skip_while(|&c| !c.is_digit(10) && c != '-')
-        .take_while(|&c| c.is_digit(10) || c == '-')
+    Ok(i.skip_while(|&c| !c.is_ascii_digit() && c != '-')
+        .take_while(|&c| c.is_ascii_digit() || c == '-')
         .collect::<String>()
         .parse()
         .map_err(|_| crate::Error::boxed(Error::InvalidInput))?)
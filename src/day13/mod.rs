@@ -1,4 +1,5 @@
-use crate::day09::{Iss, Value};
+use crate::intcode::device::{Framed, Framer};
+use crate::intcode::{Budget, Iss, StopReason, Value};
 
 use std::collections::HashMap;
 
@@ -6,79 +7,101 @@ use std::collections::HashMap;
 enum Error {
     PaddleNotFound,
     BallNotFound,
-    UnexpectedOutput,
+    UnexpectedInput,
+    /// The game ran for far longer than a game takes.
+    Hung,
 }
 
+/// More instructions than a whole game takes.
+const MAX_STEPS: u64 = 1 << 24;
+
 type Pos = (Value, Value);
 type Grid = HashMap<Pos, Value>;
 
-enum StopReason {
-    Output(Value, Value, Value),
-    OutOfInput,
-    Halted,
+#[derive(Default)]
+struct Arcade {
+    grid: Grid,
+    score: Value,
+    playing: bool,
 }
 
-fn run(iss: &mut Iss) -> crate::Result<StopReason> {
-    use crate::day09::StopReason::*;
-    match (iss.run()?, iss.run()?, iss.run()?) {
-        (Output(x), Output(y), Output(v)) => Ok(StopReason::Output(x, y, v)),
-        (Halted, _, _) => Ok(StopReason::Halted),
-        (OutOfInput, _, _) => Ok(StopReason::OutOfInput),
-        _ => Err(crate::Error::boxed(Error::UnexpectedOutput)),
+impl Arcade {
+    fn find(&self, tile: Value) -> Option<Value> {
+        self.grid
+            .iter()
+            .find(|(_, &v)| v == tile)
+            .map(|(&(x, _), _)| x)
     }
 }
 
-pub fn part1(input: &str) -> crate::Result<usize> {
+impl Framed for Arcade {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        if !self.playing {
+            return Err(crate::Error::boxed(Error::UnexpectedInput));
+        }
+        let paddle = self
+            .find(3)
+            .ok_or_else(|| crate::Error::boxed(Error::PaddleNotFound))?;
+        let ball = self
+            .find(4)
+            .ok_or_else(|| crate::Error::boxed(Error::BallNotFound))?;
+        Ok(Some((ball - paddle).signum()))
+    }
+
+    fn frame(&mut self, frame: &[Value]) -> crate::Result<()> {
+        match *frame {
+            [-1, 0, s] => self.score = s,
+            [x, y, v] => {
+                self.grid.insert((x, y), v);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// The game, with a budget for all of it.
+fn cabinet(input: &str) -> crate::Result<Iss> {
     let mem: Vec<Value> = input
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
     let mut iss = Iss::new(mem);
+    iss.set_budget(Budget {
+        steps: Some(MAX_STEPS),
+        ..Budget::default()
+    });
+    Ok(iss)
+}
 
-    let mut g = Grid::new();
-    while let StopReason::Output(x, y, v) = run(&mut iss)? {
-        g.insert((x, y), v);
+fn play(iss: &mut Iss, arcade: &mut Framer<Arcade>) -> crate::Result<()> {
+    if iss.drive(arcade)? == StopReason::BudgetExhausted {
+        return Err(crate::Error::boxed(Error::Hung));
     }
+    Ok(())
+}
 
-    Ok(g.values().filter(|&&v| v == 2).count())
+pub fn part1(input: &str) -> crate::Result<usize> {
+    let mut iss = cabinet(input)?;
+
+    let mut arcade = Framer::new(3, Arcade::default());
+    play(&mut iss, &mut arcade)?;
+
+    Ok(arcade.inner.grid.values().filter(|&&v| v == 2).count())
 }
 
 pub fn part2(input: &str) -> crate::Result<Value> {
-    let mem: Vec<Value> = input
-        .split(',')
-        .map(|s| s.parse())
-        .collect::<Result<_, _>>()?;
+    let mut iss = cabinet(input)?;
+    *iss.access(0)? = 2;
 
-    let mut iss = Iss::new(mem);
-    *iss.access(0) = 2;
-
-    let mut grid = Grid::new();
-    let mut score = 0;
-    loop {
-        match run(&mut iss)? {
-            StopReason::Output(-1, 0, s) => score = s,
-            StopReason::Output(x, y, v) => {
-                grid.insert((x, y), v);
-            }
-            StopReason::OutOfInput => {
-                let paddle = grid
-                    .iter()
-                    .find(|(_, &v)| v == 3)
-                    .map(|((x, _), _)| x)
-                    .ok_or_else(|| {
-                        crate::Error::boxed(Error::PaddleNotFound)
-                    })?;
-                let ball = grid
-                    .iter()
-                    .find(|(_, &v)| v == 4)
-                    .map(|((x, _), _)| x)
-                    .ok_or_else(|| crate::Error::boxed(Error::BallNotFound))?;
-
-                iss.feed_input((ball - paddle).signum());
-            }
-            StopReason::Halted => break,
-        }
-    }
+    let mut arcade = Framer::new(
+        3,
+        Arcade {
+            playing: true,
+            ..Arcade::default()
+        },
+    );
+    play(&mut iss, &mut arcade)?;
 
-    Ok(score)
+    Ok(arcade.inner.score)
 }
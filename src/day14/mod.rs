@@ -21,8 +21,8 @@ where
     I: Iterator<Item = char>,
 {
     let n = i
-        .skip_while(|&c| !c.is_digit(10))
-        .take_while(|&c| c.is_digit(10))
+        .skip_while(|&c| !c.is_ascii_digit())
+        .take_while(|&c| c.is_ascii_digit())
         .collect::<String>()
         .parse()
         .map_err(|_| crate::Error::boxed(Error::InvalidInput))?;
@@ -119,7 +119,7 @@ pub fn part1(input: &str) -> crate::Result<i64> {
         .get("ORE")
         .ok_or_else(|| crate::Error::boxed(Error::InvalidInput))?;
 
-    Ok(react(fuel_id, 1, &reactions, &mut HashMap::new(), ore_id)?)
+    react(fuel_id, 1, &reactions, &mut HashMap::new(), ore_id)
 }
 
 pub fn part2(input: &str) -> crate::Result<i64> {
@@ -1,4 +1,4 @@
-use crate::day09::{Iss, StopReason, Value};
+use crate::intcode::{Config, Fork, Iss, StopReason, Value};
 
 use std::collections::{HashSet, VecDeque};
 
@@ -21,7 +21,10 @@ fn step(p: Pos, d: Value) -> crate::Result<Pos> {
     }
 }
 
-fn find_oxygen_system(iss: Iss) -> crate::Result<(Pos, Value, Iss)> {
+fn find_oxygen_system(
+    iss: Iss,
+    copy: Fork,
+) -> crate::Result<(Pos, Value, Iss)> {
     let mut candidates = VecDeque::new();
     candidates.push_back(((0, 0), 0, iss));
     let mut visited = HashSet::new();
@@ -33,7 +36,7 @@ fn find_oxygen_system(iss: Iss) -> crate::Result<(Pos, Value, Iss)> {
             .ok_or_else(|| crate::Error::boxed(Error::NoPathFound))?;
 
         for dir in 1..=4 {
-            let mut iss = iss.clone();
+            let mut iss = copy(&iss);
             iss.feed_input(dir);
 
             match iss.run()? {
@@ -47,7 +50,9 @@ fn find_oxygen_system(iss: Iss) -> crate::Result<(Pos, Value, Iss)> {
 
                     candidates.push_back((pos, dist + 1, iss));
                 }
-                StopReason::Output(2) => break 'outer (step(pos, dir)?, dist + 1, iss),
+                StopReason::Output(2) => {
+                    break 'outer (step(pos, dir)?, dist + 1, iss)
+                }
                 _ => {
                     return Err(crate::Error::boxed(Error::UnexpectedIssResult))
                 }
@@ -56,7 +61,7 @@ fn find_oxygen_system(iss: Iss) -> crate::Result<(Pos, Value, Iss)> {
     })
 }
 
-fn fill_oxygen(iss: Iss) -> crate::Result<(Pos, Value, Iss)> {
+fn fill_oxygen(iss: Iss, copy: Fork) -> crate::Result<(Pos, Value, Iss)> {
     let mut candidates = VecDeque::new();
     candidates.push_back(((0, 0), 0, iss));
     let mut visited = HashSet::new();
@@ -68,7 +73,7 @@ fn fill_oxygen(iss: Iss) -> crate::Result<(Pos, Value, Iss)> {
             .ok_or_else(|| crate::Error::boxed(Error::NoPathFound))?;
 
         for dir in 1..=4 {
-            let mut iss = iss.clone();
+            let mut iss = copy(&iss);
             iss.feed_input(dir);
 
             match iss.run()? {
@@ -95,13 +100,17 @@ fn fill_oxygen(iss: Iss) -> crate::Result<(Pos, Value, Iss)> {
 }
 
 pub fn part1(input: &str) -> crate::Result<Value> {
+    part1_with(input, Iss::fork)
+}
+
+pub fn part1_with(input: &str, copy: Fork) -> crate::Result<Value> {
     let mem: Vec<Value> = input
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
-    let iss = Iss::new(mem);
+    let iss = Iss::with_config(mem, Config::DAY09.compiled());
 
-    let (_, dist, _) = find_oxygen_system(iss)?;
+    let (_, dist, _) = find_oxygen_system(iss, copy)?;
 
     Ok(dist)
 }
@@ -111,10 +120,10 @@ pub fn part2(input: &str) -> crate::Result<Value> {
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
-    let iss = Iss::new(mem);
+    let iss = Iss::with_config(mem, Config::DAY09.compiled());
 
-    let (_, _, iss) = find_oxygen_system(iss)?;
-    let (_, dist, _) = fill_oxygen(iss)?;
+    let (_, _, iss) = find_oxygen_system(iss, Iss::fork)?;
+    let (_, dist, _) = fill_oxygen(iss, Iss::fork)?;
 
     Ok(dist)
 }
@@ -5,8 +5,8 @@ enum Error {
 
 const BASE: &[i32] = &[0, 1, 0, -1];
 
-fn fft(values: &mut Vec<i32>, skip: usize) {
-    let init = values.clone();
+fn fft(values: &mut [i32], skip: usize) {
+    let init = values.to_owned();
     let total_len = init.len() + skip;
 
     for idx in 0..init.len() {
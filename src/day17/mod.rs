@@ -1,12 +1,13 @@
-use crate::day09::{Iss, StopReason, Value};
+use crate::intcode::ascii::AsciiConsole;
+use crate::intcode::{Iss, Value};
 
 use std::collections::HashMap;
-use std::convert::TryFrom;
 
 #[derive(Debug)]
 enum Error {
     RobotNotFound,
     CouldNotSolve,
+    NoDustReport,
 }
 
 type Pos = (i32, i32);
@@ -241,11 +242,10 @@ impl Grid {
     }
 }
 
-fn create_grid(iss: &mut Iss) -> crate::Result<Grid> {
+fn create_grid(console: &mut AsciiConsole) -> crate::Result<Grid> {
     let mut pos = (0, 0);
     let mut grid = Grid::new();
-    while let Ok(StopReason::Output(c)) = iss.run() {
-        let c = u8::try_from(c)?.into();
+    for c in console.run_to_string()?.chars() {
         match c {
             '\n' => pos = (0, pos.1 + 1),
             c => {
@@ -264,7 +264,7 @@ pub fn part1(input: &str) -> crate::Result<i32> {
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
 
-    let grid = create_grid(&mut Iss::new(mem))?;
+    let grid = create_grid(&mut AsciiConsole::new(Iss::new(mem)))?;
 
     Ok(grid
         .map
@@ -281,9 +281,10 @@ pub fn part2(input: &str) -> crate::Result<Value> {
         .collect::<Result<_, _>>()?;
 
     let mut iss = Iss::new(mem);
-    *iss.access(0) = 2;
+    *iss.access(0)? = 2;
+    let mut console = AsciiConsole::new(iss);
 
-    let grid = create_grid(&mut iss)?;
+    let grid = create_grid(&mut console)?;
     let path = grid.calc_path()?;
 
     let mut main = Vec::new();
@@ -292,15 +293,14 @@ pub fn part2(input: &str) -> crate::Result<Value> {
         return Err(crate::Error::boxed(Error::CouldNotSolve));
     }
 
-    iss.feed_str(&main.to_ascii())?;
-    iss.feed_str(&routines[0].to_ascii())?;
-    iss.feed_str(&routines[1].to_ascii())?;
-    iss.feed_str(&routines[2].to_ascii())?;
-    iss.feed_str("n\n")?;
+    console.send_line(&main.to_ascii())?;
+    console.send_line(&routines[0].to_ascii())?;
+    console.send_line(&routines[1].to_ascii())?;
+    console.send_line(&routines[2].to_ascii())?;
+    console.send_line("n")?;
 
-    let mut res = 0;
-    while let StopReason::Output(o) = iss.run()? {
-        res = o;
-    }
-    Ok(res)
+    console.run_to_string()?;
+    console
+        .answer()
+        .ok_or_else(|| crate::Error::boxed(Error::NoDustReport).into())
 }
@@ -47,7 +47,7 @@ impl Vault {
     #[allow(dead_code)]
     fn print(&self) {
         let mut pos = (0, 0);
-        while self.map.get(&pos).is_some() {
+        while self.map.contains_key(&pos) {
             while let Some(c) = self.map.get(&pos) {
                 print!("{}", c);
                 pos.0 += 1;
@@ -106,7 +106,7 @@ fn collect_keys_bfs(
                                 continue;
                             }
 
-                            if have_keys.contains(&c) {
+                            if have_keys.contains(c) {
                                 // got key, treat as empty
                                 droid_ps.push_back(droid_n);
                                 continue;
@@ -233,7 +233,7 @@ pub fn part2(input: &str) -> crate::Result<Dist> {
 }
 
 // #[cfg(test)]
-#[cfg(disabled)]
+#[cfg(any())]
 mod tests {
     use super::*;
 
@@ -1,4 +1,5 @@
-use crate::day09::{Iss, StopReason, Value};
+use crate::intcode::device::Closure;
+use crate::intcode::{Budget, Config, Fork, Iss, StopReason, Value};
 
 use std::collections::HashMap;
 use std::iter;
@@ -6,53 +7,77 @@ use std::iter;
 #[derive(Debug)]
 enum Error {
     UnexpectedIssResult,
+    /// The drone program ran for too long on a single check.
+    Hung,
 }
 
+/// More instructions than any check takes.
+const MAX_STEPS: u64 = 1 << 20;
+
 fn check(
     iss: &Iss,
+    copy: Fork,
     p: (Value, Value),
     cache: &mut HashMap<(Value, Value), Value>,
 ) -> crate::Result<Value> {
     if let Some(v) = cache.get(&p) {
         Ok(*v)
     } else {
-        let mut iss = iss.clone();
-        iss.feed_input(p.0);
-        iss.feed_input(p.1);
-
-        if let StopReason::Output(o) = iss.run()? {
-            cache.insert(p, o);
-            Ok(o)
-        } else {
-            Err(crate::Error::boxed(Error::UnexpectedIssResult))
+        let mut input = vec![p.0, p.1].into_iter();
+        let mut output = None;
+        let reason = copy(iss).drive(&mut Closure::new(
+            || Ok(input.next()),
+            |o| {
+                output = Some(o);
+                Ok(())
+            },
+        ))?;
+        if reason == StopReason::BudgetExhausted {
+            return Err(crate::Error::boxed(Error::Hung));
         }
+
+        let o = output
+            .ok_or_else(|| crate::Error::boxed(Error::UnexpectedIssResult))?;
+        cache.insert(p, o);
+        Ok(o)
     }
 }
 
-pub fn part1(input: &str) -> crate::Result<Value> {
+/// The drone program, with a budget for every check.
+fn drone(input: &str) -> crate::Result<Iss> {
     let mem: Vec<Value> = input
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
-    let iss = Iss::new(mem);
+    let mut iss = Iss::with_config(mem, Config::DAY09.compiled());
+    iss.set_budget(Budget {
+        steps: Some(MAX_STEPS),
+        ..Budget::default()
+    });
+    Ok(iss)
+}
+
+pub fn part1(input: &str) -> crate::Result<Value> {
+    part1_with(input, Iss::fork)
+}
+
+pub fn part1_with(input: &str, copy: Fork) -> crate::Result<Value> {
+    let iss = drone(input)?;
     let mut cache = HashMap::new();
 
     (0..50)
         .flat_map(|x| (0..50).zip(iter::repeat(x)))
-        .map(|p| check(&iss, p, &mut cache))
+        .map(|p| check(&iss, copy, p, &mut cache))
         .try_fold(0, |acc, r| Ok(acc + r?))
 }
 
 pub fn part2(input: &str) -> crate::Result<Value> {
-    let mem: Vec<Value> = input
-        .split(',')
-        .map(|s| s.parse())
-        .collect::<Result<_, _>>()?;
-    let iss = Iss::new(mem);
+    let iss = drone(input)?;
     let mut cache = HashMap::new();
 
-    let mut check =
-        |p| -> crate::Result<bool> { Ok(check(&iss, p, &mut cache)? == 1) };
+    let mut check = |p| -> crate::Result<bool> {
+        Ok(check(&iss, Iss::fork, p, &mut cache)? == 1)
+    };
 
     for y in 99.. {
         for x in 99..=y {
@@ -193,7 +193,7 @@ impl Maze {
             return Err(crate::Error::boxed(Error::InvalidInput));
         }
 
-        m.portals = portals.into_iter().map(|(_, v)| v).collect();
+        m.portals = portals.into_values().collect();
 
         Ok(m)
     }
@@ -290,7 +290,7 @@ impl FromStr for Maze {
         }
         assert_eq!(map.len(), (sz_y * sz_x.unwrap_or(0)) as usize);
 
-        Ok(Maze::new(map, (sz_x.unwrap_or(0), sz_y))?)
+        Maze::new(map, (sz_x.unwrap_or(0), sz_y))
     }
 }
 
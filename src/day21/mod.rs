@@ -1,6 +1,5 @@
-use crate::day09::{Iss, StopReason, Value};
-
-use std::convert::TryFrom;
+use crate::intcode::ascii::AsciiConsole;
+use crate::intcode::{Iss, Value};
 
 #[derive(Debug)]
 enum Error {
@@ -12,30 +11,27 @@ pub fn part1(input: &str) -> crate::Result<Value> {
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
-    let mut iss = Iss::new(mem);
+    let mut console = AsciiConsole::new(Iss::new(mem));
 
     /*
      * J = !A || !B || !C && D
      */
-    iss.feed_str("NOT A J\n")?;
-
-    iss.feed_str("NOT B T\n")?;
-    iss.feed_str("OR T J\n")?;
+    console.send_line("NOT A J")?;
 
-    iss.feed_str("NOT C T\n")?;
-    iss.feed_str("OR T J\n")?;
+    console.send_line("NOT B T")?;
+    console.send_line("OR T J")?;
 
-    iss.feed_str("AND D J\n")?;
+    console.send_line("NOT C T")?;
+    console.send_line("OR T J")?;
 
-    iss.feed_str("WALK\n")?;
+    console.send_line("AND D J")?;
 
-    while let StopReason::Output(o) = iss.run()? {
-        if u8::try_from(o).is_err() {
-            return Ok(o);
-        }
-    }
+    console.send_line("WALK")?;
 
-    Err(crate::Error::boxed(Error::NoOutput))
+    console.run_to_string()?;
+    console
+        .answer()
+        .ok_or_else(|| crate::Error::boxed(Error::NoOutput).into())
 }
 
 pub fn part2(input: &str) -> crate::Result<Value> {
@@ -43,7 +39,7 @@ pub fn part2(input: &str) -> crate::Result<Value> {
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
-    let mut iss = Iss::new(mem);
+    let mut console = AsciiConsole::new(Iss::new(mem));
 
     /*
      * @
@@ -78,35 +74,32 @@ pub fn part2(input: &str) -> crate::Result<Value> {
      */
 
     // (1)
-    iss.feed_str("NOT H J\n")?;
-    iss.feed_str("OR G J\n")?;
+    console.send_line("NOT H J")?;
+    console.send_line("OR G J")?;
 
     // (2)
-    iss.feed_str("AND F J\n")?;
-    iss.feed_str("NOT E T\n")?;
-    iss.feed_str("AND T J\n")?;
+    console.send_line("AND F J")?;
+    console.send_line("NOT E T")?;
+    console.send_line("AND T J")?;
 
     // (3)
-    iss.feed_str("NOT D T\n")?;
-    iss.feed_str("OR T J\n")?;
-    iss.feed_str("OR C J\n")?;
+    console.send_line("NOT D T")?;
+    console.send_line("OR T J")?;
+    console.send_line("OR C J")?;
 
     // (4)
-    iss.feed_str("NOT D T\n")?;
-    iss.feed_str("OR B T\n")?;
-    iss.feed_str("AND T J\n")?;
+    console.send_line("NOT D T")?;
+    console.send_line("OR B T")?;
+    console.send_line("AND T J")?;
 
     // (5)
-    iss.feed_str("AND A J\n")?;
-    iss.feed_str("NOT J J\n")?;
-
-    iss.feed_str("RUN\n")?;
+    console.send_line("AND A J")?;
+    console.send_line("NOT J J")?;
 
-    while let StopReason::Output(o) = iss.run()? {
-        if u8::try_from(o).is_err() {
-            return Ok(o);
-        }
-    }
+    console.send_line("RUN")?;
 
-    Err(crate::Error::boxed(Error::NoOutput))
+    console.run_to_string()?;
+    console
+        .answer()
+        .ok_or_else(|| crate::Error::boxed(Error::NoOutput).into())
 }
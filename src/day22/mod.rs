@@ -31,14 +31,14 @@ impl FromStr for Shuffle {
         if s.starts_with("deal with") {
             let n = s
                 .chars()
-                .skip_while(|c| !c.is_digit(10))
+                .skip_while(|c| !c.is_ascii_digit())
                 .collect::<String>()
                 .parse()?;
             Ok(Shuffle::Increment(n))
         } else if s.starts_with("cut") {
             let n = s
                 .chars()
-                .skip_while(|&c| !c.is_digit(10) && c != '-')
+                .skip_while(|&c| !c.is_ascii_digit() && c != '-')
                 .collect::<String>()
                 .parse()?;
             Ok(Shuffle::Cut(n))
@@ -140,10 +140,7 @@ impl Deck {
 }
 
 fn gen_shuffles(input: &str) -> crate::Result<Vec<Shuffle>> {
-    Ok(input
-        .lines()
-        .map(Shuffle::from_str)
-        .collect::<Result<_, _>>()?)
+    input.lines().map(Shuffle::from_str).collect()
 }
 
 pub fn part1(input: &str) -> crate::Result<usize> {
@@ -402,7 +399,7 @@ mod tests {
         let mut shuffles = gen_shuffles(&inp).unwrap();
         shuffles.reverse();
         for shuffle in &shuffles {
-            deck.shuffle_rev(&shuffle);
+            deck.shuffle_rev(shuffle);
         }
 
         assert_eq!(
@@ -423,7 +420,7 @@ mod tests {
         let mut shuffles = gen_shuffles(&inp).unwrap();
         shuffles.reverse();
         for shuffle in &shuffles {
-            deck.shuffle_rev(&shuffle);
+            deck.shuffle_rev(shuffle);
         }
 
         assert_eq!(
@@ -444,7 +441,7 @@ mod tests {
         let mut shuffles = gen_shuffles(&inp).unwrap();
         shuffles.reverse();
         for shuffle in &shuffles {
-            deck.shuffle_rev(&shuffle);
+            deck.shuffle_rev(shuffle);
         }
 
         assert_eq!(
@@ -472,7 +469,7 @@ mod tests {
         let mut shuffles = gen_shuffles(&inp).unwrap();
         shuffles.reverse();
         for shuffle in &shuffles {
-            deck.shuffle_rev(&shuffle);
+            deck.shuffle_rev(shuffle);
         }
 
         assert_eq!(
@@ -1,127 +1,76 @@
-use crate::day09::{Iss, Value};
-
-use std::convert::TryFrom;
-
-#[derive(Debug)]
-enum Error {
-    UnexpectedIssResult,
-    InvalidAddress,
+use crate::intcode::network::{
+    Action, Capture, Nat, Network, Packet, Scheduler,
+};
+use crate::intcode::{self, Value};
+
+/// The NAT address.
+const NAT: usize = 255;
+
+fn network(input: &str) -> crate::Result<Network> {
+    // tests take the reproducible route
+    let scheduler = if cfg!(test) {
+        Scheduler::Deterministic
+    } else {
+        Scheduler::Threaded
+    };
+    Ok(Network::new(intcode::parse(input)?, 50)
+        .with_nat_address(NAT)
+        .with_scheduler(scheduler))
 }
 
-#[derive(Debug)]
-struct Packet {
-    address: Value,
-    x: Value,
-    y: Value,
-}
+/// Reports the first packet sent to the NAT.
+struct First;
 
-fn run(iss: &mut Iss) -> crate::Result<Option<Packet>> {
-    use crate::day09::StopReason::*;
-    match (iss.run()?, iss.run()?, iss.run()?) {
-        (Output(address), Output(x), Output(y)) => {
-            Ok(Some(Packet { address, x, y }))
-        }
-        (OutOfInput, _, _) => Ok(None),
-        _ => Err(crate::Error::boxed(Error::UnexpectedIssResult)),
+impl Nat for First {
+    fn receive(&mut self, packet: &Packet) -> Action {
+        Action::Stop(packet.y)
+    }
+
+    fn idle(&mut self) -> Action {
+        Action::Continue
     }
 }
 
 pub fn part1(input: &str) -> crate::Result<Value> {
-    let mem: Vec<Value> = input
-        .split(',')
-        .map(|s| s.parse())
-        .collect::<Result<_, _>>()?;
-    let initial = Iss::new(mem);
-
-    let mut vms: Vec<Iss> = (0..50)
-        .map(|address| {
-            let mut vm = initial.clone();
-            vm.feed_input(address);
-            vm
-        })
-        .collect();
+    network(input)?.run(&mut First)
+}
 
-    for address in (0..50).cycle() {
-        vms[address].feed_input(-1);
+/// Keeps the last packet it received and sends it to host 0 whenever the
+/// network is idle, until it sends the same `y` twice in a row.
+#[derive(Default)]
+struct Watchdog {
+    last: Option<Packet>,
+    last_y_sent: Option<Value>,
+}
 
-        while let Some(pkt) = run(&mut vms[address])? {
-            if pkt.address == 255 {
-                return Ok(pkt.y);
-            }
+impl Nat for Watchdog {
+    fn receive(&mut self, packet: &Packet) -> Action {
+        self.last = Some(*packet);
+        Action::Continue
+    }
 
-            let tgt: &mut Iss = vms
-                .get_mut(usize::try_from(pkt.address)?)
-                .ok_or_else(|| crate::Error::boxed(Error::InvalidAddress))?;
-            tgt.feed_input(pkt.x);
-            tgt.feed_input(pkt.y);
+    fn idle(&mut self) -> Action {
+        let last = match self.last {
+            Some(last) => last,
+            None => return Action::Continue,
+        };
+        if self.last_y_sent == Some(last.y) {
+            return Action::Stop(last.y);
         }
+        self.last_y_sent = Some(last.y);
+        Action::Send(Packet {
+            src: NAT,
+            dst: 0,
+            ..last
+        })
     }
-
-    unreachable!();
-}
-
-#[derive(Debug)]
-struct Nat {
-    x: Value,
-    y: Value,
 }
 
 pub fn part2(input: &str) -> crate::Result<Value> {
-    let mem: Vec<Value> = input
-        .split(',')
-        .map(|s| s.parse())
-        .collect::<Result<_, _>>()?;
-    let initial = Iss::new(mem);
-
-    let mut vms: Vec<Iss> = (0..50)
-        .map(|address| {
-            let mut vm = initial.clone();
-            vm.feed_input(address);
-            vm
-        })
-        .collect();
-
-    let mut nat = Nat { x: 0, y: 0 };
-    let mut idle_iter_cnt = 0;
-    let mut last_y_sent = None;
-    for address in (0..50).cycle() {
-        vms[address].feed_input(-1);
-
-        let mut got_packet = false;
-        while let Some(pkt) = run(&mut vms[address])? {
-            got_packet = true;
-
-            if pkt.address == 255 {
-                nat.x = pkt.x;
-                nat.y = pkt.y;
-            } else {
-                let tgt: &mut Iss =
-                    vms.get_mut(usize::try_from(pkt.address)?).ok_or_else(
-                        || crate::Error::boxed(Error::InvalidAddress),
-                    )?;
-                tgt.feed_input(pkt.x);
-                tgt.feed_input(pkt.y);
-            }
-        }
-
-        if got_packet {
-            idle_iter_cnt = 0;
-        } else {
-            idle_iter_cnt += 1;
-        }
-
-        if idle_iter_cnt == 50 {
-            if let Some(last_y_sent) = last_y_sent {
-                if nat.y == last_y_sent {
-                    return Ok(nat.y);
-                }
-            }
-            last_y_sent = Some(nat.y);
-
-            vms[0].feed_input(nat.x);
-            vms[0].feed_input(nat.y);
-        }
-    }
+    network(input)?.run(&mut Watchdog::default())
+}
 
-    unreachable!();
+/// Runs the network of part 2, recording its packets to `capture`.
+pub fn capture(input: &str, capture: &mut Capture) -> crate::Result<Value> {
+    network(input)?.run_captured(&mut Watchdog::default(), capture)
 }
@@ -1,32 +1,222 @@
-use crate::day09::{Iss, StopReason, Value};
+use crate::intcode::{Iss, StopReason, Value};
 
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryFrom;
-use std::io;
 
-pub fn part1(input: &str) -> crate::Result<i32> {
+/// Instructions a single command may take before the game counts as stuck.
+const STEP_BUDGET: usize = 1_000_000;
+
+#[derive(Debug)]
+enum Error {
+    UnexpectedOutput,
+    CheckpointNotFound,
+    NoCombination,
+}
+
+enum Reply {
+    Prompt(String),
+    Ended(String),
+    Stuck,
+}
+
+/// Sends a command, or nothing if `cmd` is empty, and collects the text up
+/// to the next prompt.
+fn send(iss: &mut Iss, cmd: &str) -> crate::Result<Reply> {
+    if !cmd.is_empty() {
+        iss.feed_str(cmd)?;
+        iss.feed_input(Value::from(b'\n'));
+    }
+
+    let mut text = String::new();
+    for _ in 0..STEP_BUDGET {
+        match iss.step()? {
+            Some(StopReason::Output(o)) => text.push(u8::try_from(o)?.into()),
+            Some(StopReason::OutOfInput) => return Ok(Reply::Prompt(text)),
+            Some(StopReason::Halted) => return Ok(Reply::Ended(text)),
+            _ => (),
+        }
+    }
+    Ok(Reply::Stuck)
+}
+
+/// Like `send`, for commands that must not end the game.
+fn command(iss: &mut Iss, cmd: &str) -> crate::Result<String> {
+    match send(iss, cmd)? {
+        Reply::Prompt(text) => Ok(text),
+        _ => Err(crate::Error::boxed(Error::UnexpectedOutput)),
+    }
+}
+
+fn reverse(dir: &str) -> &'static str {
+    match dir {
+        "north" => "south",
+        "south" => "north",
+        "east" => "west",
+        _ => "east",
+    }
+}
+
+struct Room {
+    name: String,
+    doors: Vec<String>,
+    items: Vec<String>,
+    /// Doors to take from the start.
+    path: Vec<String>,
+    /// A machine waiting in this room, without any items.
+    iss: Iss,
+}
+
+impl Room {
+    /// Parses the last room described in `text`.
+    fn parse(text: &str, path: Vec<String>, iss: Iss) -> crate::Result<Self> {
+        let start = text
+            .rfind("== ")
+            .ok_or_else(|| crate::Error::boxed(Error::UnexpectedOutput))?;
+        let mut lines = text[start..].lines();
+        let name = lines.next().unwrap_or_default();
+        let mut room = Room {
+            name: name.trim_matches(|c| c == '=' || c == ' ').to_string(),
+            doors: Vec::new(),
+            items: Vec::new(),
+            path,
+            iss,
+        };
+
+        let mut items = false;
+        for line in lines {
+            match line {
+                "Doors here lead:" => items = false,
+                "Items here:" => items = true,
+                _ => {
+                    if let Some(entry) = line.strip_prefix("- ") {
+                        if items {
+                            room.items.push(entry.to_string());
+                        } else {
+                            room.doors.push(entry.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(room)
+    }
+
+    /// Whether picking up `item` leaves the droid alive and mobile.
+    fn is_safe(&self, item: &str) -> crate::Result<bool> {
+        let mut iss = self.iss.fork();
+        match send(&mut iss, &format!("take {}", item))? {
+            Reply::Prompt(_) => (),
+            _ => return Ok(false),
+        }
+
+        // leave the way we came, which is never the pressure plate
+        let door = match self.path.last() {
+            Some(dir) => reverse(dir),
+            None => &self.doors[0],
+        };
+        Ok(match send(&mut iss, door)? {
+            Reply::Prompt(text) => text.contains("== "),
+            _ => false,
+        })
+    }
+}
+
+/// Maps the ship. Returns its rooms in breadth first order, the index of the
+/// security checkpoint and its door to the pressure plate.
+fn explore(mut iss: Iss) -> crate::Result<(Vec<Room>, usize, String)> {
+    let text = command(&mut iss, "")?;
+    let mut rooms = vec![Room::parse(&text, Vec::new(), iss)?];
+    let mut seen: HashSet<String> = HashSet::new();
+    seen.insert(rooms[0].name.clone());
+
+    let mut checkpoint = None;
+    let mut todo: VecDeque<usize> = VecDeque::new();
+    todo.push_back(0);
+    while let Some(idx) = todo.pop_front() {
+        for door in rooms[idx].doors.clone() {
+            let mut iss = rooms[idx].iss.fork();
+            let text = command(&mut iss, &door)?;
+            if text.contains("Alert!") {
+                checkpoint = Some((idx, door));
+                continue;
+            }
+
+            let mut path = rooms[idx].path.clone();
+            path.push(door);
+            let room = Room::parse(&text, path, iss)?;
+            if seen.insert(room.name.clone()) {
+                todo.push_back(rooms.len());
+                rooms.push(room);
+            }
+        }
+    }
+
+    let (checkpoint, door) = checkpoint
+        .ok_or_else(|| crate::Error::boxed(Error::CheckpointNotFound))?;
+    Ok((rooms, checkpoint, door))
+}
+
+/// Walks from the room at `from` to the room at `to`, both given as paths
+/// from the start.
+fn walk(iss: &mut Iss, from: &[String], to: &[String]) -> crate::Result<()> {
+    let common = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+    for dir in from[common..].iter().rev() {
+        command(iss, reverse(dir))?;
+    }
+    for dir in &to[common..] {
+        command(iss, dir)?;
+    }
+    Ok(())
+}
+
+fn password(text: &str) -> crate::Result<Value> {
+    text.split("typing ")
+        .nth(1)
+        .and_then(|s| s.split_whitespace().next())
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| crate::Error::boxed(Error::UnexpectedOutput).into())
+}
+
+pub fn part1(input: &str) -> crate::Result<Value> {
     let mem: Vec<Value> = input
         .split(',')
         .map(|s| s.parse())
         .collect::<Result<_, _>>()?;
-    let mut iss = Iss::new(mem);
-    let stdin = io::stdin();
-
-    // loop {
-    //     match iss.run()? {
-    //         StopReason::Output(o) => {
-    //             if let Ok(c) = u8::try_from(o) {
-    //                 print!("{}", c as char);
-    //             }
-    //         }
-    //         StopReason::OutOfInput => {
-    //             let mut line = String::new();
-    //             stdin.read_line(&mut line)?;
-    //             iss.feed_str(&line)?;
-    //         }
-    //         _ => break,
-    //     }
-    // }
-
-    // After playing using the above.. x)
-    Ok(8_401_920)
+    let (rooms, checkpoint, plate) = explore(Iss::new(mem))?;
+
+    // collect everything that is safe to carry
+    let mut iss = rooms[0].iss.fork();
+    let mut pos: &[String] = &[];
+    let mut items = Vec::new();
+    for room in &rooms {
+        for item in &room.items {
+            if room.is_safe(item)? {
+                walk(&mut iss, pos, &room.path)?;
+                pos = &room.path;
+                command(&mut iss, &format!("take {}", item))?;
+                items.push(item);
+            }
+        }
+    }
+    walk(&mut iss, pos, &rooms[checkpoint].path)?;
+
+    // try every subset of the inventory, in Gray code order so that each
+    // attempt only takes or drops a single item
+    let mut held = vec![true; items.len()];
+    for attempt in 0..1usize << items.len() {
+        if attempt > 0 {
+            let idx = attempt.trailing_zeros() as usize;
+            let action = if held[idx] { "drop" } else { "take" };
+            command(&mut iss, &format!("{} {}", action, items[idx]))?;
+            held[idx] = !held[idx];
+        }
+
+        match send(&mut iss, &plate)? {
+            Reply::Prompt(text) if text.contains("Alert!") => (),
+            Reply::Prompt(text) | Reply::Ended(text) => return password(&text),
+            Reply::Stuck => break,
+        }
+    }
+
+    Err(crate::Error::boxed(Error::NoCombination))
 }
@@ -0,0 +1,156 @@
+use super::{Iss, StopReason, Value};
+
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+
+#[derive(Debug)]
+enum Error {
+    NonAsciiInput,
+}
+
+/// Talks to a program that speaks ASCII. Output values that are not ASCII
+/// are not printed but kept as the program's answer.
+pub struct AsciiConsole {
+    iss: Iss,
+    transcript: Option<Box<dyn Write>>,
+    answer: Option<Value>,
+}
+
+impl AsciiConsole {
+    pub fn new(iss: Iss) -> Self {
+        AsciiConsole {
+            iss,
+            transcript: None,
+            answer: None,
+        }
+    }
+
+    /// Logs all text exchanged with the program to `transcript`.
+    pub fn with_transcript(mut self, transcript: impl Write + 'static) -> Self {
+        self.transcript = Some(Box::new(transcript));
+        self
+    }
+
+    /// The last non-ASCII value the program printed, if any.
+    pub fn answer(&self) -> Option<Value> {
+        self.answer
+    }
+
+    /// Queues a line of input, terminated by a newline.
+    pub fn send_line(&mut self, line: &str) -> crate::Result<()> {
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if !line.is_ascii() {
+            return Err(crate::Error::boxed(Error::NonAsciiInput));
+        }
+        if let Some(transcript) = &mut self.transcript {
+            writeln!(transcript, "{}", line)?;
+        }
+        self.iss.feed_str(line)?;
+        self.iss.feed_input(Value::from(b'\n'));
+        Ok(())
+    }
+
+    /// Runs until the program waits for input or stops, writing its text to
+    /// `out` as it is produced.
+    pub fn run(&mut self, out: &mut impl Write) -> crate::Result<StopReason> {
+        loop {
+            let o = match self.iss.run()? {
+                StopReason::Output(o) => o,
+                reason => {
+                    if let Some(transcript) = &mut self.transcript {
+                        transcript.flush()?;
+                    }
+                    return Ok(reason);
+                }
+            };
+            match u8::try_from(o) {
+                Ok(c) if c.is_ascii() => {
+                    out.write_all(&[c])?;
+                    if let Some(transcript) = &mut self.transcript {
+                        transcript.write_all(&[c])?;
+                    }
+                }
+                _ => self.answer = Some(o),
+            }
+        }
+    }
+
+    /// Like `run`, but collects the text.
+    pub fn run_to_string(&mut self) -> crate::Result<String> {
+        let mut out = Vec::new();
+        self.run(&mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    /// Lets a user talk to the program until it halts or `input` is
+    /// exhausted, then prints the answer if there was one.
+    pub fn play(
+        &mut self,
+        mut input: impl BufRead,
+        mut out: impl Write,
+    ) -> crate::Result<()> {
+        while let StopReason::OutOfInput = self.run(&mut out)? {
+            out.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            self.send_line(&line)?;
+        }
+
+        if let Some(answer) = self.answer {
+            writeln!(out, "answer: {}", answer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // greets, echoes a line and answers
+    const ECHO: &str = "\
+        out #104
+        out #105
+        out #10
+loop:   in [c]
+        out [c]
+        eq [c], #10, [t]
+        jif [t], #loop
+        out #1000
+        hlt
+c:      .data 0
+t:      .data 0
+";
+
+    fn console() -> AsciiConsole {
+        AsciiConsole::new(Iss::new(assemble(ECHO).unwrap()))
+    }
+
+    #[test]
+    fn lines() {
+        let mut console = console();
+        assert_eq!(console.run_to_string().unwrap(), "hi\n");
+        assert_eq!(console.answer(), None);
+        console.send_line("yo\r\n").unwrap();
+        assert_eq!(console.run_to_string().unwrap(), "yo\n");
+        assert_eq!(console.answer(), Some(1000));
+        assert!(console.send_line("é").is_err());
+    }
+
+    #[test]
+    fn play() {
+        let path = std::env::temp_dir().join("aoc2019-ascii-transcript");
+        let transcript = std::fs::File::create(&path).unwrap();
+        let mut console = console().with_transcript(transcript);
+        let mut out = Vec::new();
+        console.play(&b"yo\nignored\n"[..], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "hi\nyo\nanswer: 1000\n");
+
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(transcript, "hi\nyo\nyo\n");
+    }
+}
@@ -0,0 +1,545 @@
+use super::{Insn, Mode, Value, MAX_MEMORY};
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loc {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    UnexpectedChar,
+    UnexpectedEnd,
+    InvalidNumber,
+    UnknownMnemonic,
+    UnknownDirective,
+    OperandCount,
+    /// A negative count, or a program with more cells than a machine may
+    /// hold.
+    InvalidFill,
+    /// An expression whose value does not fit into a value.
+    Overflow,
+    UndefinedLabel,
+    DuplicateLabel,
+    AddressMismatch,
+}
+
+pub struct Error {
+    pub kind: ErrorKind,
+    pub loc: Loc,
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} at line {}, column {}",
+            self.kind, self.loc.line, self.loc.col
+        )
+    }
+}
+
+fn error<T>(kind: ErrorKind, loc: Loc) -> crate::Result<T> {
+    Err(crate::Error::boxed(Error { kind, loc }))
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Num(Value),
+    Label(String),
+}
+
+/// A sum of signed terms.
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(Value, Term, Loc)>,
+}
+
+impl Expr {
+    fn zero(loc: Loc) -> Self {
+        Expr {
+            terms: vec![(1, Term::Num(0), loc)],
+        }
+    }
+
+    fn eval(&self, labels: &HashMap<String, Value>) -> crate::Result<Value> {
+        let mut sum: Value = 0;
+        for (sign, term, loc) in &self.terms {
+            let v = match term {
+                Term::Num(n) => *n,
+                Term::Label(l) => match labels.get(l) {
+                    Some(v) => *v,
+                    None => return error(ErrorKind::UndefinedLabel, *loc),
+                },
+            };
+            sum = match sign.checked_mul(v).and_then(|v| sum.checked_add(v)) {
+                Some(sum) => sum,
+                None => return error(ErrorKind::Overflow, *loc),
+            };
+        }
+        Ok(sum)
+    }
+}
+
+enum Stmt {
+    Insn(Insn, Vec<Expr>),
+    Data(Vec<Expr>),
+    Fill(usize, Expr),
+}
+
+impl Stmt {
+    fn len(&self) -> usize {
+        match self {
+            Stmt::Insn(insn, _) => insn.len(),
+            Stmt::Data(values) => values.len(),
+            Stmt::Fill(count, _) => *count,
+        }
+    }
+}
+
+enum Label {
+    Name(String),
+    /// A numeric label asserts the current address, as printed by the
+    /// disassembler.
+    Addr(Value),
+}
+
+fn insn(mnemonic: &str, modes: &[Mode], loc: Loc) -> crate::Result<Insn> {
+    Ok(match (mnemonic, modes) {
+        ("add", &[a, b, c]) => Insn::Add([a, b, c]),
+        ("mul", &[a, b, c]) => Insn::Mul([a, b, c]),
+        ("in", &[a]) => Insn::In([a]),
+        ("out", &[a]) => Insn::Out([a]),
+        ("jit", &[a, b]) => Insn::Jit([a, b]),
+        ("jif", &[a, b]) => Insn::Jif([a, b]),
+        ("lt", &[a, b, c]) => Insn::Lt([a, b, c]),
+        ("eq", &[a, b, c]) => Insn::Equ([a, b, c]),
+        ("rbo", &[a]) => Insn::Rbo([a]),
+        ("hlt", &[]) => Insn::Halt,
+        (
+            "add" | "mul" | "in" | "out" | "jit" | "jif" | "lt" | "eq" | "rbo"
+            | "hlt",
+            _,
+        ) => return error(ErrorKind::OperandCount, loc),
+        _ => return error(ErrorKind::UnknownMnemonic, loc),
+    })
+}
+
+struct Cursor {
+    line: usize,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Cursor {
+    fn new(line: usize, s: &str) -> Self {
+        Cursor {
+            line,
+            chars: s.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn loc(&self) -> Loc {
+        Loc {
+            line: self.line,
+            col: self.pos + 1,
+        }
+    }
+
+    /// The next significant character, skipping whitespace. Comments end the
+    /// line.
+    fn peek(&mut self) -> Option<char> {
+        while self.pos < self.chars.len()
+            && self.chars[self.pos].is_whitespace()
+        {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied().filter(|&c| c != ';')
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected<T>(&mut self) -> crate::Result<T> {
+        if self.peek().is_some() {
+            error(ErrorKind::UnexpectedChar, self.loc())
+        } else {
+            error(ErrorKind::UnexpectedEnd, self.loc())
+        }
+    }
+
+    fn expect(&mut self, c: char) -> crate::Result<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.unexpected()
+        }
+    }
+
+    fn end(&mut self) -> crate::Result<()> {
+        if self.peek().is_none() {
+            Ok(())
+        } else {
+            self.unexpected()
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.pos < self.chars.len() && f(self.chars[self.pos]) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+                Some(self.take_while(|c| {
+                    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// A number, negated before it is parsed so that the smallest value has
+    /// a literal.
+    fn number(&mut self, negative: bool) -> crate::Result<Option<Value>> {
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() => {
+                let loc = self.loc();
+                let mut n = self.take_while(|c| c.is_ascii_digit());
+                if negative {
+                    n.insert(0, '-');
+                }
+                match n.parse() {
+                    Ok(n) => Ok(Some(n)),
+                    Err(_) => error(ErrorKind::InvalidNumber, loc),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn label(&mut self) -> crate::Result<Option<(Label, Loc)>> {
+        let start = self.pos;
+        self.peek();
+        let loc = self.loc();
+
+        let label = if let Some(n) = self.number(false)? {
+            Label::Addr(n)
+        } else if let Some(name) = self.ident() {
+            Label::Name(name)
+        } else {
+            return Ok(None);
+        };
+
+        if self.eat(':') {
+            Ok(Some((label, loc)))
+        } else {
+            self.pos = start;
+            Ok(None)
+        }
+    }
+
+    fn expr(&mut self) -> crate::Result<Expr> {
+        let mut terms = Vec::new();
+        let mut sign = if self.eat('-') {
+            -1
+        } else {
+            self.eat('+');
+            1
+        };
+
+        loop {
+            self.peek();
+            let loc = self.loc();
+            if let Some(n) = self.number(sign < 0)? {
+                terms.push((1, Term::Num(n), loc));
+            } else if let Some(name) = self.ident() {
+                terms.push((sign, Term::Label(name), loc));
+            } else {
+                return self.unexpected();
+            }
+
+            sign = if self.eat('+') {
+                1
+            } else if self.eat('-') {
+                -1
+            } else {
+                break;
+            };
+        }
+
+        Ok(Expr { terms })
+    }
+
+    fn operand(&mut self) -> crate::Result<(Mode, Expr)> {
+        if self.eat('[') {
+            let e = self.expr()?;
+            self.expect(']')?;
+            Ok((Mode::Position, e))
+        } else if self.eat('#') {
+            Ok((Mode::Immediate, self.expr()?))
+        } else {
+            self.peek();
+            let start = self.pos;
+            if self.ident().as_deref() != Some("rb") {
+                self.pos = start;
+                return self.unexpected();
+            }
+
+            let e = match self.peek() {
+                Some('+') | Some('-') => self.expr()?,
+                _ => Expr::zero(self.loc()),
+            };
+            Ok((Mode::Relative, e))
+        }
+    }
+
+    fn list<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> crate::Result<T>,
+    ) -> crate::Result<Vec<T>> {
+        let mut items = Vec::new();
+        if self.peek().is_some() {
+            items.push(f(self)?);
+            while self.eat(',') {
+                items.push(f(self)?);
+            }
+        }
+        Ok(items)
+    }
+
+    fn stmt(
+        &mut self,
+        labels: &HashMap<String, Value>,
+    ) -> crate::Result<Option<Stmt>> {
+        self.peek();
+        let loc = self.loc();
+        let word = match self.ident() {
+            Some(word) => word,
+            None if self.peek().is_none() => return Ok(None),
+            None => return self.unexpected(),
+        };
+
+        let stmt = match word.as_str() {
+            ".data" => Stmt::Data(self.list(Cursor::expr)?),
+            ".fill" => {
+                let count = usize::try_from(self.expr()?.eval(labels)?);
+                let count = match count {
+                    Ok(count) if count <= MAX_MEMORY => count,
+                    _ => return error(ErrorKind::InvalidFill, loc),
+                };
+                let value = if self.eat(',') {
+                    self.expr()?
+                } else {
+                    Expr::zero(self.loc())
+                };
+                Stmt::Fill(count, value)
+            }
+            w if w.starts_with('.') => {
+                return error(ErrorKind::UnknownDirective, loc)
+            }
+            mnemonic => {
+                let (modes, args): (Vec<_>, Vec<_>) =
+                    self.list(Cursor::operand)?.into_iter().unzip();
+                Stmt::Insn(insn(mnemonic, &modes, loc)?, args)
+            }
+        };
+
+        self.end()?;
+        Ok(Some(stmt))
+    }
+}
+
+/// Assembles a program into a memory image.
+///
+/// Each line holds any number of `label:` definitions followed by an optional
+/// instruction or directive. Instructions use the mnemonics printed by the
+/// disassembler with `[addr]`, `#value` and `rb+offset` operands, where
+/// addresses, values and offsets may be sums of numbers and labels.
+/// `.data a, b, ...` emits values verbatim and `.fill count[, value]` repeats
+/// a value. Comments start with `;`.
+pub fn assemble(src: &str) -> crate::Result<Vec<Value>> {
+    Ok(assemble_with_labels(src)?.0)
+}
+
+/// Like `assemble`, also returning the first label defined at each address.
+pub fn assemble_with_labels(
+    src: &str,
+) -> crate::Result<(Vec<Value>, BTreeMap<usize, String>)> {
+    let mut labels = HashMap::new();
+    let mut order = Vec::new();
+    let mut stmts = Vec::new();
+    let mut addr = 0;
+
+    for (idx, line) in src.lines().enumerate() {
+        let mut cur = Cursor::new(idx + 1, line);
+
+        while let Some((label, loc)) = cur.label()? {
+            match label {
+                Label::Name(name) => {
+                    if labels.insert(name.clone(), addr as Value).is_some() {
+                        return error(ErrorKind::DuplicateLabel, loc);
+                    }
+                    order.push((name, addr));
+                }
+                Label::Addr(a) => {
+                    if a != addr as Value {
+                        return error(ErrorKind::AddressMismatch, loc);
+                    }
+                }
+            }
+        }
+
+        cur.peek();
+        let loc = cur.loc();
+        if let Some(stmt) = cur.stmt(&labels)? {
+            addr += stmt.len();
+            if addr > MAX_MEMORY {
+                return error(ErrorKind::InvalidFill, loc);
+            }
+            stmts.push(stmt);
+        }
+    }
+
+    let mut mem = Vec::with_capacity(addr);
+    for stmt in stmts {
+        match stmt {
+            Stmt::Insn(insn, args) => {
+                mem.push(insn.encode());
+                for arg in args {
+                    mem.push(arg.eval(&labels)?);
+                }
+            }
+            Stmt::Data(values) => {
+                for v in values {
+                    mem.push(v.eval(&labels)?);
+                }
+            }
+            Stmt::Fill(count, value) => {
+                let v = value.eval(&labels)?;
+                mem.resize(mem.len() + count, v);
+            }
+        }
+    }
+
+    let mut names = BTreeMap::new();
+    for (name, addr) in order {
+        names.entry(addr).or_insert(name);
+    }
+    Ok((mem, names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{disasm, Iss};
+
+    fn error(src: &str) -> String {
+        assemble(src).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn countdown() {
+        let mut src = String::new();
+        src.push_str("        in [n]          ; counter\n");
+        src.push_str("loop:   out [n]\n");
+        src.push_str("        add [n], #-1, [n]\n");
+        src.push_str("        jit [n], #loop\n");
+        src.push_str("        hlt\n");
+        src.push_str("n:      .data 0\n");
+
+        let mem = assemble(&src).unwrap();
+        assert_eq!(
+            mem,
+            vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]
+        );
+
+        let mut iss = Iss::with_input(mem, vec![3]);
+        assert_eq!(iss.run_continuous().unwrap(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn operands_and_directives() {
+        let mut src = String::new();
+        src.push_str("start: rbo #end+2\n");
+        src.push_str("       add rb, rb-1, rb+1\n");
+        src.push_str("       .fill 3, -start-1\n");
+        src.push_str("end:   .data end, 7, -8\n");
+        src.push_str("       .fill 2\n");
+
+        assert_eq!(
+            assemble(&src).unwrap(),
+            vec![109, 11, 22201, 0, -1, 1, -1, -1, -1, 9, 7, -8, 0, 0]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("hlt\n  jit #1, #nowhere"),
+            "UndefinedLabel at line 2, column 12"
+        );
+        assert_eq!(
+            error("  div [1], [2], [3]"),
+            "UnknownMnemonic at line 1, column 3"
+        );
+        assert_eq!(error("out [1], [2]"), "OperandCount at line 1, column 1");
+        assert_eq!(error("out 1"), "UnexpectedChar at line 1, column 5");
+        assert_eq!(error("out [1"), "UnexpectedEnd at line 1, column 7");
+        assert_eq!(
+            error("a: hlt\na: hlt"),
+            "DuplicateLabel at line 2, column 1"
+        );
+        assert_eq!(error("0001: hlt"), "AddressMismatch at line 1, column 1");
+        assert_eq!(error(".org 5"), "UnknownDirective at line 1, column 1");
+        assert_eq!(
+            error(".data 9223372036854775807 + 1"),
+            "Overflow at line 1, column 29"
+        );
+        assert_eq!(
+            error(".data -9223372036854775807 - 1 - 1"),
+            "Overflow at line 1, column 34"
+        );
+        assert_eq!(error(".fill -1"), "InvalidFill at line 1, column 1");
+        assert_eq!(error(".fill 16777217"), "InvalidFill at line 1, column 1");
+        assert_eq!(
+            error(".fill 16777216\n  .fill 16777216\n.fill 16777216"),
+            "InvalidFill at line 2, column 3"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut images = vec![
+            vec![1105, 1, 7, 1099, 10003, -5, 99_999, 21101, 1, 2, 3, 99],
+            vec![204, -1, 3],
+            vec![99, Value::MIN, Value::MAX],
+        ];
+        for inp in &[
+            include_str!("../../input/day09"),
+            include_str!("../../input/day25"),
+        ] {
+            images.push(crate::intcode::parse(inp).unwrap());
+        }
+
+        for mem in images {
+            let listing = disasm::disassemble(&mem).to_string();
+            assert_eq!(assemble(&listing).unwrap(), mem);
+        }
+    }
+}
@@ -0,0 +1,525 @@
+//! Machines generic over the number type, for programs that need values
+//! beyond 64 bits.
+//!
+//! `Machine` executes through the same `Core` as `Iss`. With `Big` as its
+//! number type arithmetic never overflows, with `i64` it behaves like the
+//! interpreter of `Iss`.
+
+use super::exec::{Core, Event, Meter};
+use super::pages::Pages;
+use super::{reach, Budget, Config, Icache, Insn, Mode, StopReason, Value};
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidNumber,
+}
+
+/// The arithmetic a machine needs from its values.
+pub trait Number:
+    Clone + Default + Ord + fmt::Display + FromStr + From<Value> + 'static
+{
+    /// The sum, or `None` if it does not fit.
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    /// The product, or `None` if it does not fit.
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    /// The sum, wrapped around if it does not fit.
+    fn wrapping_add(&self, other: &Self) -> Self;
+    /// The product, wrapped around if it does not fit.
+    fn wrapping_mul(&self, other: &Self) -> Self;
+    /// The value, if it fits into a machine word.
+    fn to_value(&self) -> Option<Value>;
+}
+
+impl Number for Value {
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Value::checked_add(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Value::checked_mul(*self, *other)
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        Value::wrapping_add(*self, *other)
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        Value::wrapping_mul(*self, *other)
+    }
+
+    fn to_value(&self) -> Option<Value> {
+        Some(*self)
+    }
+}
+
+/// An integer of any size.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Big {
+    neg: bool,
+    /// Base 2^32 digits, least significant first, without leading zeros.
+    mag: Vec<u32>,
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() < b.len() { (b, a) } else { (a, b) };
+    let mut res = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0;
+    for (idx, &d) in a.iter().enumerate() {
+        let sum = u64::from(d) + u64::from(*b.get(idx).unwrap_or(&0)) + carry;
+        res.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        res.push(carry as u32);
+    }
+    res
+}
+
+/// `a - b`, where `a` is at least `b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (idx, &d) in a.iter().enumerate() {
+        let sub = i64::from(*b.get(idx).unwrap_or(&0)) + borrow;
+        let mut diff = i64::from(d) - sub;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        res.push(diff as u32);
+    }
+    res
+}
+
+impl Big {
+    fn new(neg: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        Big {
+            neg: neg && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    fn sum(&self, other: &Big) -> Big {
+        if self.neg == other.neg {
+            return Big::new(self.neg, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => {
+                Big::new(other.neg, sub_mag(&other.mag, &self.mag))
+            }
+            _ => Big::new(self.neg, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+
+    fn product(&self, other: &Big) -> Big {
+        let mut res = vec![0u32; self.mag.len() + other.mag.len()];
+        for (i, &a) in self.mag.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.mag.iter().enumerate() {
+                let v =
+                    u64::from(a) * u64::from(b) + u64::from(res[i + j]) + carry;
+                res[i + j] = v as u32;
+                carry = v >> 32;
+            }
+            res[i + other.mag.len()] = carry as u32;
+        }
+        Big::new(self.neg != other.neg, res)
+    }
+
+    /// `self * m + a` for small non-negative numbers.
+    fn mul_add_small(&self, m: u32, a: u32) -> Big {
+        let mut res = Vec::with_capacity(self.mag.len() + 1);
+        let mut carry = u64::from(a);
+        for &d in &self.mag {
+            let v = u64::from(d) * u64::from(m) + carry;
+            res.push(v as u32);
+            carry = v >> 32;
+        }
+        res.push(carry as u32);
+        Big::new(self.neg, res)
+    }
+}
+
+impl From<Value> for Big {
+    fn from(v: Value) -> Self {
+        let m = v.unsigned_abs();
+        Big::new(v < 0, vec![m as u32, (m >> 32) as u32])
+    }
+}
+
+impl Number for Big {
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self.sum(other))
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self.product(other))
+    }
+
+    // nothing ever wraps around
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self.sum(other)
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self.product(other)
+    }
+
+    fn to_value(&self) -> Option<Value> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let m = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0i128, |acc, &d| (acc << 32) | i128::from(d));
+        Value::try_from(if self.neg { -m } else { m }).ok()
+    }
+}
+
+impl Ord for Big {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+            (neg, _) => other.neg.cmp(&neg),
+        }
+    }
+}
+
+impl PartialOrd for Big {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Big {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const CHUNK: u64 = 1_000_000_000;
+
+        // peel off nine decimal digits at a time
+        let mut mag = self.mag.clone();
+        let mut chunks = Vec::new();
+        while !mag.is_empty() {
+            let mut rem = 0;
+            for d in mag.iter_mut().rev() {
+                let v = (rem << 32) | u64::from(*d);
+                *d = (v / CHUNK) as u32;
+                rem = v % CHUNK;
+            }
+            chunks.push(rem);
+            while mag.last() == Some(&0) {
+                mag.pop();
+            }
+        }
+
+        if self.neg {
+            write!(f, "-")?;
+        }
+        match chunks.split_last() {
+            Some((first, rest)) => {
+                write!(f, "{}", first)?;
+                for chunk in rest.iter().rev() {
+                    write!(f, "{:09}", chunk)?;
+                }
+                Ok(())
+            }
+            None => write!(f, "0"),
+        }
+    }
+}
+
+impl FromStr for Big {
+    type Err = Box<crate::Error<Error>>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() {
+            return Err(crate::Error::boxed(Error::InvalidNumber));
+        }
+        let mut res = Big::default();
+        for c in digits.chars() {
+            let d = c
+                .to_digit(10)
+                .ok_or_else(|| crate::Error::boxed(Error::InvalidNumber))?;
+            res = res.mul_add_small(10, d);
+        }
+        Ok(Big::new(neg, res.mag))
+    }
+}
+
+/// Parses a comma separated memory image.
+pub fn parse<N>(input: &str) -> crate::Result<Vec<N>>
+where
+    N: Number,
+    N::Err: error::Error + 'static,
+{
+    Ok(input
+        .trim()
+        .split(',')
+        .map(|s| s.parse())
+        .collect::<Result<_, _>>()?)
+}
+
+/// A machine with values of type `N`. It executes like `Iss` in the
+/// interpreter, under the same configuration, but without any of its
+/// debugging support.
+pub struct Machine<N> {
+    mem: Pages<N>,
+    pc: usize,
+    rb: N,
+    input: VecDeque<N>,
+    icache: Icache,
+    config: Config,
+    meter: Meter,
+}
+
+impl<N: Number> Machine<N> {
+    pub fn new(mem: Vec<N>) -> Self {
+        Machine::with_config(mem, Config::DAY09)
+    }
+
+    /// A machine under `config`, whose execution tier is ignored.
+    pub fn with_config(mem: Vec<N>, config: Config) -> Self {
+        Machine {
+            mem: mem.into(),
+            pc: 0,
+            rb: N::default(),
+            input: VecDeque::new(),
+            icache: Icache::new(config.isa),
+            config,
+            meter: Meter::new(),
+        }
+    }
+
+    pub fn feed_input(&mut self, i: N) {
+        self.input.push_back(i);
+    }
+
+    /// The memory touched so far.
+    pub fn mem(&self) -> &Pages<N> {
+        &self.mem
+    }
+
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.meter.set_budget(budget);
+    }
+
+    /// Runs until the machine outputs a value or stops.
+    pub fn run(&mut self) -> crate::Result<Event<N>> {
+        loop {
+            match self.execute() {
+                Ok(Some(event)) => return Ok(event),
+                Ok(None) => (),
+                Err(e) => return Err(self.fault(e)),
+            }
+        }
+    }
+}
+
+impl<N: Number> Core for Machine<N> {
+    type N = N;
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn meter(&self) -> &Meter {
+        &self.meter
+    }
+
+    fn meter_mut(&mut self) -> &mut Meter {
+        &mut self.meter
+    }
+
+    fn mem(&self) -> &Pages<N> {
+        &self.mem
+    }
+
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn rb(&self) -> N {
+        self.rb.clone()
+    }
+
+    fn read(&mut self, addr: usize) -> crate::Result<N> {
+        reach(&mut self.mem, &self.config, addr)?;
+        Ok(self.mem.get(addr).unwrap_or_default())
+    }
+
+    fn fetch(&mut self) -> crate::Result<Insn> {
+        let val = self.read(self.pc)?.to_value().ok_or_else(|| {
+            crate::Error::boxed(super::Error::IllegalInstruction)
+        })?;
+        self.icache.fetch_insn(val)
+    }
+
+    fn load(&mut self, m: &[Mode], n: usize) -> crate::Result<N> {
+        let addr = self.addr(m, n)?;
+        self.read(addr)
+    }
+
+    fn store(&mut self, m: &[Mode], n: usize, v: N) -> crate::Result<()> {
+        let addr = self.addr(m, n)?;
+        reach(&mut self.mem, &self.config, addr)?;
+        *self.mem.cell_mut(addr) = v;
+        Ok(())
+    }
+
+    fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn input(&mut self) -> N {
+        self.input.pop_front().expect("input checked by has_input")
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.pc += len;
+    }
+
+    fn jump(&mut self, target: usize) -> Option<StopReason> {
+        self.pc = target;
+        None
+    }
+
+    fn rebase(&mut self, rb: N) {
+        self.rb = rb;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use StopReason::{BudgetExhausted as Exhausted, Halted, OutOfInput};
+
+    fn big(s: &str) -> Big {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        for s in &["0", "-1", "4294967296", "-123456789012345678901234567890"] {
+            assert_eq!(big(s).to_string(), *s);
+        }
+        assert!("".parse::<Big>().is_err());
+        assert!("12a".parse::<Big>().is_err());
+        assert_eq!(big("-0"), Big::default());
+
+        let a = big("18446744073709551616");
+        let b = big("-18446744073709551617");
+        assert_eq!(a.checked_add(&b).unwrap(), big("-1"));
+        assert_eq!(b.checked_add(&a).unwrap(), big("-1"));
+        assert_eq!(
+            a.checked_mul(&b).unwrap().to_string(),
+            "-340282366920938463481821351505477763072"
+        );
+        assert!(
+            b < big("-1") && big("-1") < a && a < big("18446744073709551617")
+        );
+        assert_eq!(Big::from(Value::MIN).to_value(), Some(Value::MIN));
+        assert_eq!(a.to_value(), None);
+    }
+
+    // squares its input until it is zero
+    const SQUARE: &str = "\
+loop:   in [x]
+        jif [x], #done
+        mul [x], [x], [x]
+        out [x]
+        jit #1, #loop
+done:   hlt
+x:      .data 0
+";
+
+    fn run<N: Number>(
+        m: &mut Machine<N>,
+    ) -> crate::Result<(Vec<N>, StopReason)> {
+        let mut output = Vec::new();
+        loop {
+            match m.run()? {
+                Event::Output(o) => output.push(o),
+                Event::Stop(reason) => return Ok((output, reason)),
+            }
+        }
+    }
+
+    #[test]
+    fn machine() {
+        let mem: Vec<Big> = parse(
+            &assemble(SQUARE)
+                .unwrap()
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .unwrap();
+        let mut m = Machine::new(mem);
+        m.feed_input(big("-3"));
+        m.feed_input(big("4294967296"));
+        assert_eq!(
+            run(&mut m).unwrap(),
+            (vec![big("9"), big("18446744073709551616")], OutOfInput)
+        );
+        m.feed_input(Big::default());
+        assert_eq!(run(&mut m).unwrap(), (Vec::new(), Halted));
+
+        let mut m = Machine::new(assemble(SQUARE).unwrap());
+        m.feed_input(1 << 32);
+        assert_eq!(
+            run(&mut m).unwrap_err().to_string(),
+            "fault at pc 5 with rb 0, opcode 2: Overflow at pc 5"
+        );
+
+        // relative base, negative addresses
+        let mut m = Machine::<Value>::new(vec![109, 5, 204, -6, 99]);
+        assert_eq!(
+            run(&mut m).unwrap_err().to_string(),
+            "fault at pc 2 with rb 5, opcode 204: NegativeAddress"
+        );
+        let mut m = Machine::<Value>::new(vec![109, 5, 204, -1, 99]);
+        assert_eq!(run(&mut m).unwrap(), (vec![99], Halted));
+
+        // the configuration applies as it does to `Iss`
+        let mut m = Machine::<Big>::with_config(
+            vec![big("109"), big("5"), big("99")],
+            Config::DAY05,
+        );
+        assert_eq!(
+            run(&mut m).unwrap_err().to_string(),
+            "fault at pc 0 with rb 0, opcode 109: IllegalInstruction"
+        );
+        let mut m = Machine::<Big>::new(vec![big("4"), big("1000"), big("99")]);
+        m.set_budget(Budget {
+            steps: Some(1),
+            ..Budget::default()
+        });
+        assert_eq!(run(&mut m).unwrap(), (vec![Big::default()], Exhausted));
+    }
+}
@@ -0,0 +1,351 @@
+//! Control flow graphs of Intcode programs.
+//!
+//! Blocks are built from the instructions the disassembler finds. A function
+//! is a block jumped to unconditionally that raises the relative base first
+//! thing, together with every block reachable from it without calling
+//! another function. A call that pushes the address right after it returns
+//! there.
+
+use super::disasm::{self, Item, Operand};
+use super::{Insn, Mode, Value};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// To the next instruction, including jumps not taken.
+    Fall,
+    /// A jump taken.
+    Jump,
+    /// A jump to the entry of a function.
+    Call,
+    /// From a call to where the function returns to.
+    Return,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: usize,
+    /// The address right after the last instruction.
+    pub end: usize,
+    pub succs: Vec<(usize, Edge)>,
+    /// Whether the block may jump to a computed address.
+    pub indirect: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry: usize,
+    /// The amount the prologue raises the relative base by.
+    pub frame: Value,
+    pub blocks: BTreeSet<usize>,
+}
+
+impl Function {
+    pub fn name(&self) -> String {
+        format!("fn_{:04}", self.entry)
+    }
+
+    /// The address right after the last instruction of the function.
+    pub fn end(&self, cfg: &Cfg) -> usize {
+        self.blocks
+            .iter()
+            .map(|b| cfg.blocks[b].end)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub code: BTreeMap<usize, (Insn, Vec<Operand>)>,
+    pub blocks: BTreeMap<usize, Block>,
+    pub functions: BTreeMap<usize, Function>,
+}
+
+fn is_jump(insn: &Insn) -> bool {
+    matches!(insn, Insn::Jit(_) | Insn::Jif(_))
+}
+
+/// The target of an unconditional jump with an immediate target.
+pub fn goto_target(insn: &Insn, args: &[Operand]) -> Option<usize> {
+    if !is_jump(insn) || args[1].mode != Mode::Immediate {
+        return None;
+    }
+    match (insn, args[0].mode, args[0].value) {
+        (Insn::Jit(_), Mode::Immediate, v) if v != 0 => (),
+        (Insn::Jif(_), Mode::Immediate, 0) => (),
+        _ => return None,
+    }
+    usize::try_from(args[1].value).ok()
+}
+
+/// The amount `rbo #k` raises the relative base by, if positive.
+fn prologue(code: &(Insn, Vec<Operand>)) -> Option<Value> {
+    match code {
+        (Insn::Rbo(_), args) if args[0].mode == Mode::Immediate => {
+            Some(args[0].value).filter(|&k| k > 0)
+        }
+        _ => None,
+    }
+}
+
+impl Cfg {
+    pub fn build(mem: &[Value]) -> Self {
+        let code: BTreeMap<usize, (Insn, Vec<Operand>)> =
+            disasm::disassemble(mem)
+                .items
+                .into_iter()
+                .filter_map(|item| match item {
+                    Item::Insn { addr, insn, args } => {
+                        Some((addr, (insn, args)))
+                    }
+                    Item::Data { .. } => None,
+                })
+                .collect();
+
+        let mut leaders: BTreeSet<usize> = BTreeSet::new();
+        let mut prev_end = None;
+        for (&addr, (insn, args)) in &code {
+            if prev_end != Some(addr) {
+                leaders.insert(addr);
+            }
+            let end = addr + insn.len();
+            if is_jump(insn) || *insn == Insn::Halt {
+                leaders.insert(end);
+                leaders.extend(disasm::successors(addr, insn, args));
+            }
+            prev_end = Some(end);
+        }
+        leaders.retain(|addr| code.contains_key(addr));
+
+        let entries: BTreeSet<usize> = code
+            .iter()
+            .filter_map(|(_, (insn, args))| goto_target(insn, args))
+            .filter(|&t| t != 0 && code.get(&t).and_then(prologue).is_some())
+            .collect();
+
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut end = start;
+            let mut last = start;
+            while let Some((insn, _)) = code.get(&end) {
+                last = end;
+                end += insn.len();
+                if leaders.contains(&end) {
+                    break;
+                }
+            }
+
+            let (insn, args) = &code[&last];
+            let mut block = Block {
+                start,
+                end,
+                succs: Vec::new(),
+                indirect: false,
+            };
+            if is_jump(insn) {
+                let succs = disasm::successors(last, insn, args);
+                if args[1].mode != Mode::Immediate {
+                    block.indirect = args[0].mode != Mode::Immediate
+                        || (args[0].value != 0) == matches!(insn, Insn::Jit(_));
+                }
+                for succ in succs {
+                    let edge = if succ == end
+                        && goto_target(insn, args) != Some(end)
+                    {
+                        Edge::Fall
+                    } else if entries.contains(&succ) {
+                        Edge::Call
+                    } else {
+                        Edge::Jump
+                    };
+                    block.succs.push((succ, edge));
+                }
+                // calls push the address right after them to return to,
+                // and go to a function or to an address computed
+                let pushed = code
+                    .range(start..end)
+                    .flat_map(|(_, (_, args))| args)
+                    .any(|op| {
+                        op.mode == Mode::Immediate && op.value == end as Value
+                    });
+                let call = match goto_target(insn, args) {
+                    Some(target) => entries.contains(&target),
+                    None => block.indirect && args[0].mode == Mode::Immediate,
+                };
+                if pushed && leaders.contains(&end) && call {
+                    block.succs.push((end, Edge::Return));
+                }
+            } else if *insn != Insn::Halt && code.contains_key(&end) {
+                block.succs.push((end, Edge::Fall));
+            }
+            blocks.insert(start, block);
+        }
+
+        let mut functions = BTreeMap::new();
+        for &entry in &entries {
+            let mut seen = BTreeSet::new();
+            let mut todo = vec![entry];
+            while let Some(addr) = todo.pop() {
+                if !seen.insert(addr) {
+                    continue;
+                }
+                let block: &Block = &blocks[&addr];
+                todo.extend(
+                    block
+                        .succs
+                        .iter()
+                        .filter(|(_, edge)| *edge != Edge::Call)
+                        .map(|&(succ, _)| succ)
+                        .filter(|succ| blocks.contains_key(succ)),
+                );
+            }
+            let frame = prologue(&code[&entry]).unwrap_or_default();
+            functions.insert(
+                entry,
+                Function {
+                    entry,
+                    frame,
+                    blocks: seen,
+                },
+            );
+        }
+
+        Cfg {
+            code,
+            blocks,
+            functions,
+        }
+    }
+
+    /// The function starting at `addr`, if any.
+    pub fn function(&self, addr: usize) -> Option<&Function> {
+        self.functions.get(&addr)
+    }
+
+    /// Renders the graph in the Graphviz DOT language, with each function in
+    /// a cluster of its own.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let node = |dot: &mut String, block: &Block, indent: &str| {
+            let mut label = String::new();
+            for (&addr, (insn, args)) in self.code.range(block.start..block.end)
+            {
+                let item = Item::Insn {
+                    addr,
+                    insn: *insn,
+                    args: args.clone(),
+                };
+                let _ = write!(label, "{}\\l", item);
+            }
+            if block.indirect {
+                label.push_str("(indirect)\\l");
+            }
+            let _ = writeln!(
+                dot,
+                "{}b{:04} [label=\"{}\"];",
+                indent, block.start, label
+            );
+        };
+
+        let _ = writeln!(dot, "digraph cfg {{");
+        let _ = writeln!(dot, "    node [shape=box, fontname=\"monospace\"];");
+        let mut clustered = BTreeSet::new();
+        for function in self.functions.values() {
+            let name = function.name();
+            let _ = writeln!(dot, "    subgraph cluster_{} {{", name);
+            let _ = writeln!(dot, "        label=\"{}\";", name);
+            for start in &function.blocks {
+                if clustered.insert(*start) {
+                    node(&mut dot, &self.blocks[start], "        ");
+                }
+            }
+            let _ = writeln!(dot, "    }}");
+        }
+        for block in self.blocks.values() {
+            if !clustered.contains(&block.start) {
+                node(&mut dot, block, "    ");
+            }
+        }
+        for block in self.blocks.values() {
+            for &(succ, edge) in &block.succs {
+                let attrs = match edge {
+                    Edge::Fall => "",
+                    Edge::Jump => " [label=\"jump\"]",
+                    Edge::Call => " [label=\"call\", style=dashed]",
+                    Edge::Return => " [style=dotted]",
+                };
+                let _ = writeln!(
+                    dot,
+                    "    b{:04} -> b{:04}{};",
+                    block.start, succ, attrs
+                );
+            }
+        }
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // calls a function that doubles its argument until it exceeds 100
+    const DOUBLE: &str = "\
+        rbo #stack
+        in [x]
+loop:   add [x], #0, rb+1
+        add #back, #0, rb+0
+        jit #1, #double
+back:   add rb+1, #0, [x]
+        lt [x], #100, [small]
+        jit [small], #loop
+        out [x]
+        hlt
+double: rbo #2
+        mul rb-1, #2, rb-1
+        rbo #-2
+        jif #0, rb+0
+x:      .data 0
+small:  .data 0
+stack:  .data 0
+";
+
+    #[test]
+    fn blocks_and_functions() {
+        let cfg = Cfg::build(&assemble(DOUBLE).unwrap());
+        let starts: Vec<usize> = cfg.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0, 4, 15, 26, 29]);
+
+        let succs = |start| cfg.blocks[&start].succs.clone();
+        assert_eq!(succs(4), vec![(29, Edge::Call), (15, Edge::Return)]);
+        assert_eq!(succs(15), vec![(26, Edge::Fall), (4, Edge::Jump)]);
+        assert!(cfg.blocks[&29].indirect);
+
+        let double = cfg.function(29).unwrap();
+        assert_eq!((double.frame, double.end(&cfg)), (2, 40));
+        assert_eq!(double.blocks.iter().copied().collect::<Vec<_>>(), vec![29]);
+        assert!(cfg.function(0).is_none());
+    }
+
+    #[test]
+    fn dot() {
+        let dot = Cfg::build(&assemble(DOUBLE).unwrap()).to_dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains(
+            "    subgraph cluster_fn_0029 {\n        \
+             label=\"fn_0029\";\n        \
+             b0029 [label=\"0029: rbo #2\\l0031: mul rb-1, #2, rb-1\\l\
+             0035: rbo #-2\\l0037: jif #0, rb+0\\l(indirect)\\l\"];\n    }\n"
+        ));
+        assert!(dot
+            .contains("    b0004 -> b0029 [label=\"call\", style=dashed];\n"));
+        assert!(dot.contains("    b0004 -> b0015 [style=dotted];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
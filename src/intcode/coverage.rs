@@ -0,0 +1,279 @@
+//! Which instructions and branches a program exercised.
+
+use super::disasm::{self, Item};
+use super::{Insn, Mode, Value};
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Instructions and branch outcomes seen during a run.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// The memory image when recording started.
+    pub mem: Vec<Value>,
+    executed: BTreeMap<usize, u64>,
+    /// How often each conditional jump was not taken and taken.
+    branches: BTreeMap<usize, [u64; 2]>,
+}
+
+/// A conditional jump, as opposed to jumps with an immediate condition.
+fn is_branch(insn: &Insn) -> bool {
+    match insn {
+        Insn::Jit(m) | Insn::Jif(m) => m[0] != Mode::Immediate,
+        _ => false,
+    }
+}
+
+fn percent(n: usize, total: usize) -> f64 {
+    100.0 * n as f64 / total.max(1) as f64
+}
+
+impl Coverage {
+    pub(super) fn new(mem: Vec<Value>) -> Self {
+        Coverage {
+            mem,
+            executed: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    pub(super) fn execute(&mut self, pc: usize) {
+        *self.executed.entry(pc).or_default() += 1;
+    }
+
+    pub(super) fn branch(&mut self, pc: usize, taken: bool) {
+        self.branches.entry(pc).or_default()[usize::from(taken)] += 1;
+    }
+
+    /// How often the instruction at `addr` was executed.
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.executed.get(&addr).copied().unwrap_or_default()
+    }
+
+    /// How often the jump at `addr` was not taken and taken.
+    pub fn outcomes(&self, addr: usize) -> [u64; 2] {
+        self.branches.get(&addr).copied().unwrap_or_default()
+    }
+
+    /// The instructions of the program, including those found by following
+    /// the jumps taken at run time.
+    fn listing(&self) -> Vec<Item> {
+        let mut roots = vec![0];
+        roots.extend(self.executed.keys());
+        disasm::disassemble_from(&self.mem[..], &roots).items
+    }
+
+    /// Writes the disassembly, with the first label defined at each address
+    /// from `labels`, each instruction marked `+` if executed and `-` if not,
+    /// followed by the outcomes of conditional jumps.
+    pub fn write_text(
+        &self,
+        labels: &BTreeMap<usize, String>,
+        w: &mut impl Write,
+    ) -> crate::Result<()> {
+        let listing = self.listing();
+        let (mut insns, mut hit) = (0, 0);
+        let (mut outcomes, mut seen) = (0, 0);
+        for item in &listing {
+            if let Item::Insn { addr, insn, .. } = item {
+                insns += 1;
+                hit += usize::from(self.hits(*addr) > 0);
+                if is_branch(insn) {
+                    outcomes += 2;
+                    seen +=
+                        self.outcomes(*addr).iter().filter(|&&n| n > 0).count();
+                }
+            }
+        }
+        writeln!(
+            w,
+            "instructions {}/{} ({:.2}%), branch outcomes {}/{} ({:.2}%)",
+            hit,
+            insns,
+            percent(hit, insns),
+            seen,
+            outcomes,
+            percent(seen, outcomes),
+        )?;
+
+        for item in &listing {
+            match item {
+                Item::Insn { addr, insn, .. } => {
+                    if let Some(label) = labels.get(addr) {
+                        writeln!(w, "{}:", label)?;
+                    }
+                    let mark = if self.hits(*addr) > 0 { '+' } else { '-' };
+                    write!(w, "{} {}", mark, item)?;
+                    if is_branch(insn) {
+                        let [not, taken] = self.outcomes(*addr);
+                        let show = |n: u64| if n > 0 { "" } else { "never " };
+                        write!(
+                            w,
+                            "  ; {}taken, {}not taken",
+                            show(taken),
+                            show(not)
+                        )?;
+                    }
+                    writeln!(w)?;
+                }
+                Item::Data { addr, values } => {
+                    // split data at labels so that each of them gets a line
+                    let mut start = 0;
+                    for end in 1..=values.len() {
+                        if end < values.len()
+                            && !labels.contains_key(&(addr + end))
+                        {
+                            continue;
+                        }
+                        if let Some(label) = labels.get(&(addr + start)) {
+                            writeln!(w, "{}:", label)?;
+                        }
+                        let data = Item::Data {
+                            addr: addr + start,
+                            values: values[start..end].to_vec(),
+                        };
+                        writeln!(w, "  {}", data)?;
+                        start = end;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the coverage as an lcov tracefile for `source`. Lines are
+    /// addresses plus one, since lcov counts lines from one, and every label
+    /// from `labels` that names an instruction is a function.
+    pub fn write_lcov(
+        &self,
+        source: &str,
+        labels: &BTreeMap<usize, String>,
+        w: &mut impl Write,
+    ) -> crate::Result<()> {
+        let listing = self.listing();
+        writeln!(w, "TN:")?;
+        writeln!(w, "SF:{}", source)?;
+
+        let functions: Vec<(usize, &String)> = labels
+            .iter()
+            .filter(|(addr, _)| {
+                listing.iter().any(|item| {
+                    matches!(item, Item::Insn { addr: a, .. } if a == *addr)
+                })
+            })
+            .map(|(&addr, name)| (addr, name))
+            .collect();
+        for (addr, name) in &functions {
+            writeln!(w, "FN:{},{}", addr + 1, name)?;
+        }
+        for (addr, name) in &functions {
+            writeln!(w, "FNDA:{},{}", self.hits(*addr), name)?;
+        }
+        writeln!(w, "FNF:{}", functions.len())?;
+        let called = functions.iter().filter(|(a, _)| self.hits(*a) > 0);
+        writeln!(w, "FNH:{}", called.count())?;
+
+        let (mut lines, mut hit, mut branches, mut taken) = (0, 0, 0, 0);
+        for item in &listing {
+            if let Item::Insn { addr, insn, .. } = item {
+                let hits = self.hits(*addr);
+                if is_branch(insn) {
+                    for (idx, &n) in
+                        self.outcomes(*addr).iter().rev().enumerate()
+                    {
+                        if hits > 0 {
+                            writeln!(w, "BRDA:{},0,{},{}", addr + 1, idx, n)?;
+                        } else {
+                            writeln!(w, "BRDA:{},0,{},-", addr + 1, idx)?;
+                        }
+                        branches += 1;
+                        taken += usize::from(n > 0);
+                    }
+                }
+                writeln!(w, "DA:{},{}", addr + 1, hits)?;
+                lines += 1;
+                hit += usize::from(hits > 0);
+            }
+        }
+        writeln!(w, "BRF:{}", branches)?;
+        writeln!(w, "BRH:{}", taken)?;
+        writeln!(w, "LF:{}", lines)?;
+        writeln!(w, "LH:{}", hit)?;
+        writeln!(w, "end_of_record")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Iss;
+    use crate::intcode::asm::assemble_with_labels;
+
+    // sums inputs until it reads a 0, and never takes the error path
+    const SUM: &str = "\
+loop:   in [x]
+        jif [x], #done
+        lt [x], #0, [neg]
+        jit [neg], #error
+        add [x], [sum], [sum]
+        jit #1, #loop
+done:   out [sum]
+        hlt
+error:  out #-1
+        hlt
+x:      .data 0
+neg:    .data 0
+sum:    .data 0
+";
+
+    #[test]
+    fn reports() {
+        let (mem, labels) = assemble_with_labels(SUM).unwrap();
+        let mut iss = Iss::with_input(mem, vec![2, 3, 0]);
+        iss.start_coverage();
+        assert_eq!(iss.run_continuous().unwrap(), vec![5]);
+        let coverage = iss.take_coverage().unwrap();
+        assert_eq!(coverage.hits(0), 3);
+        assert_eq!(coverage.outcomes(2), [2, 1]);
+        assert_eq!(coverage.outcomes(9), [2, 0]);
+
+        let mut text = Vec::new();
+        coverage.write_text(&labels, &mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "instructions 8/10 (80.00%), branch outcomes 3/4 (75.00%)\n\
+             loop:\n\
+             + 0000: in [25]\n\
+             + 0002: jif [25], #19  ; taken, not taken\n\
+             + 0005: lt [25], #0, [26]\n\
+             + 0009: jit [26], #22  ; never taken, not taken\n\
+             + 0012: add [25], [27], [27]\n\
+             + 0016: jit #1, #0\n\
+             done:\n\
+             + 0019: out [27]\n\
+             + 0021: hlt\n\
+             error:\n\
+             - 0022: out #-1\n\
+             - 0024: hlt\n\
+             x:\n  \
+             0025: .data 0\n\
+             neg:\n  \
+             0026: .data 0\n\
+             sum:\n  \
+             0027: .data 0\n"
+        );
+
+        let mut lcov = Vec::new();
+        coverage.write_lcov("sum.s", &labels, &mut lcov).unwrap();
+        let lcov = String::from_utf8(lcov).unwrap();
+        for line in &[
+            "SF:sum.s\n",
+            "FN:23,error\nFNDA:3,loop\nFNDA:1,done\nFNDA:0,error\n",
+            "FNF:3\nFNH:2\n",
+            "BRDA:10,0,0,0\nBRDA:10,0,1,2\nDA:10,2\n",
+            "BRF:4\nBRH:3\nLF:10\nLH:8\nend_of_record\n",
+        ] {
+            assert!(lcov.contains(line), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Telling that a machine loops forever.
+//!
+//! Every jump backwards compares the machine with a checkpoint taken at an
+//! earlier one, and the checkpoint moves on after twice as many jumps each
+//! time (Brent's algorithm). A machine back in the state of the checkpoint
+//! without having done input or output repeats itself forever. Digests of
+//! memory keep most comparisons from looking at memory at all.
+
+use super::pages::Pages;
+use super::Value;
+
+#[derive(Clone)]
+struct Checkpoint {
+    pc: usize,
+    rb: Value,
+    digest: u64,
+    mem: Pages,
+    steps: u64,
+}
+
+#[derive(Clone)]
+pub(super) struct Cycles {
+    /// The sum of the hashes of all cells.
+    digest: u64,
+    /// Whether memory changed without the digest being updated.
+    stale: bool,
+    steps: u64,
+    jumps: u64,
+    power: u64,
+    checkpoint: Option<Checkpoint>,
+}
+
+fn hash(addr: usize, v: Value) -> u64 {
+    if v == 0 {
+        return 0;
+    }
+    // splitmix64
+    let mut z = (addr as u64 ^ (v as u64).rotate_left(32))
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn digest(mem: &Pages) -> u64 {
+    mem.iter()
+        .enumerate()
+        .chain(mem.far())
+        .fold(0, |d, (addr, v)| d.wrapping_add(hash(addr, v)))
+}
+
+impl Cycles {
+    pub(super) fn new() -> Self {
+        Cycles {
+            digest: 0,
+            stale: true,
+            steps: 0,
+            jumps: 0,
+            power: 1,
+            checkpoint: None,
+        }
+    }
+
+    pub(super) fn step(&mut self) {
+        self.steps += 1;
+    }
+
+    pub(super) fn write(&mut self, addr: usize, old: Value, new: Value) {
+        self.digest = self
+            .digest
+            .wrapping_sub(hash(addr, old))
+            .wrapping_add(hash(addr, new));
+    }
+
+    /// Forgets the checkpoint, after input, output or changes made from
+    /// outside.
+    pub(super) fn reset(&mut self) {
+        self.jumps = 0;
+        self.power = 1;
+        self.checkpoint = None;
+    }
+
+    /// Like `reset`, for changes to memory the digest does not know of.
+    pub(super) fn invalidate(&mut self) {
+        self.reset();
+        self.stale = true;
+    }
+
+    /// Notes a jump backwards to `pc`. Returns the number of instructions
+    /// the machine takes to get back there if it loops forever.
+    pub(super) fn jump(
+        &mut self,
+        pc: usize,
+        rb: Value,
+        mem: &Pages,
+    ) -> Option<u64> {
+        if self.stale {
+            self.digest = digest(mem);
+            self.stale = false;
+        }
+        if let Some(c) = &self.checkpoint {
+            if (c.pc, c.rb, c.digest) == (pc, rb, self.digest)
+                && c.mem.same(mem)
+            {
+                return Some(self.steps - c.steps);
+            }
+        }
+
+        self.jumps += 1;
+        if self.checkpoint.is_none() || self.jumps == self.power {
+            self.checkpoint = Some(Checkpoint {
+                pc,
+                rb,
+                digest: self.digest,
+                mem: mem.clone(),
+                steps: self.steps,
+            });
+            self.jumps = 0;
+            self.power *= 2;
+        }
+        None
+    }
+}
@@ -0,0 +1,692 @@
+use super::disasm::{self, Item};
+use super::{Access, Iss, StopReason, Value};
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+#[derive(Debug)]
+enum Error {
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+}
+
+/// How many of the instructions executed last can be undone. Older ones
+/// are forgotten every so often, which keeps a long run from recording
+/// without bound.
+const HISTORY: usize = 1 << 16;
+
+const MNEMONICS: &[&str] = &[
+    "add", "mul", "in", "out", "jit", "jif", "lt", "eq", "rbo", "hlt",
+];
+
+const HELP: &str = "\
+s, step [n]            execute n instructions (default 1)
+c, continue            run until a breakpoint, output break or stop
+rs, rstep [n]          undo n instructions (default 1)
+rc, rcontinue          undo until a breakpoint, output or watched write
+b, break [addr]        break at addr, or list breakpoints
+b, break op <mnem>     break before any <mnem> instruction
+b, break output        break after each output
+d, delete <as break>   remove a breakpoint
+w, watch [addr[..end]] [r|w|rw] [== value]
+                       stop on accesses to memory (default writes),
+                       optionally only of value, or list watchpoints
+d, delete watch <id>   remove a watchpoint
+x, examine <addr> [n]  print n memory cells (default 1)
+set <addr> <value>     write memory
+set pc|rb <value>      write a register
+r, regs                print pc, rb and pending input
+i, input <values..>    queue input values
+a, ascii <text>        queue text followed by a newline
+l, list [n]            disassemble n items around the pc (default 10)
+save <file>            write a snapshot of the machine
+load <file>            restore a snapshot, dropping watchpoints
+q, quit                leave the debugger
+";
+
+fn arg<T: std::str::FromStr>(arg: Option<&str>) -> crate::Result<T> {
+    arg.ok_or_else(|| crate::Error::boxed(Error::MissingArgument))?
+        .parse()
+        .map_err(|_| crate::Error::boxed(Error::InvalidArgument).into())
+}
+
+fn render(v: Value) -> String {
+    match u8::try_from(v) {
+        Ok(c) if c.is_ascii_graphic() || c == b' ' => {
+            format!("{} '{}'", v, c as char)
+        }
+        Ok(b'\n') => format!("{} '\\n'", v),
+        _ => v.to_string(),
+    }
+}
+
+/// Where execution should stop besides machine stops.
+#[derive(Default)]
+struct Breaks {
+    addrs: BTreeSet<usize>,
+    ops: BTreeSet<&'static str>,
+    output: bool,
+}
+
+impl Breaks {
+    fn hit(&self, iss: &Iss) -> bool {
+        let pc = iss.pc();
+        self.addrs.contains(&pc)
+            || (!self.ops.is_empty()
+                && disasm::decode(iss.mem(), pc)
+                    .is_some_and(|(i, _)| self.ops.contains(i.mnemonic())))
+    }
+}
+
+pub struct Debugger {
+    iss: Iss,
+    breaks: Breaks,
+    /// Whether instructions were dropped from the recording.
+    forgot: bool,
+}
+
+impl Debugger {
+    /// Records what is executed, so that the last `HISTORY` instructions can
+    /// be rewound.
+    pub fn new(mut iss: Iss) -> Self {
+        iss.start_trace();
+        Debugger {
+            iss,
+            breaks: Breaks::default(),
+            forgot: false,
+        }
+    }
+
+    fn forget(&mut self) {
+        if self.iss.truncate_trace(HISTORY) > 0 {
+            self.forgot = true;
+        }
+    }
+
+    /// Where rewinding has to stop.
+    fn start(&self) -> String {
+        if self.forgot {
+            format!(
+                "end of history, only the last {} instructions are kept",
+                HISTORY
+            )
+        } else {
+            "start of recording".to_string()
+        }
+    }
+
+    fn current(&self) -> String {
+        let pc = self.iss.pc();
+        match disasm::decode(self.iss.mem(), pc) {
+            Some((insn, args)) => Item::Insn {
+                addr: pc,
+                insn,
+                args,
+            }
+            .to_string(),
+            None => format!("{:04}: <invalid>", pc),
+        }
+    }
+
+    fn at_break(&self) -> bool {
+        self.breaks.hit(&self.iss)
+    }
+
+    /// Executes up to `steps` instructions, or until the next break if there
+    /// is no limit.
+    fn resume(
+        &mut self,
+        steps: Option<usize>,
+        out: &mut impl Write,
+    ) -> crate::Result<()> {
+        let mut executed = 0;
+        loop {
+            if let Some(steps) = steps {
+                if executed == steps {
+                    break;
+                }
+            } else if executed > 0 && self.at_break() {
+                writeln!(out, "breakpoint at {}", self.iss.pc())?;
+                break;
+            }
+            executed += 1;
+            if executed % HISTORY == 0 {
+                self.forget();
+            }
+
+            match self.iss.step()? {
+                None => (),
+                Some(StopReason::Output(o)) => {
+                    writeln!(out, "output: {}", render(o))?;
+                    if steps.is_none() && self.breaks.output {
+                        break;
+                    }
+                }
+                Some(StopReason::OutOfInput) => {
+                    writeln!(out, "waiting for input")?;
+                    break;
+                }
+                Some(StopReason::Halted) => {
+                    writeln!(out, "halted")?;
+                    break;
+                }
+                Some(StopReason::Watchpoint { addr, old, new, pc }) => {
+                    writeln!(
+                        out,
+                        "watchpoint: [{}] {} -> {} by {}",
+                        addr, old, new, pc
+                    )?;
+                    break;
+                }
+                Some(StopReason::BudgetExhausted) => {
+                    writeln!(out, "budget exhausted")?;
+                    break;
+                }
+                Some(StopReason::InfiniteLoop { pc, period }) => {
+                    writeln!(
+                        out,
+                        "infinite loop at {} every {} instructions",
+                        pc, period
+                    )?;
+                    break;
+                }
+            }
+        }
+
+        self.forget();
+        writeln!(out, "=> {}", self.current())?;
+        Ok(())
+    }
+
+    fn rewind(
+        &mut self,
+        steps: Option<usize>,
+        out: &mut impl Write,
+    ) -> crate::Result<()> {
+        if let Some(steps) = steps {
+            for _ in 0..steps {
+                if self.iss.step_back().is_none() {
+                    writeln!(out, "{}", self.start())?;
+                    break;
+                }
+            }
+        } else {
+            let breaks = &self.breaks;
+            match self.iss.reverse_continue(|iss| breaks.hit(iss)) {
+                Some(StopReason::Output(o)) => {
+                    writeln!(out, "rewound output: {}", render(o))?
+                }
+                Some(StopReason::Watchpoint { addr, old, new, pc }) => {
+                    writeln!(
+                        out,
+                        "rewound watchpoint: [{}] {} -> {} by {}",
+                        addr, old, new, pc
+                    )?
+                }
+                _ if self.at_break() => {
+                    writeln!(out, "breakpoint at {}", self.iss.pc())?
+                }
+                _ => writeln!(out, "{}", self.start())?,
+            }
+        }
+
+        writeln!(out, "=> {}", self.current())?;
+        Ok(())
+    }
+
+    fn set_break(&mut self, args: &[&str], on: bool) -> crate::Result<()> {
+        match args {
+            ["op", mnemonic] => {
+                let m = MNEMONICS.iter().find(|&m| m == mnemonic).ok_or_else(
+                    || crate::Error::boxed(Error::InvalidArgument),
+                )?;
+                if on {
+                    self.breaks.ops.insert(m);
+                } else {
+                    self.breaks.ops.remove(m);
+                }
+            }
+            ["output"] => self.breaks.output = on,
+            [addr] => {
+                let addr = arg(Some(addr))?;
+                if on {
+                    self.breaks.addrs.insert(addr);
+                } else {
+                    self.breaks.addrs.remove(&addr);
+                }
+            }
+            [] => return Err(crate::Error::boxed(Error::MissingArgument)),
+            _ => return Err(crate::Error::boxed(Error::InvalidArgument)),
+        }
+        Ok(())
+    }
+
+    fn set_watch(&mut self, args: &[&str]) -> crate::Result<()> {
+        let range = args
+            .first()
+            .ok_or_else(|| crate::Error::boxed(Error::MissingArgument))?;
+        let (start, end) = match range.find("..") {
+            Some(idx) => {
+                (arg(Some(&range[..idx]))?, arg(Some(&range[idx + 2..]))?)
+            }
+            None => {
+                let addr: usize = arg(Some(range))?;
+                let end = addr.checked_add(1).ok_or_else(|| {
+                    crate::Error::boxed(Error::InvalidArgument)
+                })?;
+                (addr, end)
+            }
+        };
+        let (args, value) = match args {
+            [args @ .., "==", v] => (args, Some(arg::<Value>(Some(v))?)),
+            _ => (args, None),
+        };
+        let access = match args.get(1..) {
+            Some([]) | Some(["w"]) => Access::Write,
+            Some(["r"]) => Access::Read,
+            Some(["rw"]) => Access::Any,
+            _ => return Err(crate::Error::boxed(Error::InvalidArgument)),
+        };
+        match value {
+            Some(v) => self.iss.watch_if(start..end, access, move |n| n == v),
+            None => self.iss.watch(start..end, access),
+        };
+        Ok(())
+    }
+
+    fn list_watches(&self, out: &mut impl Write) -> crate::Result<()> {
+        for (id, range, access) in self.iss.watches() {
+            let access = match access {
+                Access::Read => "r",
+                Access::Write => "w",
+                Access::Any => "rw",
+            };
+            writeln!(
+                out,
+                "watch {}: {}..{} {}",
+                id, range.start, range.end, access
+            )?;
+        }
+        Ok(())
+    }
+
+    fn list_breaks(&self, out: &mut impl Write) -> crate::Result<()> {
+        for addr in &self.breaks.addrs {
+            writeln!(out, "break {}", addr)?;
+        }
+        for op in &self.breaks.ops {
+            writeln!(out, "break op {}", op)?;
+        }
+        if self.breaks.output {
+            writeln!(out, "break output")?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, n: usize, out: &mut impl Write) -> crate::Result<()> {
+        let pc = self.iss.pc();
+        let listing = disasm::disassemble_from(self.iss.mem(), &[0, pc]);
+        let idx = listing
+            .items
+            .iter()
+            .position(|item| pc < item.addr() + item.len())
+            .unwrap_or(listing.items.len());
+
+        let start = idx.saturating_sub(n / 2);
+        for item in listing.items.iter().skip(start).take(n) {
+            let marker = if item.addr() == pc { "=>" } else { "  " };
+            writeln!(out, "{} {}", marker, item)?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single command. Returns `false` once the user quits.
+    pub fn command(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+    ) -> crate::Result<bool> {
+        let mut words = line.split_whitespace();
+        let cmd = if let Some(cmd) = words.next() {
+            cmd
+        } else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = words.collect();
+
+        match cmd {
+            "s" | "step" => {
+                let n = args.first().map_or(Ok(1), |&n| arg(Some(n)))?;
+                self.resume(Some(n), out)?;
+            }
+            "c" | "continue" => self.resume(None, out)?,
+            "rs" | "rstep" => {
+                let n = args.first().map_or(Ok(1), |&n| arg(Some(n)))?;
+                self.rewind(Some(n), out)?;
+            }
+            "rc" | "rcontinue" => self.rewind(None, out)?,
+            "b" | "break" if args.is_empty() => self.list_breaks(out)?,
+            "b" | "break" => self.set_break(&args, true)?,
+            "d" | "delete" if args.first() == Some(&"watch") => {
+                if !self.iss.unwatch(arg(args.get(1).copied())?) {
+                    return Err(crate::Error::boxed(Error::InvalidArgument));
+                }
+            }
+            "d" | "delete" => self.set_break(&args, false)?,
+            "w" | "watch" if args.is_empty() => self.list_watches(out)?,
+            "w" | "watch" => self.set_watch(&args)?,
+            "x" | "examine" => {
+                let addr: usize = arg(args.first().copied())?;
+                let n = args.get(1).map_or(Ok(1), |&n| arg(Some(n)))?;
+                let end = addr.checked_add(n).ok_or_else(|| {
+                    crate::Error::boxed(Error::InvalidArgument)
+                })?;
+                for addr in addr..end {
+                    let v = self.iss.mem().get(addr).unwrap_or_default();
+                    writeln!(out, "{:04}: {}", addr, v)?;
+                }
+            }
+            "set" => match args.as_slice() {
+                ["pc", v] => self.iss.set_pc(arg(Some(v))?),
+                ["rb", v] => self.iss.set_rb(arg(Some(v))?),
+                [addr, v] => {
+                    *self.iss.access(arg(Some(addr))?)? = arg(Some(v))?
+                }
+                _ => return Err(crate::Error::boxed(Error::MissingArgument)),
+            },
+            "r" | "regs" => {
+                let input: Vec<_> = self.iss.pending_input().collect();
+                writeln!(
+                    out,
+                    "pc: {} rb: {} input: {:?}",
+                    self.iss.pc(),
+                    self.iss.rb(),
+                    input
+                )?;
+            }
+            "i" | "input" => {
+                let values = args
+                    .iter()
+                    .map(|&v| arg(Some(v)))
+                    .collect::<crate::Result<Vec<Value>>>()?;
+                for v in values {
+                    self.iss.feed_input(v);
+                }
+            }
+            "a" | "ascii" => {
+                let text = line.trim_start()[cmd.len()..].trim_start();
+                self.iss.feed_str(text)?;
+                self.iss.feed_input(Value::from(b'\n'));
+            }
+            "l" | "list" => {
+                let n = args.first().map_or(Ok(10), |&n| arg(Some(n)))?;
+                self.list(n, out)?;
+            }
+            "save" => {
+                let path: String = arg(args.first().copied())?;
+                self.iss.save(File::create(path)?)?;
+            }
+            "load" => {
+                let path: String = arg(args.first().copied())?;
+                self.iss = Iss::load(BufReader::new(File::open(path)?))?;
+                self.iss.start_trace();
+                self.forgot = false;
+                writeln!(out, "=> {}", self.current())?;
+            }
+            "h" | "help" => write!(out, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => return Err(crate::Error::boxed(Error::UnknownCommand)),
+        }
+
+        Ok(true)
+    }
+
+    /// Reads commands from `input` until it is exhausted or the user quits.
+    pub fn repl(
+        &mut self,
+        mut input: impl BufRead,
+        mut out: impl Write,
+    ) -> crate::Result<()> {
+        writeln!(out, "=> {}", self.current())?;
+        loop {
+            write!(out, "(idb) ")?;
+            out.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            match self.command(&line, &mut out) {
+                Ok(true) => (),
+                Ok(false) => return Ok(()),
+                Err(e) => writeln!(out, "error: {}", e)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    fn session(src: &str, commands: &[&str]) -> String {
+        let mem = assemble(src).unwrap();
+        let mut dbg = Debugger::new(Iss::new(mem));
+        let mut out = Vec::new();
+        for cmd in commands {
+            dbg.command(cmd, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    const ECHO: &str = "\
+loop:   in [c]
+        out [c]
+        jit #1, #loop
+c:      .data 0
+";
+
+    #[test]
+    fn step_and_continue() {
+        let out = session(ECHO, &["s", "i 65 66", "s 2", "b 4", "c", "c"]);
+        assert_eq!(
+            out,
+            "waiting for input\n\
+             => 0000: in [7]\n\
+             output: 65 'A'\n\
+             => 0004: jit #1, #0\n\
+             output: 66 'B'\n\
+             breakpoint at 4\n\
+             => 0004: jit #1, #0\n\
+             waiting for input\n\
+             => 0000: in [7]\n"
+        );
+    }
+
+    #[test]
+    fn break_on_opcode_and_output() {
+        let out = session(
+            ECHO,
+            &[
+                "b op jit", "b output", "b", "d output", "a x", "c",
+                "d op jit", "b output", "c", "c",
+            ],
+        );
+        assert_eq!(
+            out,
+            "break op jit\n\
+             break output\n\
+             output: 120 'x'\n\
+             breakpoint at 4\n\
+             => 0004: jit #1, #0\n\
+             output: 10 '\\n'\n\
+             => 0004: jit #1, #0\n\
+             waiting for input\n\
+             => 0000: in [7]\n"
+        );
+    }
+
+    #[test]
+    fn memory_and_registers() {
+        let out = session(
+            ECHO,
+            &["set 7 42", "set rb -3", "set pc 2", "x 6 2", "r", "l 3"],
+        );
+        assert_eq!(
+            out,
+            "0006: 0\n\
+             0007: 42\n\
+             pc: 2 rb: -3 input: []\n\
+             \x20  0000: in [7]\n\
+             => 0002: out [7]\n\
+             \x20  0004: jit #1, #0\n"
+        );
+
+        // examining memory leaves it as it is
+        let mut dbg = Debugger::new(Iss::new(assemble(ECHO).unwrap()));
+        let mut out = Vec::new();
+        dbg.command("x 100000 2", &mut out).unwrap();
+        assert_eq!(out, b"100000: 0\n100001: 0\n");
+        assert_eq!(dbg.iss.mem().cells(), 8);
+
+        // far code is decoded but not listed
+        let out = session(
+            ECHO,
+            &["set 1099511627776 99", "set pc 1099511627776", "l 2", "s"],
+        );
+        assert_eq!(out, "\x20  0007: .data 0\nhalted\n=> 1099511627776: hlt\n");
+    }
+
+    #[test]
+    fn watchpoints() {
+        let out = session(
+            ECHO,
+            &[
+                "w 7",
+                "w 7 r",
+                "i 5 6",
+                "c",
+                "c",
+                "w",
+                "d watch 0",
+                "c",
+                "d watch 1",
+                "w 6..8 rw == 9",
+                "i 8 9",
+                "c",
+            ],
+        );
+        assert_eq!(
+            out,
+            "watchpoint: [7] 0 -> 5 by 0\n\
+             => 0002: out [7]\n\
+             watchpoint: [7] 5 -> 5 by 2\n\
+             => 0004: jit #1, #0\n\
+             watch 0: 7..8 w\n\
+             watch 1: 7..8 r\n\
+             output: 5\n\
+             watchpoint: [7] 6 -> 6 by 2\n\
+             => 0004: jit #1, #0\n\
+             output: 6\n\
+             output: 8\n\
+             watchpoint: [7] 8 -> 9 by 0\n\
+             => 0002: out [7]\n"
+        );
+    }
+
+    #[test]
+    fn reverse() {
+        let out = session(
+            ECHO,
+            &[
+                "i 1 2 3",
+                "c",
+                "rc",
+                "w 7",
+                "rc",
+                "d watch 0",
+                "b 4",
+                "rc",
+                "rs 10",
+            ],
+        );
+        assert_eq!(
+            out,
+            "output: 1\n\
+             output: 2\n\
+             output: 3\n\
+             waiting for input\n\
+             => 0000: in [7]\n\
+             rewound output: 3\n\
+             => 0002: out [7]\n\
+             rewound watchpoint: [7] 2 -> 3 by 0\n\
+             => 0000: in [7]\n\
+             breakpoint at 4\n\
+             => 0004: jit #1, #0\n\
+             start of recording\n\
+             => 0000: in [7]\n"
+        );
+    }
+
+    #[test]
+    fn history() {
+        let out = session("jit #1, #0", &["s 100000", "rs 65536", "rs"]);
+        assert_eq!(
+            out,
+            "=> 0000: jit #1, #0\n\
+             => 0000: jit #1, #0\n\
+             end of history, only the last 65536 instructions are kept\n\
+             => 0000: jit #1, #0\n"
+        );
+    }
+
+    #[test]
+    fn snapshots() {
+        let path = std::env::temp_dir().join("aoc2019-debugger-snapshot");
+        let path = path.to_str().unwrap();
+        let save = format!("save {}", path);
+        let load = format!("load {}", path);
+        let out = session(
+            ECHO,
+            &["i 7 8", "s 2", &save, "s 4", "x 7", &load, "x 7", "r"],
+        );
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            out,
+            "output: 7\n\
+             => 0004: jit #1, #0\n\
+             output: 8\n\
+             => 0000: in [7]\n\
+             0007: 8\n\
+             => 0004: jit #1, #0\n\
+             0007: 7\n\
+             pc: 4 rb: 0 input: [8]\n"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut dbg = Debugger::new(Iss::new(assemble(ECHO).unwrap()));
+        let mut out = Vec::new();
+        assert!(dbg.command("frobnicate", &mut out).is_err());
+        assert!(dbg.command("b op div", &mut out).is_err());
+        assert!(dbg.command("x", &mut out).is_err());
+        assert!(dbg.command("x 18446744073709551615 2", &mut out).is_err());
+        assert!(dbg.command("w 18446744073709551615", &mut out).is_err());
+        assert!(dbg.command("w 3 x", &mut out).is_err());
+        assert!(dbg.command("d watch 0", &mut out).is_err());
+        assert!(!dbg.command("q", &mut out).unwrap());
+
+        let mut dbg = Debugger::new(Iss::new(vec![42]));
+        let e = dbg.command("s", &mut out).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "fault at pc 0 with rb 0, opcode 42: IllegalInstruction"
+        );
+    }
+}
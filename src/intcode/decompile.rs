@@ -0,0 +1,527 @@
+//! Lifting control flow graphs into pseudo-code.
+//!
+//! This is best effort: backward jumps become loops, forward jumps over a
+//! run of instructions become `if` and `else`, and everything else is left as
+//! a `goto`. Inside functions, cells relative to `rb` are named after their
+//! place in the frame: `ret` and `arg1`, `arg2`, ... live in the caller's
+//! frame below the base, `local0`, `local1`, ... above it. Cells outside
+//! of both frames keep their `rb[n]` name.
+
+use super::cfg::{self, Cfg, Function};
+use super::disasm::Operand;
+use super::{Insn, Mode, Value};
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+struct Decompiler<'a> {
+    cfg: &'a Cfg,
+    function: Option<&'a Function>,
+    /// Targets of the `goto`s emitted, which need labels.
+    gotos: BTreeSet<usize>,
+    out: String,
+}
+
+/// A loop being emitted, with the address of its head and the one right
+/// after it.
+#[derive(Clone, Copy)]
+struct Loop {
+    head: usize,
+    exit: usize,
+}
+
+impl<'a> Decompiler<'a> {
+    fn line(&mut self, depth: usize, text: &str) {
+        let _ = writeln!(self.out, "{:1$}{2}", "", 4 * depth, text);
+    }
+
+    fn operand(&self, op: &Operand) -> String {
+        match (op.mode, self.function) {
+            (Mode::Immediate, _) => {
+                match usize::try_from(op.value)
+                    .ok()
+                    .and_then(|a| self.cfg.function(a))
+                {
+                    Some(f) => f.name(),
+                    None => op.value.to_string(),
+                }
+            }
+            (Mode::Position, _) => format!("m{}", op.value),
+            (Mode::Relative, Some(f)) => {
+                match op.value.checked_add(f.frame) {
+                    Some(0) => "ret".to_string(),
+                    Some(slot) if slot > 0 && op.value < 0 => {
+                        format!("arg{}", slot)
+                    }
+                    Some(_) if op.value >= 0 => format!("local{}", op.value),
+                    // below the caller's frame, or past any frame at all
+                    _ => format!("rb[{}]", op.value),
+                }
+            }
+            (Mode::Relative, None) => format!("rb[{}]", op.value),
+        }
+    }
+
+    /// Whether the jump at `addr` to `target` goes through the return slot,
+    /// counting the changes to `rb` earlier in its block but after the
+    /// prologue.
+    fn returns(&self, addr: usize, target: &Operand) -> bool {
+        let f = match self.function {
+            Some(f) if target.mode == Mode::Relative => f,
+            _ => return false,
+        };
+        let start = self
+            .cfg
+            .blocks
+            .range(..=addr)
+            .next_back()
+            .map_or(addr, |(&start, _)| start);
+        self.cfg
+            .code
+            .range(start..addr)
+            .filter(|(&a, _)| a != f.entry)
+            .filter_map(|(_, (insn, args))| match insn {
+                Insn::Rbo(_) if args[0].mode == Mode::Immediate => {
+                    Some(args[0].value)
+                }
+                _ => None,
+            })
+            .try_fold(target.value, Value::checked_add)
+            .and_then(|v| v.checked_add(f.frame))
+            == Some(0)
+    }
+
+    /// The condition under which a jump is taken, or not taken.
+    fn condition(&self, insn: &Insn, args: &[Operand], taken: bool) -> String {
+        let value = self.operand(&args[0]);
+        if taken == matches!(insn, Insn::Jit(_)) {
+            value
+        } else {
+            format!("!{}", value)
+        }
+    }
+
+    fn statement(
+        &self,
+        addr: usize,
+        insn: &Insn,
+        args: &[Operand],
+    ) -> Option<String> {
+        let op = |n: usize| self.operand(&args[n]);
+        let imm = |n: usize, v: Value| {
+            args[n].mode == Mode::Immediate && args[n].value == v
+        };
+        Some(match insn {
+            Insn::Add(_) if imm(1, 0) => format!("{} = {}", op(2), op(0)),
+            Insn::Add(_) if imm(0, 0) => format!("{} = {}", op(2), op(1)),
+            Insn::Add(_)
+                if args[1].mode == Mode::Immediate && args[1].value < 0 =>
+            {
+                let n = args[1].value.unsigned_abs();
+                format!("{} = {} - {}", op(2), op(0), n)
+            }
+            Insn::Add(_) => format!("{} = {} + {}", op(2), op(0), op(1)),
+            Insn::Mul(_) if imm(1, 1) => format!("{} = {}", op(2), op(0)),
+            Insn::Mul(_) if imm(0, 1) => format!("{} = {}", op(2), op(1)),
+            Insn::Mul(_) => format!("{} = {} * {}", op(2), op(0), op(1)),
+            Insn::Lt(_) => format!("{} = {} < {}", op(2), op(0), op(1)),
+            Insn::Equ(_) => format!("{} = {} == {}", op(2), op(0), op(1)),
+            Insn::In(_) => format!("{} = input()", op(0)),
+            Insn::Out(_) => format!("output({})", op(0)),
+            Insn::Rbo(_) => {
+                // the prologue and epilogue are implied by the function
+                if let Some(f) = self.function {
+                    let imm = args[0].mode == Mode::Immediate;
+                    if imm
+                        && (addr == f.entry && args[0].value == f.frame
+                            || args[0].value == -f.frame)
+                    {
+                        return None;
+                    }
+                }
+                format!("rb += {}", op(0))
+            }
+            Insn::Halt => "halt()".to_string(),
+            Insn::Jit(_) | Insn::Jif(_) => return None,
+        })
+    }
+
+    /// The start of a loop at `head` within `head..end`: the last jump back
+    /// to it.
+    fn back_edge(&self, head: usize, end: usize) -> Option<usize> {
+        self.cfg
+            .code
+            .range(head..end)
+            .filter(|(_, (insn, args))| {
+                matches!(insn, Insn::Jit(_) | Insn::Jif(_))
+                    && args[1].mode == Mode::Immediate
+                    && args[1].value == head as Value
+                    && !(args[0].mode == Mode::Immediate
+                        && cfg::goto_target(insn, args).is_none())
+            })
+            .map(|(&addr, _)| addr)
+            .next_back()
+    }
+
+    fn jump(
+        &mut self,
+        target: usize,
+        cond: Option<String>,
+        lp: Option<Loop>,
+        depth: usize,
+    ) {
+        let action = match lp {
+            Some(lp) if target == lp.exit => "break;".to_string(),
+            Some(lp) if target == lp.head => "continue;".to_string(),
+            _ => {
+                self.gotos.insert(target);
+                format!("goto L{:04};", target)
+            }
+        };
+        match cond {
+            Some(cond) => {
+                self.line(depth, &format!("if ({}) {}", cond, action))
+            }
+            None => self.line(depth, &action),
+        }
+    }
+
+    fn emit(
+        &mut self,
+        start: usize,
+        end: usize,
+        lp: Option<Loop>,
+        depth: usize,
+        labels: &BTreeSet<usize>,
+    ) {
+        let mut pc = start;
+        while let Some((&addr, (insn, args))) =
+            self.cfg.code.range(pc..end).next()
+        {
+            let head = lp.is_some_and(|lp| lp.head == addr);
+            if labels.contains(&addr) && !head {
+                self.line(depth.saturating_sub(1), &format!("L{:04}:", addr));
+            }
+            if !head {
+                if let Some(back) = self.back_edge(addr, end) {
+                    let (binsn, bargs) = &self.cfg.code[&back];
+                    let exit = back + binsn.len();
+                    let inner = Some(Loop { head: addr, exit });
+                    if cfg::goto_target(binsn, bargs).is_some() {
+                        self.line(depth, "loop {");
+                        self.emit(addr, back, inner, depth + 1, labels);
+                        self.line(depth, "}");
+                    } else {
+                        let cond = self.condition(binsn, bargs, true);
+                        self.line(depth, "do {");
+                        self.emit(addr, back, inner, depth + 1, labels);
+                        self.line(depth, &format!("}} while ({});", cond));
+                    }
+                    pc = exit;
+                    continue;
+                }
+            }
+
+            let next = addr + insn.len();
+            pc = next;
+            if !matches!(insn, Insn::Jit(_) | Insn::Jif(_)) {
+                if let Some(stmt) = self.statement(addr, insn, args) {
+                    self.line(depth, &format!("{};", stmt));
+                }
+                continue;
+            }
+
+            let target = match args[1].mode {
+                Mode::Immediate => usize::try_from(args[1].value).ok(),
+                _ => None,
+            };
+            let unconditional = cfg::goto_target(insn, args).is_some()
+                || (args[0].mode == Mode::Immediate
+                    && (args[0].value != 0) == matches!(insn, Insn::Jit(_)));
+            if args[0].mode == Mode::Immediate && !unconditional {
+                // never taken
+                continue;
+            }
+            let cond = if unconditional {
+                None
+            } else {
+                Some(self.condition(insn, args, true))
+            };
+
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    let text = if self.returns(addr, &args[1]) {
+                        "return;".to_string()
+                    } else {
+                        format!("goto *{};", self.operand(&args[1]))
+                    };
+                    match cond {
+                        Some(cond) => {
+                            self.line(depth, &format!("if ({}) {}", cond, text))
+                        }
+                        None => self.line(depth, &text),
+                    }
+                    continue;
+                }
+            };
+
+            if unconditional {
+                if let Some(f) = self.cfg.function(target) {
+                    let call = format!("{}();", f.name());
+                    self.line(depth, &call);
+                } else {
+                    self.jump(target, None, lp, depth);
+                }
+                continue;
+            }
+
+            let exits =
+                lp.is_some_and(|lp| target == lp.exit || target == lp.head);
+            if exits || target <= next || target > end {
+                self.jump(target, cond, lp, depth);
+                continue;
+            }
+
+            // a forward jump over `next..target`, with an `else` if that ends
+            // in a jump over `target..other`
+            let other = self
+                .cfg
+                .code
+                .range(next..target)
+                .last()
+                .filter(|(&a, (i, _))| a + i.len() == target)
+                .and_then(|(_, (i, a))| cfg::goto_target(i, a))
+                .filter(|&other| {
+                    other > target
+                        && other <= end
+                        && self.cfg.function(other).is_none()
+                        && lp.is_none_or(|lp| other != lp.exit)
+                });
+            let cond = self.condition(insn, args, false);
+            self.line(depth, &format!("if ({}) {{", cond));
+            match other {
+                Some(other) => {
+                    let jump = self
+                        .cfg
+                        .code
+                        .range(next..target)
+                        .last()
+                        .map(|(&a, _)| a);
+                    self.emit(
+                        next,
+                        jump.unwrap_or(target),
+                        lp,
+                        depth + 1,
+                        labels,
+                    );
+                    self.line(depth, "} else {");
+                    self.emit(target, other, lp, depth + 1, labels);
+                    pc = other;
+                }
+                None => {
+                    self.emit(next, target, lp, depth + 1, labels);
+                    pc = target;
+                }
+            }
+            self.line(depth, "}");
+        }
+    }
+
+    /// Emits `name` covering the given ranges of code.
+    fn emit_function(&mut self, name: &str, ranges: &[(usize, usize)]) {
+        // the first pass only finds out which labels are needed
+        let mut labels = BTreeSet::new();
+        for pass in 0..2 {
+            self.out.clear();
+            self.gotos.clear();
+            self.line(0, &format!("fn {}() {{", name));
+            for &(start, end) in ranges {
+                self.emit(start, end, None, 1, &labels);
+            }
+            self.line(0, "}");
+            if pass == 0 {
+                labels = self.gotos.clone();
+            }
+        }
+    }
+}
+
+/// Decompiles the program, starting with the code outside of functions.
+pub fn decompile(cfg: &Cfg) -> String {
+    let mut res = String::new();
+
+    let owned: BTreeSet<usize> = cfg
+        .functions
+        .values()
+        .flat_map(|f| f.blocks.iter().copied())
+        .collect();
+    let mut main: Vec<(usize, usize)> = Vec::new();
+    for block in cfg.blocks.values().filter(|b| !owned.contains(&b.start)) {
+        match main.last_mut() {
+            Some(range) if range.1 == block.start => range.1 = block.end,
+            _ => main.push((block.start, block.end)),
+        }
+    }
+
+    let mut decompiler = Decompiler {
+        cfg,
+        function: None,
+        gotos: BTreeSet::new(),
+        out: String::new(),
+    };
+    decompiler.emit_function("main", &main);
+    res.push_str(&decompiler.out);
+
+    for function in cfg.functions.values() {
+        decompiler.function = Some(function);
+        let end = function.end(cfg);
+        decompiler.emit_function(&function.name(), &[(function.entry, end)]);
+        res.push('\n');
+        res.push_str(&decompiler.out);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // reads numbers until a 0, printing the larger of each number and 10
+    // after running it through a function adding its two arguments
+    const PROGRAM: &str = "\
+        rbo #stack
+loop:   in [x]
+        jif [x], #done
+        lt [x], #10, [small]
+        jif [small], #large
+        out #10
+        jit #1, #next
+large:  out [x]
+next:   add [x], #0, rb+1
+        add #-1, #0, rb+2
+        add #back, #0, rb+0
+        jit #1, #sum
+back:   out rb+1
+        jit #1, #loop
+done:   hlt
+sum:    rbo #3
+        add rb-2, rb-1, rb-2
+count:  add rb-1, #1, rb-1
+        jit rb-1, #count
+        rbo #-3
+        jit #1, rb+0
+x:      .data 0
+small:  .data 0
+stack:  .data 0
+";
+
+    #[test]
+    fn structure() {
+        let cfg = Cfg::build(&assemble(PROGRAM).unwrap());
+        assert_eq!(
+            decompile(&cfg),
+            "\
+fn main() {
+    rb += 62;
+    loop {
+        m60 = input();
+        if (!m60) break;
+        m61 = m60 < 10;
+        if (m61) {
+            output(10);
+        } else {
+            output(m60);
+        }
+        rb[1] = m60;
+        rb[2] = -1;
+        rb[0] = 36;
+        fn_0042();
+        output(rb[1]);
+    }
+    halt();
+}
+
+fn fn_0042() {
+    arg1 = arg1 + arg2;
+    do {
+        arg2 = arg2 + 1;
+    } while (arg2);
+    return;
+}
+"
+        );
+    }
+
+    #[test]
+    fn indirect_calls() {
+        // calls the function passed as its first argument
+        let src = "\
+        rbo #stack
+        add #done, #0, rb+1
+        add #done, #0, rb+0
+        jit #1, #apply
+done:   hlt
+apply:  rbo #2
+        add #after, #0, rb+0
+        jit #1, rb-1
+after:  rbo #-2
+        jit #1, rb+0
+stack:  .data 0
+";
+        let cfg = Cfg::build(&assemble(src).unwrap());
+        assert_eq!(cfg.blocks[&14].succs, vec![(23, cfg::Edge::Return)]);
+        assert_eq!(
+            decompile(&cfg),
+            "\
+fn main() {
+    rb += 28;
+    rb[1] = 13;
+    rb[0] = 13;
+    fn_0014();
+    halt();
+}
+
+fn fn_0014() {
+    local0 = 23;
+    goto *arg1;
+    return;
+}
+"
+        );
+    }
+
+    #[test]
+    fn extreme_operands() {
+        let decompiled = |src: &str| {
+            decompile(&Cfg::build(&crate::intcode::parse(src).unwrap()))
+        };
+        assert_eq!(
+            decompiled("1101,5,-9223372036854775808,7,99"),
+            "\
+fn main() {
+    m7 = 5 - 9223372036854775808;
+    halt();
+}
+"
+        );
+        // a function with a frame of 5 reaching past it both ways
+        assert_eq!(
+            decompiled(
+                "1105,1,3,109,5,22201,9223372036854775807,-9,-3,109,-5,2105,1,0"
+            ),
+            "\
+fn main() {
+    fn_0003();
+}
+
+fn fn_0003() {
+    arg2 = rb[9223372036854775807] + rb[-9];
+    return;
+}
+"
+        );
+    }
+}
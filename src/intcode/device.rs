@@ -0,0 +1,298 @@
+use super::{Iss, StopReason, Value};
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+#[derive(Debug)]
+enum Error {
+    Disconnected,
+}
+
+/// What a machine exchanges values with.
+pub trait Device {
+    /// Provides the next input value, or `None` to pause the machine.
+    fn input(&mut self) -> crate::Result<Option<Value>>;
+
+    fn output(&mut self, value: Value) -> crate::Result<()>;
+}
+
+impl Iss {
+    /// Runs the machine against `device` until it halts, the device has no
+    /// input to give, or a watchpoint triggers. Input already queued on the
+    /// machine is consumed before asking the device.
+    pub fn drive(
+        &mut self,
+        device: &mut (impl Device + ?Sized),
+    ) -> crate::Result<StopReason> {
+        loop {
+            match self.run()? {
+                StopReason::Output(o) => device.output(o)?,
+                StopReason::OutOfInput => match device.input()? {
+                    Some(i) => self.feed_input(i),
+                    None => return Ok(StopReason::OutOfInput),
+                },
+                reason => return Ok(reason),
+            }
+        }
+    }
+}
+
+/// Takes input from one queue and collects output in another.
+#[derive(Debug, Default)]
+pub struct Queue {
+    pub input: VecDeque<Value>,
+    pub output: Vec<Value>,
+}
+
+impl Queue {
+    pub fn new(input: impl IntoIterator<Item = Value>) -> Self {
+        Queue {
+            input: input.into_iter().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Device for Queue {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn output(&mut self, value: Value) -> crate::Result<()> {
+        self.output.push(value);
+        Ok(())
+    }
+}
+
+/// Forwards input and output to a pair of closures.
+pub struct Closure<I, O> {
+    input: I,
+    output: O,
+}
+
+impl<I, O> Closure<I, O>
+where
+    I: FnMut() -> crate::Result<Option<Value>>,
+    O: FnMut(Value) -> crate::Result<()>,
+{
+    pub fn new(input: I, output: O) -> Self {
+        Closure { input, output }
+    }
+}
+
+impl<I, O> Device for Closure<I, O>
+where
+    I: FnMut() -> crate::Result<Option<Value>>,
+    O: FnMut(Value) -> crate::Result<()>,
+{
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        (self.input)()
+    }
+
+    fn output(&mut self, value: Value) -> crate::Result<()> {
+        (self.output)(value)
+    }
+}
+
+/// Receives input from and sends output to other threads. Input blocks until
+/// a value arrives and pauses the machine once all senders are gone. Sending
+/// to a dropped receiver is an error.
+pub struct Channel {
+    pub rx: Receiver<Value>,
+    pub tx: Sender<Value>,
+}
+
+impl Device for Channel {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        Ok(self.rx.recv().ok())
+    }
+
+    fn output(&mut self, value: Value) -> crate::Result<()> {
+        self.tx
+            .send(value)
+            .map_err(|_| crate::Error::boxed(Error::Disconnected).into())
+    }
+}
+
+/// Reads input a line at a time and writes output as text. Output that is
+/// not ASCII is written as a number on its own line.
+pub struct Ascii<R, W> {
+    reader: R,
+    writer: W,
+    line: VecDeque<Value>,
+}
+
+impl<R: BufRead, W: Write> Ascii<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Ascii {
+            reader,
+            writer,
+            line: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Device for Ascii<R, W> {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        if self.line.is_empty() {
+            self.writer.flush()?;
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+            self.line
+                .extend(line.chars().map(|c| Value::from(u32::from(c))));
+        }
+        Ok(self.line.pop_front())
+    }
+
+    fn output(&mut self, value: Value) -> crate::Result<()> {
+        match u8::try_from(value) {
+            Ok(c) if c.is_ascii() => self.writer.write_all(&[c])?,
+            _ => writeln!(self.writer, "{}", value)?,
+        }
+        Ok(())
+    }
+}
+
+/// A device that consumes output in groups of a fixed size.
+pub trait Framed {
+    /// Provides the next input value, or `None` to pause the machine.
+    fn input(&mut self) -> crate::Result<Option<Value>>;
+
+    fn frame(&mut self, frame: &[Value]) -> crate::Result<()>;
+}
+
+/// Groups output into frames of `n` values for a `Framed` device.
+pub struct Framer<T> {
+    pub inner: T,
+    n: usize,
+    buf: Vec<Value>,
+}
+
+impl<T: Framed> Framer<T> {
+    pub fn new(n: usize, inner: T) -> Self {
+        Framer {
+            inner,
+            n,
+            buf: Vec::with_capacity(n),
+        }
+    }
+}
+
+impl<T: Framed> Device for Framer<T> {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        self.inner.input()
+    }
+
+    fn output(&mut self, value: Value) -> crate::Result<()> {
+        self.buf.push(value);
+        if self.buf.len() == self.n {
+            self.inner.frame(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use std::sync::mpsc;
+    use std::thread;
+
+    // outputs the sum and the product of each pair of inputs
+    const PAIRS: &str = "\
+loop:   in [a]
+        in [b]
+        add [a], [b], [r]
+        out [r]
+        mul [a], [b], [r]
+        out [r]
+        jit #1, #loop
+a:      .data 0
+b:      .data 0
+r:      .data 0
+";
+
+    fn pairs() -> Iss {
+        Iss::new(assemble(PAIRS).unwrap())
+    }
+
+    #[test]
+    fn queue() {
+        let mut dev = Queue::new(vec![2, 3, 4, 5]);
+        assert_eq!(pairs().drive(&mut dev).unwrap(), StopReason::OutOfInput);
+        assert_eq!(dev.output, vec![5, 6, 9, 20]);
+    }
+
+    #[test]
+    fn closure() {
+        let mut input = 0..4;
+        let mut output = Vec::new();
+        let mut dev = Closure::new(
+            || Ok(input.next()),
+            |v| {
+                output.push(v);
+                Ok(())
+            },
+        );
+        pairs().drive(&mut dev).unwrap();
+        assert_eq!(output, vec![1, 0, 5, 6]);
+    }
+
+    #[test]
+    fn channel() {
+        let (tx, rx) = mpsc::channel();
+        let (out_tx, out_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            pairs()
+                .drive(&mut Channel { rx, tx: out_tx })
+                .map_err(|e| e.to_string())
+        });
+        tx.send(3).unwrap();
+        tx.send(3).unwrap();
+        assert_eq!(out_rx.recv().unwrap(), 6);
+        assert_eq!(out_rx.recv().unwrap(), 9);
+        drop(tx);
+        assert_eq!(handle.join().unwrap().unwrap(), StopReason::OutOfInput);
+    }
+
+    #[test]
+    fn ascii() {
+        let mut out = Vec::new();
+        let mut dev = Ascii::new(&b"\x01\x02d\n"[..], &mut out);
+        pairs().drive(&mut dev).unwrap();
+        assert_eq!(out, b"\x03\x02n1000\n".to_vec());
+    }
+
+    struct Pairs {
+        input: VecDeque<Value>,
+        frames: Vec<Vec<Value>>,
+    }
+
+    impl Framed for Pairs {
+        fn input(&mut self) -> crate::Result<Option<Value>> {
+            Ok(self.input.pop_front())
+        }
+
+        fn frame(&mut self, frame: &[Value]) -> crate::Result<()> {
+            self.frames.push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn framer() {
+        let mut dev = Framer::new(
+            2,
+            Pairs {
+                input: vec![2, 3, 4, 5, 6].into(),
+                frames: Vec::new(),
+            },
+        );
+        pairs().drive(&mut dev).unwrap();
+        assert_eq!(dev.inner.frames, vec![vec![5, 6], vec![9, 20]]);
+    }
+}
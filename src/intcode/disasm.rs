@@ -0,0 +1,290 @@
+use super::pages::Pages;
+use super::{Insn, Mode, Value};
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Number of values per `.data` line.
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Operand {
+    pub mode: Mode,
+    pub value: Value,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "#{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "rb{}", self.value),
+            Mode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Insn {
+        addr: usize,
+        insn: Insn,
+        args: Vec<Operand>,
+    },
+    Data {
+        addr: usize,
+        values: Vec<Value>,
+    },
+}
+
+impl Item {
+    pub fn addr(&self) -> usize {
+        match self {
+            Item::Insn { addr, .. } | Item::Data { addr, .. } => *addr,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Item::Insn { insn, .. } => insn.len(),
+            Item::Data { values, .. } => values.len(),
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Insn { addr, insn, args } => {
+                write!(f, "{:04}: {}", addr, insn.mnemonic())?;
+                for (idx, arg) in args.iter().enumerate() {
+                    let sep = if idx == 0 { " " } else { ", " };
+                    write!(f, "{}{}", sep, arg)?;
+                }
+            }
+            Item::Data { addr, values } => {
+                write!(f, "{:04}: .data", addr)?;
+                for (idx, v) in values.iter().enumerate() {
+                    let sep = if idx == 0 { " " } else { ", " };
+                    write!(f, "{}{}", sep, v)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub items: Vec<Item>,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            writeln!(f, "{}", item)?;
+        }
+        Ok(())
+    }
+}
+
+/// Memory to disassemble, without copying it.
+pub trait Cells {
+    /// The number of cells listed, all of which exist.
+    fn size(&self) -> usize;
+
+    fn cell(&self, addr: usize) -> Option<Value>;
+}
+
+impl Cells for [Value] {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn cell(&self, addr: usize) -> Option<Value> {
+        self.get(addr).copied()
+    }
+}
+
+/// The dense part is listed, far cells are not.
+impl Cells for Pages {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn cell(&self, addr: usize) -> Option<Value> {
+        self.get(addr)
+    }
+}
+
+/// Decodes the instruction at `addr` together with its operands, if it fits
+/// into `mem`. Values with mode digits for operands the instruction does not
+/// have are not considered instructions, so that listings reassemble to the
+/// same image.
+pub fn decode<M: Cells + ?Sized>(
+    mem: &M,
+    addr: usize,
+) -> Option<(Insn, Vec<Operand>)> {
+    let value = mem.cell(addr)?;
+    let insn = Insn::decode(value).ok().filter(|i| i.encode() == value)?;
+    let args = insn
+        .modes()
+        .iter()
+        .enumerate()
+        .map(|(n, &mode)| {
+            let value = mem.cell(addr.checked_add(n + 1)?)?;
+            Some(Operand { mode, value })
+        })
+        .collect::<Option<_>>()?;
+    Some((insn, args))
+}
+
+/// Statically known successors of an instruction. Jump targets are only known
+/// for immediate operands, and conditions with immediate operands are
+/// resolved.
+pub fn successors(addr: usize, insn: &Insn, args: &[Operand]) -> Vec<usize> {
+    let next = addr + insn.len();
+    let target = |op: &Operand| match op.mode {
+        Mode::Immediate if op.value >= 0 => Some(op.value as usize),
+        _ => None,
+    };
+
+    match insn {
+        Insn::Halt => Vec::new(),
+        Insn::Jit(_) | Insn::Jif(_) => {
+            let jit = matches!(insn, Insn::Jit(_));
+            match args[0].mode {
+                Mode::Immediate if (args[0].value != 0) == jit => {
+                    target(&args[1]).into_iter().collect()
+                }
+                Mode::Immediate => vec![next],
+                _ => {
+                    let mut succs = vec![next];
+                    succs.extend(target(&args[1]));
+                    succs
+                }
+            }
+        }
+        _ => vec![next],
+    }
+}
+
+/// Disassembles a memory image, following control flow from address 0.
+pub fn disassemble(mem: &[Value]) -> Listing {
+    disassemble_from(mem, &[0])
+}
+
+/// Disassembles a memory image, following control flow from `roots`.
+///
+/// Call sites push their return address as an immediate operand before
+/// jumping away, so immediate operands pointing right behind decoded code are
+/// followed as well. Everything not reached is rendered as `.data`.
+pub fn disassemble_from<M: Cells + ?Sized>(
+    mem: &M,
+    roots: &[usize],
+) -> Listing {
+    let mut code = BTreeMap::new();
+    let mut covered = vec![false; mem.size()];
+    let mut ends = HashSet::new();
+    let mut hints = HashSet::new();
+
+    let mut todo = roots.to_vec();
+    while !todo.is_empty() {
+        while let Some(addr) = todo.pop() {
+            // far cells are left out of listings
+            let (insn, args) = match decode(mem, addr) {
+                Some(d) if addr + d.0.len() <= mem.size() => d,
+                _ => continue,
+            };
+            let end = addr + insn.len();
+            if covered[addr..end].iter().any(|&c| c) {
+                continue;
+            }
+            covered[addr..end].iter_mut().for_each(|c| *c = true);
+            ends.insert(end);
+
+            hints.extend(
+                args.iter()
+                    .filter(|op| op.mode == Mode::Immediate && op.value >= 0)
+                    .map(|op| op.value as usize),
+            );
+            todo.extend(successors(addr, &insn, &args));
+            code.insert(addr, (insn, args));
+        }
+
+        todo.extend(hints.iter().filter(|&&addr| {
+            ends.contains(&addr) && addr < mem.size() && !covered[addr]
+        }));
+        hints.retain(|addr| !todo.contains(addr));
+    }
+
+    let mut items = Vec::new();
+    let mut addr = 0;
+    while addr < mem.size() {
+        if let Some((insn, args)) = code.remove(&addr) {
+            items.push(Item::Insn { addr, insn, args });
+            addr += insn.len();
+        } else {
+            let values: Vec<Value> = (addr..mem.size())
+                .take_while(|&a| !covered[a])
+                .take(DATA_PER_LINE)
+                .map(|a| mem.cell(a).unwrap_or_default())
+                .collect();
+            let len = values.len();
+            items.push(Item::Data { addr, values });
+            addr += len;
+        }
+    }
+
+    Listing { items }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operands() {
+        let mem = vec![1002, 4, 3, 4, 33];
+        let listing = disassemble(&mem);
+        assert_eq!(
+            listing.to_string(),
+            "0000: mul [4], #3, [4]\n0004: .data 33\n"
+        );
+
+        let mem = vec![21201, -3, 7, 2, 99];
+        let listing = disassemble(&mem);
+        assert_eq!(
+            listing.to_string(),
+            "0000: add rb-3, #7, rb+2\n0004: hlt\n"
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        // jump over a data cell
+        let mem = vec![1105, 1, 4, 1234, 21101, 8, 0, 0, 99];
+        let listing = disassemble(&mem);
+        assert_eq!(
+            listing.to_string(),
+            "0000: jit #1, #4\n\
+             0003: .data 1234\n\
+             0004: add #8, #0, rb+0\n\
+             0008: hlt\n"
+        );
+    }
+
+    #[test]
+    fn return_address() {
+        // push 7 as return address, call 8 which returns right away
+        let mem = vec![21101, 7, 0, 0, 1105, 1, 8, 99, 2106, 0, 0];
+        let listing = disassemble(&mem);
+        assert_eq!(
+            listing.to_string(),
+            "0000: add #7, #0, rb+0\n\
+             0004: jit #1, #8\n\
+             0007: hlt\n\
+             0008: jif #0, rb+0\n"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! What instructions do, for machines of any number type.
+//!
+//! `Core::execute` executes a single instruction in terms of the machine
+//! implementing `Core`, which provides memory, input and hooks for
+//! instrumentation. `Iss` and `big::Machine` both execute through it, so
+//! they differ only in the values they hold. The compiled tier of `Iss` and
+//! the symbolic machine have their own notion of an instruction.
+
+use super::big::Number;
+use super::fault::{Fault, RECENT};
+use super::pages::Pages;
+use super::{address, Budget, Config, Error, Insn, Mode, StopReason};
+use super::{Arith, Value, Width};
+
+use std::convert::TryFrom;
+use std::time::Instant;
+
+/// The deadline of a budget is checked every so many instructions.
+const DEADLINE_INTERVAL: u32 = 1 << 12;
+
+/// What made a machine stop executing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<N> {
+    Output(N),
+    Stop(StopReason),
+}
+
+/// Spends the budget of a machine and remembers the instructions it
+/// executed last.
+#[derive(Clone)]
+pub(super) struct Meter {
+    budget: Budget,
+    /// Instructions executed since the deadline was last checked.
+    clock: u32,
+    /// The addresses of the instructions executed last, by `executed`
+    /// modulo `RECENT`.
+    recent: [usize; RECENT],
+    executed: usize,
+}
+
+impl Meter {
+    pub(super) fn new() -> Self {
+        Meter {
+            budget: Budget::default(),
+            clock: 0,
+            recent: [0; RECENT],
+            executed: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    pub(super) fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+        self.clock = 0;
+    }
+
+    /// Whether the budget ran out. Counts towards checking the deadline.
+    pub(super) fn over_budget(&mut self) -> bool {
+        if self.budget.steps == Some(0) {
+            return true;
+        }
+        if let Some(deadline) = self.budget.deadline {
+            self.clock += 1;
+            if self.clock >= DEADLINE_INTERVAL {
+                self.clock = 0;
+                return Instant::now() >= deadline;
+            }
+        }
+        false
+    }
+
+    /// Takes the instruction executed at `pc` off the budget and remembers
+    /// it for faults.
+    pub(super) fn charge(&mut self, pc: usize) {
+        if let Some(steps) = &mut self.budget.steps {
+            *steps -= 1;
+        }
+        self.recent[self.executed % RECENT] = pc;
+        self.executed = self.executed.wrapping_add(1);
+    }
+
+    /// The addresses of the instructions executed last, oldest first.
+    fn recent(&self) -> impl Iterator<Item = usize> + '_ {
+        let n = self.executed.min(RECENT);
+        (self.executed - n..self.executed).map(move |i| self.recent[i % RECENT])
+    }
+}
+
+/// The address a value stands for.
+pub(super) fn address_of<N: Number>(v: &N) -> crate::Result<usize> {
+    let v = v
+        .to_value()
+        .ok_or_else(|| crate::Error::boxed(Error::AddressOutOfRange))?;
+    address(v)
+}
+
+/// A machine as far as executing instructions goes. Hooks default to doing
+/// nothing.
+pub(super) trait Core {
+    type N: Number;
+
+    fn config(&self) -> &Config;
+    fn meter(&self) -> &Meter;
+    fn meter_mut(&mut self) -> &mut Meter;
+    fn mem(&self) -> &Pages<Self::N>;
+    fn pc(&self) -> usize;
+    fn rb(&self) -> Self::N;
+
+    /// Reads a cell, creating it if the memory model allows.
+    fn read(&mut self, addr: usize) -> crate::Result<Self::N>;
+    /// Decodes the instruction at `pc`.
+    fn fetch(&mut self) -> crate::Result<Insn>;
+    /// Loads operand `n`, counting from 1.
+    fn load(&mut self, m: &[Mode], n: usize) -> crate::Result<Self::N>;
+    /// Stores to operand `n` a value the configured width allows.
+    fn store(&mut self, m: &[Mode], n: usize, v: Self::N) -> crate::Result<()>;
+    fn has_input(&self) -> bool;
+    /// Takes the next input, which exists.
+    fn input(&mut self) -> Self::N;
+    /// Moves on to the instruction `len` cells after the current one.
+    fn advance(&mut self, len: usize);
+    /// Continues at `target`, returning why to stop there, if at all.
+    fn jump(&mut self, target: usize) -> Option<StopReason>;
+    fn rebase(&mut self, rb: Self::N);
+
+    /// An input instruction waits for input instead of being executed.
+    fn wait(&mut self) {}
+    /// The instruction at `pc` is about to be executed.
+    fn enter(&mut self, _insn: &Insn) {}
+    fn output(&mut self, _o: &Self::N) {}
+    /// A conditional jump at `pc` was or was not taken.
+    fn branch(&mut self, _taken: bool) {}
+
+    fn overflow(&self) -> Box<dyn std::error::Error> {
+        crate::Error::boxed(Error::Overflow { pc: self.pc() })
+    }
+
+    /// Applies an arithmetic operation under the configured policy.
+    fn arith(
+        &self,
+        a: &Self::N,
+        b: &Self::N,
+        checked: fn(&Self::N, &Self::N) -> Option<Self::N>,
+        wrapping: fn(&Self::N, &Self::N) -> Self::N,
+    ) -> crate::Result<Self::N> {
+        match self.config().arith {
+            Arith::Checked => checked(a, b).ok_or_else(|| self.overflow()),
+            Arith::Wrapping => Ok(wrapping(a, b)),
+        }
+    }
+
+    /// The address of operand `n`, counting from 1.
+    fn addr(&mut self, m: &[Mode], n: usize) -> crate::Result<usize> {
+        let at = self.pc() + n;
+        match m[n - 1] {
+            Mode::Immediate => Ok(at),
+            Mode::Position => address_of(&self.read(at)?),
+            Mode::Relative => {
+                let offset = self.read(at)?;
+                let addr = offset
+                    .checked_add(&self.rb())
+                    .ok_or_else(|| self.overflow())?;
+                address_of(&addr)
+            }
+        }
+    }
+
+    fn put(&mut self, m: &[Mode], n: usize, v: Self::N) -> crate::Result<()> {
+        if self.config().width == Width::W32
+            && v.to_value().and_then(|v| i32::try_from(v).ok()).is_none()
+        {
+            return Err(crate::Error::boxed(Error::ValueOutOfRange));
+        }
+        self.store(m, n, v)
+    }
+
+    /// Executes the instruction at `pc`. Returns why the machine stopped, if
+    /// it did. A machine stopped for lack of input, by `hlt` or by its
+    /// budget does not advance its `pc`.
+    fn execute(&mut self) -> crate::Result<Option<Event<Self::N>>> {
+        if self.meter_mut().over_budget() {
+            return Ok(Some(Event::Stop(StopReason::BudgetExhausted)));
+        }
+        let insn = self.fetch()?;
+        if matches!(insn, Insn::In(_)) && !self.has_input() {
+            self.wait();
+            return Ok(Some(Event::Stop(StopReason::OutOfInput)));
+        }
+        let pc = self.pc();
+        self.meter_mut().charge(pc);
+        self.enter(&insn);
+
+        match insn {
+            Insn::Add(m) | Insn::Mul(m) => {
+                let (a, b) = (self.load(&m, 1)?, self.load(&m, 2)?);
+                let v = if let Insn::Add(_) = insn {
+                    self.arith(
+                        &a,
+                        &b,
+                        Number::checked_add,
+                        Number::wrapping_add,
+                    )
+                } else {
+                    self.arith(
+                        &a,
+                        &b,
+                        Number::checked_mul,
+                        Number::wrapping_mul,
+                    )
+                }?;
+                self.put(&m, 3, v)?;
+            }
+            Insn::Lt(m) | Insn::Equ(m) => {
+                let (a, b) = (self.load(&m, 1)?, self.load(&m, 2)?);
+                let v = if let Insn::Lt(_) = insn {
+                    a < b
+                } else {
+                    a == b
+                };
+                self.put(&m, 3, Value::from(v).into())?;
+            }
+            Insn::In(m) => {
+                let i = self.input();
+                self.put(&m, 1, i)?;
+            }
+            Insn::Out(m) => {
+                let o = self.load(&m, 1)?;
+                self.output(&o);
+                self.advance(insn.len());
+                return Ok(Some(Event::Output(o)));
+            }
+            Insn::Jit(m) | Insn::Jif(m) => {
+                let zero = self.load(&m, 1)? == Self::N::default();
+                let taken = zero != matches!(insn, Insn::Jit(_));
+                self.branch(taken);
+                if taken {
+                    let target = address_of(&self.load(&m, 2)?)?;
+                    return Ok(self.jump(target).map(Event::Stop));
+                }
+            }
+            Insn::Rbo(m) => {
+                let offset = self.load(&m, 1)?;
+                let rb = self.arith(
+                    &self.rb(),
+                    &offset,
+                    Number::checked_add,
+                    Number::wrapping_add,
+                )?;
+                self.rebase(rb);
+            }
+            Insn::Halt => return Ok(Some(Event::Stop(StopReason::Halted))),
+        }
+        self.advance(insn.len());
+        Ok(None)
+    }
+
+    /// Adds the state of the machine to an error raised by the instruction
+    /// at `pc`.
+    fn fault(
+        &self,
+        source: Box<dyn std::error::Error>,
+    ) -> Box<dyn std::error::Error> {
+        let recent = self.meter().recent();
+        let fault = Fault::new(self.pc(), self.rb(), self.mem(), recent);
+        crate::Error::caused_by(fault, source)
+    }
+}
@@ -0,0 +1,97 @@
+//! What a machine was doing when an instruction failed.
+
+use super::big::Number;
+use super::disasm::{self, Item};
+use super::pages::Pages;
+use super::Value;
+
+use std::fmt;
+
+/// The number of executed instructions a fault lists.
+pub(super) const RECENT: usize = 16;
+
+/// The number of cells shown on either side of the failing instruction.
+const EXCERPT: usize = 8;
+
+/// The state of a machine whose instruction at `pc` failed. The failure
+/// itself is the source of the error carrying the fault.
+pub struct Fault<N = Value> {
+    pub pc: usize,
+    pub rb: N,
+    /// The raw value at `pc`, if it exists.
+    pub opcode: Option<N>,
+    /// The instructions executed last, oldest first, disassembled as memory
+    /// held them at the time of the fault.
+    pub recent: Vec<(usize, String)>,
+    /// The cells around `pc`, starting at the address given.
+    pub excerpt: (usize, Vec<N>),
+}
+
+fn listing<N: Number>(mem: &Pages<N>, pc: usize) -> String {
+    // values beyond a machine word are no instructions or addresses
+    let code: Vec<Value> = (pc..pc + 4)
+        .map_while(|addr| mem.get(addr)?.to_value())
+        .collect();
+    match disasm::decode(&code[..], 0) {
+        Some((insn, args)) => Item::Insn {
+            addr: pc,
+            insn,
+            args,
+        }
+        .to_string(),
+        None => format!("{:04}: <invalid>", pc),
+    }
+}
+
+impl<N: Number> Fault<N> {
+    pub(super) fn new(
+        pc: usize,
+        rb: N,
+        mem: &Pages<N>,
+        recent: impl Iterator<Item = usize>,
+    ) -> Self {
+        let end = (pc + EXCERPT + 1).min(mem.len());
+        let start = pc.saturating_sub(EXCERPT).min(end);
+        Fault {
+            pc,
+            rb,
+            opcode: mem.get(pc),
+            recent: recent.map(|pc| (pc, listing(mem, pc))).collect(),
+            excerpt: (
+                start,
+                mem.iter().skip(start).take(end - start).collect(),
+            ),
+        }
+    }
+}
+
+/// A single line, or with `{:#?}` a report with the instructions executed
+/// last and the memory around `pc`.
+impl<N: Number> fmt::Debug for Fault<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fault at pc {} with rb {}", self.pc, self.rb)?;
+        if let Some(opcode) = &self.opcode {
+            write!(f, ", opcode {}", opcode)?;
+        }
+        if !f.alternate() {
+            return Ok(());
+        }
+        if !self.recent.is_empty() {
+            write!(f, "\nlast executed:")?;
+            for (_, line) in &self.recent {
+                write!(f, "\n    {}", line)?;
+            }
+        }
+        let (start, cells) = &self.excerpt;
+        if !cells.is_empty() {
+            write!(f, "\nmemory:")?;
+            for (n, row) in cells.chunks(EXCERPT).enumerate() {
+                write!(f, "\n    {:04}:", start + n * EXCERPT)?;
+                for v in row {
+                    write!(f, " {}", v)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
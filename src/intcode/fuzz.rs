@@ -0,0 +1,561 @@
+//! Differential testing of the ways to run a program.
+//!
+//! Random programs are run by the interpreter and the compiled tier, by
+//! `big::Machine` and, if they stick to the day05 instruction set, under the
+//! day05 configuration. Everything observable must agree: output, whether
+//! the program halted or failed with which error, and the final memory.
+//!
+//! Programs are built from statements that always terminate: loops count
+//! down a counter of their own that nothing else writes, and relative base
+//! changes come in pairs around a block, keeping relative operands on a
+//! stack after the data. Failing programs are shrunk statement by statement.
+
+use super::asm::assemble;
+use super::big::Machine;
+use super::pages::Pages;
+use super::{Budget, Config, Isa, Iss, StopReason, Value};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Cells the program computes with.
+const DATA: usize = 6;
+/// Cells relative operands may reach.
+const STACK: Value = 12;
+/// Loops, branches and frames nest at most this deep.
+const MAX_DEPTH: usize = 3;
+const MAX_STMTS: usize = 6;
+const MAX_INPUT: usize = 6;
+/// Instructions a run may take, should a program not terminate after all.
+const BUDGET: Budget = Budget {
+    steps: Some(1 << 20),
+    deadline: None,
+};
+/// Immediates large enough to overflow now and then.
+const LARGE: [Value; 4] = [1 << 31, -(1 << 31) - 1, 1 << 62, -(1 << 62)];
+
+/// A small deterministic generator, so that a run can be repeated from its
+/// seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    // splitmix64
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// A value in `lo..hi`.
+    fn range(&mut self, lo: Value, hi: Value) -> Value {
+        lo + (self.next() % (hi - lo) as u64) as Value
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Arg {
+    Imm(Value),
+    Data(usize),
+    /// The counter of a loop, which only the loop writes.
+    Counter(usize),
+    /// Relative to the base, which a program keeps on the stack.
+    Rel(Value),
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::Imm(v) => write!(f, "#{}", v),
+            Arg::Data(i) => write!(f, "[d{}]", i),
+            Arg::Counter(i) => write!(f, "[c{}]", i),
+            Arg::Rel(o) if *o < 0 => write!(f, "rb{}", o),
+            Arg::Rel(o) => write!(f, "rb+{}", o),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stmt {
+    Op(&'static str, Arg, Arg, Arg),
+    In(Arg),
+    Out(Arg),
+    /// Runs the body a number of times, counting down the given counter.
+    Loop(usize, Value, Vec<Stmt>),
+    If(Arg, Vec<Stmt>, Vec<Stmt>),
+    /// Raises the relative base around the body.
+    Frame(Value, Vec<Stmt>),
+}
+
+/// A program with its input.
+#[derive(Debug, Clone)]
+pub struct Program {
+    isa: Isa,
+    stmts: Vec<Stmt>,
+    data: Vec<Value>,
+    pub input: Vec<Value>,
+}
+
+struct Gen<'a> {
+    rng: &'a mut Rng,
+    isa: Isa,
+}
+
+impl Gen<'_> {
+    /// An operand read with `loops` loops around it and the base `base`
+    /// cells into the stack.
+    fn arg(&mut self, loops: usize, base: Value) -> Arg {
+        match self.rng.below(4) {
+            0 if self.rng.below(8) == 0 => Arg::Imm(LARGE[self.rng.below(4)]),
+            0 => Arg::Imm(self.rng.range(-5, 10)),
+            1 if loops > 0 => Arg::Counter(self.rng.below(loops)),
+            2 if self.isa == Isa::Day09 => {
+                Arg::Rel(self.rng.range(-base, STACK - base))
+            }
+            _ => Arg::Data(self.rng.below(DATA)),
+        }
+    }
+
+    fn dest(&mut self, base: Value) -> Arg {
+        if self.isa == Isa::Day09 && self.rng.below(3) == 0 {
+            Arg::Rel(self.rng.range(-base, STACK - base))
+        } else {
+            Arg::Data(self.rng.below(DATA))
+        }
+    }
+
+    fn block(&mut self, depth: usize, loops: usize, base: Value) -> Vec<Stmt> {
+        let n = self.rng.below(MAX_STMTS) + 1;
+        (0..n).map(|_| self.stmt(depth, loops, base)).collect()
+    }
+
+    fn stmt(&mut self, depth: usize, loops: usize, base: Value) -> Stmt {
+        let kinds = if depth < MAX_DEPTH { 9 } else { 6 };
+        match self.rng.below(kinds) {
+            0..=3 => {
+                let op = ["add", "mul", "lt", "eq"][self.rng.below(4)];
+                let (a, b) = (self.arg(loops, base), self.arg(loops, base));
+                Stmt::Op(op, a, b, self.dest(base))
+            }
+            4 => Stmt::In(self.dest(base)),
+            5 => Stmt::Out(self.arg(loops, base)),
+            6 => {
+                let times = self.rng.range(1, 4);
+                let body = self.block(depth + 1, loops + 1, base);
+                Stmt::Loop(loops, times, body)
+            }
+            7 if self.isa == Isa::Day09 && base + 1 < STACK => {
+                let size = self.rng.range(1, (STACK - base).min(4));
+                Stmt::Frame(size, self.block(depth + 1, loops, base + size))
+            }
+            _ => {
+                let cond = self.arg(loops, base);
+                let then = self.block(depth + 1, loops, base);
+                let other = if self.rng.below(2) == 0 {
+                    self.block(depth + 1, loops, base)
+                } else {
+                    Vec::new()
+                };
+                Stmt::If(cond, then, other)
+            }
+        }
+    }
+}
+
+/// Whether relative operands stay on the stack and no loop counts down the
+/// counter of a loop around it.
+fn valid(stmts: &[Stmt], loops: &mut Vec<usize>, base: Value) -> bool {
+    let arg = |arg: &Arg| match arg {
+        Arg::Rel(o) => (0..STACK).contains(&(base + o)),
+        _ => true,
+    };
+    stmts.iter().all(|stmt| match stmt {
+        Stmt::Op(_, a, b, c) => arg(a) && arg(b) && arg(c),
+        Stmt::In(a) | Stmt::Out(a) => arg(a),
+        Stmt::Loop(counter, _, body) => {
+            if loops.contains(counter) {
+                return false;
+            }
+            loops.push(*counter);
+            let ok = valid(body, loops, base);
+            loops.pop();
+            ok
+        }
+        Stmt::If(cond, then, other) => {
+            arg(cond) && valid(then, loops, base) && valid(other, loops, base)
+        }
+        Stmt::Frame(size, body) => valid(body, loops, base + size),
+    })
+}
+
+/// Every program one step simpler than `stmts`: without a statement, with
+/// a statement replaced by its body, with fewer iterations or with an
+/// operand read replaced by zero.
+fn simpler(stmts: &[Stmt]) -> Vec<Vec<Stmt>> {
+    let mut variants = Vec::new();
+    for (idx, stmt) in stmts.iter().enumerate() {
+        let mut with = |replacement: &[Stmt]| {
+            let mut v = stmts[..idx].to_vec();
+            v.extend_from_slice(replacement);
+            v.extend_from_slice(&stmts[idx + 1..]);
+            variants.push(v);
+        };
+        with(&[]);
+        let zero = |arg: &Arg| match arg {
+            Arg::Imm(0) => None,
+            _ => Some(Arg::Imm(0)),
+        };
+        match stmt {
+            Stmt::Op(op, a, b, c) => {
+                if let Some(a) = zero(a) {
+                    with(&[Stmt::Op(op, a, b.clone(), c.clone())]);
+                }
+                if let Some(b) = zero(b) {
+                    with(&[Stmt::Op(op, a.clone(), b, c.clone())]);
+                }
+            }
+            Stmt::In(_) => (),
+            Stmt::Out(a) => {
+                if let Some(a) = zero(a) {
+                    with(&[Stmt::Out(a)]);
+                }
+            }
+            Stmt::Loop(counter, times, body) => {
+                with(body);
+                if *times > 1 {
+                    with(&[Stmt::Loop(*counter, 1, body.clone())]);
+                }
+                for body in simpler(body) {
+                    with(&[Stmt::Loop(*counter, *times, body)]);
+                }
+            }
+            Stmt::If(cond, then, other) => {
+                with(then);
+                with(other);
+                if let Some(cond) = zero(cond) {
+                    with(&[Stmt::If(cond, then.clone(), other.clone())]);
+                }
+                for then in simpler(then) {
+                    with(&[Stmt::If(cond.clone(), then, other.clone())]);
+                }
+                for other in simpler(other) {
+                    with(&[Stmt::If(cond.clone(), then.clone(), other)]);
+                }
+            }
+            Stmt::Frame(size, body) => {
+                with(body);
+                for body in simpler(body) {
+                    with(&[Stmt::Frame(*size, body)]);
+                }
+            }
+        }
+    }
+    variants
+}
+
+impl Program {
+    pub fn generate(rng: &mut Rng, isa: Isa) -> Self {
+        let stmts = Gen { rng, isa }.block(0, 0, 0);
+        let data = (0..DATA).map(|_| rng.range(-5, 10)).collect();
+        let n = rng.below(MAX_INPUT + 1);
+        let input = (0..n).map(|_| rng.range(-5, 10)).collect();
+        Program {
+            isa,
+            stmts,
+            data,
+            input,
+        }
+    }
+
+    pub fn assemble(&self) -> Vec<Value> {
+        assemble(&self.source()).expect("generated source assembles")
+    }
+
+    /// The program in assembly.
+    pub fn source(&self) -> String {
+        let mut src = String::new();
+        if self.isa == Isa::Day09 {
+            src.push_str("        rbo #stack\n");
+        }
+        lower(&self.stmts, &mut 0, &mut src);
+        src.push_str("        hlt\n");
+        for counter in 0..MAX_DEPTH {
+            src.push_str(&format!("c{}:     .data 0\n", counter));
+        }
+        for (idx, v) in self.data.iter().enumerate() {
+            src.push_str(&format!("d{}:     .data {}\n", idx, v));
+        }
+        if self.isa == Isa::Day09 {
+            src.push_str(&format!("stack:  .fill {}\n", STACK));
+        }
+        src
+    }
+
+    /// Programs one step simpler that still keep to the rules.
+    fn simpler(&self) -> Vec<Program> {
+        let mut variants = Vec::new();
+        for stmts in simpler(&self.stmts) {
+            if valid(&stmts, &mut Vec::new(), 0) {
+                variants.push(Program {
+                    stmts,
+                    ..self.clone()
+                });
+            }
+        }
+        for idx in 0..self.input.len() {
+            let mut p = self.clone();
+            p.input.remove(idx);
+            variants.push(p);
+        }
+        for idx in 0..self.data.len() {
+            if self.data[idx] != 0 {
+                let mut p = self.clone();
+                p.data[idx] = 0;
+                variants.push(p);
+            }
+        }
+        variants
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let input: Vec<String> =
+            self.input.iter().map(|v| v.to_string()).collect();
+        writeln!(f, "; input: {}", input.join(", "))?;
+        write!(f, "{}", self.source())
+    }
+}
+
+fn lower(stmts: &[Stmt], labels: &mut usize, src: &mut String) {
+    let line = |src: &mut String, s: String| {
+        src.push_str("        ");
+        src.push_str(&s);
+        src.push('\n');
+    };
+    let label = |labels: &mut usize| {
+        *labels += 1;
+        format!("l{}", labels)
+    };
+    for stmt in stmts {
+        match stmt {
+            Stmt::Op(op, a, b, c) => {
+                line(src, format!("{} {}, {}, {}", op, a, b, c))
+            }
+            Stmt::In(a) => line(src, format!("in {}", a)),
+            Stmt::Out(a) => line(src, format!("out {}", a)),
+            Stmt::Loop(counter, times, body) => {
+                let head = label(labels);
+                line(src, format!("add #{}, #0, [c{}]", times, counter));
+                src.push_str(&format!("{}:\n", head));
+                lower(body, labels, src);
+                line(src, format!("add [c{0}], #-1, [c{0}]", counter));
+                line(src, format!("jit [c{}], #{}", counter, head));
+            }
+            Stmt::If(cond, then, other) => {
+                let (other_label, end) = (label(labels), label(labels));
+                line(src, format!("jif {}, #{}", cond, other_label));
+                lower(then, labels, src);
+                line(src, format!("jit #1, #{}", end));
+                src.push_str(&format!("{}:\n", other_label));
+                lower(other, labels, src);
+                src.push_str(&format!("{}:\n", end));
+            }
+            Stmt::Frame(size, body) => {
+                line(src, format!("rbo #{}", size));
+                lower(body, labels, src);
+                line(src, format!("rbo #{}", -size));
+            }
+        }
+    }
+}
+
+/// Everything observable about a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub output: Vec<Value>,
+    /// Whether the program halted rather than ran out of input, or the
+    /// error it failed with.
+    pub end: Result<bool, String>,
+    /// The cells that are not zero.
+    pub mem: BTreeMap<usize, Value>,
+}
+
+/// Runs a machine by `run` until it stops.
+fn run_until_stopped(
+    mut run: impl FnMut() -> crate::Result<StopReason>,
+) -> (Vec<Value>, Result<bool, String>) {
+    let mut output = Vec::new();
+    let end = loop {
+        match run() {
+            Ok(StopReason::Output(o)) => output.push(o),
+            Ok(StopReason::Halted) => break Ok(true),
+            Ok(StopReason::OutOfInput) => break Ok(false),
+            Ok(reason) => break Err(format!("{:?}", reason)),
+            Err(e) => break Err(e.to_string()),
+        }
+    };
+    (output, end)
+}
+
+fn nonzero(mem: &Pages) -> BTreeMap<usize, Value> {
+    let cells = mem.iter().enumerate().chain(mem.far());
+    cells.filter(|&(_, v)| v != 0).collect()
+}
+
+fn run_iss(mem: &[Value], config: Config, input: &[Value]) -> Outcome {
+    let mut iss = Iss::with_config(mem.to_vec(), config);
+    iss.set_budget(BUDGET);
+    for &i in input {
+        iss.feed_input(i);
+    }
+    let (output, end) = run_until_stopped(|| iss.run());
+    Outcome {
+        output,
+        end,
+        mem: nonzero(iss.mem()),
+    }
+}
+
+fn run_big(mem: &[Value], config: Config, input: &[Value]) -> Outcome {
+    let mut machine = Machine::with_config(mem.to_vec(), config);
+    machine.set_budget(BUDGET);
+    for &i in input {
+        machine.feed_input(i);
+    }
+    let (output, end) =
+        run_until_stopped(|| machine.run().map(StopReason::from));
+    Outcome {
+        output,
+        end,
+        mem: nonzero(machine.mem()),
+    }
+}
+
+/// Two ways to run a program that disagree.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub runs: [(&'static str, Outcome); 2],
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, outcome) in &self.runs {
+            writeln!(f, "{}: {:?}", name, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `program` every way that applies and returns the first two
+/// outcomes that differ.
+pub fn check(program: &Program) -> Option<Mismatch> {
+    let mem = program.assemble();
+    let input = &program.input;
+    let interpreter = ("interpreter", run_iss(&mem, Config::DAY09, input));
+    let mut runs = vec![
+        (
+            "compiled",
+            run_iss(&mem, Config::DAY09.compiled(), input),
+            true,
+        ),
+        ("big", run_big(&mem, Config::DAY09, input), true),
+    ];
+    if program.isa == Isa::Day05 {
+        let day05 = run_iss(&mem, Config::DAY05, input);
+        let compiled = run_iss(&mem, Config::DAY05.compiled(), input);
+        if day05 != compiled {
+            return Some(Mismatch {
+                runs: [("day05", day05), ("day05 compiled", compiled)],
+            });
+        }
+        // day05 machines fail on values and addresses others allow
+        let ok = day05.end.is_ok();
+        runs.push(("day05", day05, ok));
+    }
+    for (name, outcome, compare) in runs {
+        if compare && outcome != interpreter.1 {
+            return Some(Mismatch {
+                runs: [interpreter, (name, outcome)],
+            });
+        }
+    }
+    None
+}
+
+/// Simplifies `program` for as long as it keeps failing.
+pub fn shrink(
+    mut program: Program,
+    fails: impl Fn(&Program) -> bool,
+) -> Program {
+    'shrink: loop {
+        for candidate in program.simpler() {
+            if fails(&candidate) {
+                program = candidate;
+                continue 'shrink;
+            }
+        }
+        return program;
+    }
+}
+
+/// Checks `runs` programs generated from `seed`, alternately sticking to
+/// the day05 and using the day09 instruction set. Returns the first
+/// mismatch with its program shrunk.
+pub fn fuzz(seed: u64, runs: usize) -> Option<(Program, Mismatch)> {
+    let mut rng = Rng::new(seed);
+    for run in 0..runs {
+        let isa = if run % 2 == 0 { Isa::Day05 } else { Isa::Day09 };
+        let program = Program::generate(&mut rng, isa);
+        if check(&program).is_some() {
+            let program = shrink(program, |p| check(p).is_some());
+            let mismatch = check(&program)?;
+            return Some((program, mismatch));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 2019;
+
+    #[test]
+    fn backends_agree() {
+        if let Some((program, mismatch)) = fuzz(SEED, 400) {
+            panic!("{}\n{}", program, mismatch);
+        }
+    }
+
+    #[test]
+    fn shrinks() {
+        let mut rng = Rng::new(SEED);
+        let outputs = |p: &Program| {
+            !run_iss(&p.assemble(), Config::DAY09, &p.input)
+                .output
+                .is_empty()
+        };
+        let program = (0..)
+            .map(|_| Program::generate(&mut rng, Isa::Day09))
+            .find(|p| outputs(p) && p.stmts.len() > 2)
+            .unwrap();
+        let program = shrink(program, outputs);
+        assert_eq!(program.stmts, vec![Stmt::Out(Arg::Imm(0))]);
+        assert!(program.input.is_empty());
+        assert!(program.data.iter().all(|&v| v == 0));
+        assert!(program
+            .to_string()
+            .starts_with("; input: \n        rbo #stack\n        out #0\n"));
+    }
+}
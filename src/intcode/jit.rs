@@ -0,0 +1,443 @@
+//! The compiled execution tier.
+//!
+//! Straight-line runs of instructions are translated once into blocks of
+//! pre-decoded operations, with their operands resolved to immediates,
+//! absolute addresses or offsets from the relative base. A block ends after
+//! a jump, I/O or `hlt`, or before an instruction that cannot be decoded.
+//!
+//! Blocks are shared between clones of a machine, so they are checked
+//! against the code they were compiled from whenever they are entered. A
+//! write to a block's own code leaves it right away. Whenever an operation
+//! would fail, the interpreter executes that instruction instead, which
+//! keeps errors identical between the tiers.
+
+use super::big::Number;
+use super::exec::Core;
+use super::pages::Pages;
+use super::{reach, Insn, Iss, Mode, StopReason, Value, Width};
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+/// Instructions translated into a single block at most.
+const MAX_BLOCK: usize = 64;
+
+/// Blocks kept for the same address, compiled from differing code.
+const MAX_VARIANTS: usize = 4;
+
+#[derive(Clone, Copy)]
+enum Arg {
+    Imm(Value),
+    Pos(usize),
+    Rel(Value),
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Add(Arg, Arg, Arg),
+    Mul(Arg, Arg, Arg),
+    Lt(Arg, Arg, Arg),
+    Equ(Arg, Arg, Arg),
+    Jit(Arg, Arg),
+    Jif(Arg, Arg),
+    In(Arg),
+    Out(Arg),
+    Rbo(Arg),
+    Halt,
+}
+
+struct Block {
+    start: usize,
+    /// The memory the block was compiled from.
+    code: Vec<Value>,
+    /// Operations with the address of their instruction.
+    ops: Vec<(usize, Op)>,
+}
+
+impl Block {
+    fn compile(iss: &Iss, start: usize) -> Option<Block> {
+        let mem = &iss.mem;
+        let mut ops = Vec::new();
+        let mut pc = start;
+        while ops.len() < MAX_BLOCK {
+            let insn = match mem.get(pc).map(Insn::decode) {
+                Some(Ok(insn)) if insn.isa() <= iss.config.isa => insn,
+                _ => break,
+            };
+            let cells: Vec<Value> = match (pc + 1..pc + insn.len())
+                .map(|addr| mem.get(addr))
+                .collect()
+            {
+                Some(cells) => cells,
+                None => break,
+            };
+            let arg = |n: usize| match insn.modes()[n] {
+                Mode::Immediate => Some(Arg::Imm(cells[n])),
+                Mode::Position => usize::try_from(cells[n]).ok().map(Arg::Pos),
+                Mode::Relative => Some(Arg::Rel(cells[n])),
+            };
+            // an immediate destination is the operand's own cell
+            let dst = |n: usize| match insn.modes()[n] {
+                Mode::Immediate => Some(Arg::Pos(pc + 1 + n)),
+                _ => arg(n),
+            };
+            let op = match insn {
+                Insn::Add(_) => (|| Some(Op::Add(arg(0)?, arg(1)?, dst(2)?)))(),
+                Insn::Mul(_) => (|| Some(Op::Mul(arg(0)?, arg(1)?, dst(2)?)))(),
+                Insn::Lt(_) => (|| Some(Op::Lt(arg(0)?, arg(1)?, dst(2)?)))(),
+                Insn::Equ(_) => (|| Some(Op::Equ(arg(0)?, arg(1)?, dst(2)?)))(),
+                Insn::Jit(_) => (|| Some(Op::Jit(arg(0)?, arg(1)?)))(),
+                Insn::Jif(_) => (|| Some(Op::Jif(arg(0)?, arg(1)?)))(),
+                Insn::In(_) => dst(0).map(Op::In),
+                Insn::Out(_) => arg(0).map(Op::Out),
+                Insn::Rbo(_) => arg(0).map(Op::Rbo),
+                Insn::Halt => Some(Op::Halt),
+            };
+            let op = match op {
+                Some(op) => op,
+                None => break,
+            };
+            ops.push((pc, op));
+            pc += insn.len();
+            if let Op::Jit(..)
+            | Op::Jif(..)
+            | Op::In(_)
+            | Op::Out(_)
+            | Op::Halt = op
+            {
+                break;
+            }
+        }
+
+        if ops.is_empty() {
+            None
+        } else {
+            Some(Block {
+                start,
+                code: (start..pc).filter_map(|addr| mem.get(addr)).collect(),
+                ops,
+            })
+        }
+    }
+
+    fn is_valid(&self, mem: &Pages) -> bool {
+        mem.matches(self.start, &self.code)
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.start + self.code.len()
+    }
+}
+
+/// Compiled blocks by start address, most recently compiled first.
+#[derive(Clone, Default)]
+pub(super) struct Blocks(Rc<RefCell<Vec<Vec<Rc<Block>>>>>);
+
+enum Flow {
+    Next,
+    /// Continue at `pc` with a fresh block.
+    Leave,
+    Stop(StopReason),
+    /// Leave the instruction to the interpreter.
+    Interpret,
+}
+
+impl Iss {
+    /// Runs compiled blocks until the machine stops, or returns `None` once
+    /// the instruction at `pc` has to be interpreted.
+    pub(super) fn run_compiled(&mut self) -> Option<StopReason> {
+        loop {
+            let block = self.block()?;
+            for &(pc, op) in &block.ops {
+                self.pc = pc;
+                if self.meter.over_budget() {
+                    return Some(StopReason::BudgetExhausted);
+                }
+                let flow = self.execute_op(op, &block);
+                if !matches!(
+                    flow,
+                    Flow::Interpret | Flow::Stop(StopReason::OutOfInput)
+                ) {
+                    self.meter.charge(pc);
+                }
+                match flow {
+                    Flow::Next => (),
+                    Flow::Leave => break,
+                    Flow::Stop(reason) => return Some(reason),
+                    Flow::Interpret => return None,
+                }
+            }
+        }
+    }
+
+    /// The block starting at `pc`. Code beyond the dense part of memory is
+    /// left to the interpreter, keeping the blocks by address dense as well.
+    fn block(&mut self) -> Option<Rc<Block>> {
+        if self.pc >= self.mem.len() {
+            return None;
+        }
+        let blocks = self.blocks.clone()?;
+        let cached = blocks.0.borrow().get(self.pc).and_then(|variants| {
+            variants.iter().find(|b| b.is_valid(&self.mem)).cloned()
+        });
+        if cached.is_some() {
+            return cached;
+        }
+
+        // forks may have modified their code in different ways
+        let block = Rc::new(Block::compile(self, self.pc)?);
+        let mut blocks = blocks.0.borrow_mut();
+        if blocks.len() <= self.pc {
+            blocks.resize(self.pc + 1, Vec::new());
+        }
+        let variants = &mut blocks[self.pc];
+        variants.truncate(MAX_VARIANTS - 1);
+        variants.insert(0, Rc::clone(&block));
+        Some(block)
+    }
+
+    fn address(&self, arg: Arg) -> Option<usize> {
+        match arg {
+            Arg::Imm(_) => None,
+            Arg::Pos(addr) => Some(addr),
+            Arg::Rel(offset) => {
+                usize::try_from(offset.checked_add(self.rb)?).ok()
+            }
+        }
+    }
+
+    fn load_arg(&mut self, arg: Arg) -> Option<Value> {
+        if let Arg::Imm(v) = arg {
+            return Some(v);
+        }
+        let addr = self.address(arg)?;
+        reach(&mut self.mem, &self.config, addr).ok()?;
+        Some(self.mem.get(addr).unwrap_or_default())
+    }
+
+    /// Stores `v` unless that would fail, returning whether `block` was
+    /// written to.
+    fn store_arg(&mut self, arg: Arg, v: Value, block: &Block) -> Option<bool> {
+        if self.config.width == Width::W32 && i32::try_from(v).is_err() {
+            return None;
+        }
+        let addr = self.address(arg)?;
+        reach(&mut self.mem, &self.config, addr).ok()?;
+        *self.mem.cell_mut(addr) = v;
+        Some(block.contains(addr))
+    }
+
+    fn execute_op(&mut self, op: Op, block: &Block) -> Flow {
+        let store =
+            |iss: &mut Iss, dst, v, len| match iss.store_arg(dst, v, block) {
+                Some(modified) => {
+                    iss.pc += len;
+                    if modified {
+                        Flow::Leave
+                    } else {
+                        Flow::Next
+                    }
+                }
+                None => Flow::Interpret,
+            };
+        let binary = |iss: &mut Iss, a, b| -> Option<(Value, Value)> {
+            Some((iss.load_arg(a)?, iss.load_arg(b)?))
+        };
+        let jump = |iss: &mut Iss, cond: bool, target| {
+            if cond {
+                let target = iss.load_arg(target);
+                match target.and_then(|t| usize::try_from(t).ok()) {
+                    Some(t) => iss.pc = t,
+                    None => return Flow::Interpret,
+                }
+            } else {
+                iss.pc += 3;
+            }
+            Flow::Leave
+        };
+
+        match op {
+            Op::Add(a, b, dst) => match binary(self, a, b).and_then(|(a, b)| {
+                let (checked, wrapping) =
+                    (Number::checked_add, Number::wrapping_add);
+                self.arith(&a, &b, checked, wrapping).ok()
+            }) {
+                Some(v) => store(self, dst, v, 4),
+                None => Flow::Interpret,
+            },
+            Op::Mul(a, b, dst) => match binary(self, a, b).and_then(|(a, b)| {
+                let (checked, wrapping) =
+                    (Number::checked_mul, Number::wrapping_mul);
+                self.arith(&a, &b, checked, wrapping).ok()
+            }) {
+                Some(v) => store(self, dst, v, 4),
+                None => Flow::Interpret,
+            },
+            Op::Lt(a, b, dst) => match binary(self, a, b) {
+                Some((a, b)) => store(self, dst, Value::from(a < b), 4),
+                None => Flow::Interpret,
+            },
+            Op::Equ(a, b, dst) => match binary(self, a, b) {
+                Some((a, b)) => store(self, dst, Value::from(a == b), 4),
+                None => Flow::Interpret,
+            },
+            Op::Jit(cond, target) => match self.load_arg(cond) {
+                Some(v) => jump(self, v != 0, target),
+                None => Flow::Interpret,
+            },
+            Op::Jif(cond, target) => match self.load_arg(cond) {
+                Some(v) => jump(self, v == 0, target),
+                None => Flow::Interpret,
+            },
+            Op::In(dst) => match self.input.front() {
+                Some(&i) => {
+                    let flow = store(self, dst, i, 2);
+                    if let Flow::Next | Flow::Leave = flow {
+                        self.input.pop_front();
+                    }
+                    flow
+                }
+                None => Flow::Stop(StopReason::OutOfInput),
+            },
+            Op::Out(src) => match self.load_arg(src) {
+                Some(o) => {
+                    self.pc += 2;
+                    Flow::Stop(StopReason::Output(o))
+                }
+                None => Flow::Interpret,
+            },
+            Op::Rbo(offset) => match self.load_arg(offset).and_then(|v| {
+                let (checked, wrapping) =
+                    (Number::checked_add, Number::wrapping_add);
+                self.arith(&self.rb, &v, checked, wrapping).ok()
+            }) {
+                Some(rb) => {
+                    self.rb = rb;
+                    self.pc += 2;
+                    Flow::Next
+                }
+                None => Flow::Interpret,
+            },
+            Op::Halt => Flow::Stop(StopReason::Halted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Config, Exec};
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::parse;
+
+    fn both(mem: &[Value], config: Config, input: &[Value]) -> [Iss; 2] {
+        [Exec::Interpreter, Exec::Compiled].map(|exec| {
+            let config = Config { exec, ..config };
+            let mut iss = Iss::with_config(mem.to_vec(), config);
+            for &i in input {
+                iss.feed_input(i);
+            }
+            iss
+        })
+    }
+
+    /// Runs both tiers to completion and compares everything observable.
+    fn identical(mem: &[Value], config: Config, input: &[Value]) {
+        let [mut a, mut b] = both(mem, config, input);
+        loop {
+            let (ra, rb) = (a.run(), b.run());
+            assert_eq!(a.mem().to_vec(), b.mem().to_vec());
+            assert_eq!((a.pc(), a.rb()), (b.pc(), b.rb()));
+            match (ra, rb) {
+                (Ok(ra), Ok(rb)) => {
+                    assert_eq!(ra, rb);
+                    if let StopReason::Output(_) = ra {
+                        continue;
+                    }
+                }
+                (Err(ea), Err(eb)) => {
+                    assert_eq!(ea.to_string(), eb.to_string())
+                }
+                (ra, rb) => panic!("{:?} != {:?}", ra, rb),
+            }
+            break;
+        }
+    }
+
+    #[test]
+    fn days() {
+        let day = |n| match n {
+            5 => include_str!("../../input/day05"),
+            9 => include_str!("../../input/day09"),
+            _ => include_str!("../../input/day19"),
+        };
+        identical(&parse(day(5)).unwrap(), Config::DAY05, &[1]);
+        identical(&parse(day(5)).unwrap(), Config::DAY05, &[5]);
+        identical(&parse(day(9)).unwrap(), Config::DAY09, &[1]);
+        identical(&parse(day(19)).unwrap(), Config::DAY09, &[12, 17]);
+    }
+
+    #[test]
+    fn self_modifying() {
+        // patches the operand of the next instruction, and later the opcode
+        // of one in the same block
+        let mem = assemble(
+            "
+        add #7, #0, [patch+1]
+patch:  out #0
+        add #0, #1101, [op]
+op:     mul #3, #4, [v]
+        out [v]
+        hlt
+v:      .data 0
+",
+        )
+        .unwrap();
+        identical(&mem, Config::DAY09, &[]);
+        let [_, mut b] = both(&mem, Config::DAY09, &[]);
+        assert_eq!(b.run_continuous().unwrap(), vec![7, 7]);
+    }
+
+    #[test]
+    fn errors_and_writes() {
+        // jumps to a negative address
+        identical(&[1105, 1, 4, 99, -1], Config::DAY09, &[]);
+        // overflows a 32 bit cell
+        identical(&[1002, 5, 3, 5, 99, 1 << 30], Config::DAY05, &[]);
+        // reads beyond strict memory
+        identical(&[1, 100, 0, 0, 99], Config::DAY05, &[]);
+        // runs out of input, then is given some
+        let mem = [3, 7, 4, 7, 99, 0, 0, 0];
+        for mut iss in both(&mem, Config::DAY09, &[]) {
+            assert_eq!(iss.run().unwrap(), StopReason::OutOfInput);
+            iss.feed_input(42);
+            assert_eq!(iss.run().unwrap(), StopReason::Output(42));
+            assert_eq!(iss.run().unwrap(), StopReason::Halted);
+            // writes from outside invalidate blocks too
+            iss.set_pc(0);
+            *iss.access(4).unwrap() = 104;
+            iss.feed_input(1);
+            assert_eq!(iss.run().unwrap(), StopReason::Output(1));
+            assert_eq!(iss.run().unwrap(), StopReason::Output(0));
+        }
+    }
+
+    #[test]
+    fn far_code() {
+        // writes `out #7, hlt` far beyond the program and jumps there
+        let mem = assemble(
+            "
+        add #104, #0, [1099511627776]
+        add #7, #0, [1099511627777]
+        add #99, #0, [1099511627778]
+        jit #1, #1099511627776
+",
+        )
+        .unwrap();
+        identical(&mem, Config::DAY09, &[]);
+        let [_, mut b] = both(&mem, Config::DAY09, &[]);
+        assert_eq!(b.run_continuous().unwrap(), vec![7]);
+        assert_eq!(b.pc(), (1 << 40) + 2);
+    }
+}
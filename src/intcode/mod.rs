@@ -0,0 +1,1431 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Instant;
+
+use self::coverage::Coverage;
+use self::cycles::Cycles;
+pub use self::exec::Event;
+use self::exec::{Core, Meter};
+use self::pages::Pages;
+use self::profile::Profile;
+use self::trace::{Io, MemWrite, Record, Trace};
+
+pub mod ascii;
+pub mod asm;
+pub mod big;
+pub mod cfg;
+pub mod coverage;
+mod cycles;
+pub mod debugger;
+pub mod decompile;
+pub mod device;
+pub mod disasm;
+mod exec;
+pub mod fault;
+pub mod fuzz;
+mod jit;
+pub mod network;
+pub mod pages;
+pub mod pipeline;
+pub mod profile;
+pub mod snapshot;
+pub mod symbolic;
+pub mod trace;
+
+pub enum Error {
+    IllegalInstruction,
+    /// An instruction giving a parameter an unknown mode, or one the
+    /// instruction set lacks.
+    IllegalMode {
+        param: usize,
+        mode: Value,
+    },
+    AddressOutOfRange,
+    NegativeAddress,
+    MemoryLimit,
+    ValueOutOfRange,
+    Overflow {
+        pc: usize,
+    },
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::IllegalInstruction => write!(f, "IllegalInstruction"),
+            Error::IllegalMode { param, mode } => {
+                write!(f, "IllegalMode {} for parameter {}", mode, param)
+            }
+            Error::AddressOutOfRange => write!(f, "AddressOutOfRange"),
+            Error::NegativeAddress => write!(f, "NegativeAddress"),
+            Error::MemoryLimit => write!(f, "MemoryLimit"),
+            Error::ValueOutOfRange => write!(f, "ValueOutOfRange"),
+            Error::Overflow { pc } => write!(f, "Overflow at pc {}", pc),
+        }
+    }
+}
+
+pub type Value = i64;
+
+/// Parses a comma separated memory image.
+pub fn parse(input: &str) -> crate::Result<Vec<Value>> {
+    Ok(input
+        .trim()
+        .split(',')
+        .map(|s| s.parse())
+        .collect::<Result<_, _>>()?)
+}
+
+/// Converts a value used as an address.
+fn address(v: Value) -> crate::Result<usize> {
+    usize::try_from(v)
+        .map_err(|_| crate::Error::boxed(Error::NegativeAddress).into())
+}
+
+/// Makes `addr` valid, if the memory model allows.
+fn reach<T>(
+    mem: &mut Pages<T>,
+    config: &Config,
+    addr: usize,
+) -> crate::Result<()>
+where
+    T: Clone + Default + PartialEq,
+{
+    let cost = mem.cost(addr);
+    if cost > 0 {
+        match config.memory {
+            Memory::Strict => {
+                return Err(crate::Error::boxed(Error::AddressOutOfRange))
+            }
+            Memory::Growing => {
+                if mem.cells() + cost > config.max_memory {
+                    return Err(crate::Error::boxed(Error::MemoryLimit));
+                }
+                mem.touch(addr)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How accesses beyond the end of memory are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Memory {
+    /// Out of range accesses are an error.
+    Strict,
+    /// Memory grows on demand, fresh cells read as zero. Cells far beyond
+    /// the end are kept in a sparse map instead.
+    Growing,
+}
+
+/// The instruction set a machine understands. Each level includes the
+/// previous ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Isa {
+    /// `add`, `mul` and `hlt` in position mode.
+    Day02,
+    /// I/O, jumps, comparisons and immediate mode.
+    Day05,
+    /// Relative base offsets and relative mode.
+    Day09,
+}
+
+/// The range of values a machine may store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    W32,
+    W64,
+}
+
+/// What happens when `add`, `mul` or `rbo` overflow a 64 bit value. See
+/// `big` for machines without a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arith {
+    /// Overflow is an error.
+    Checked,
+    /// Results wrap around.
+    Wrapping,
+}
+
+/// How a machine executes its program. Both give identical results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exec {
+    /// Decodes every instruction as it is executed.
+    Interpreter,
+    /// Compiles runs of instructions into blocks of pre-decoded operations
+    /// and falls back to the interpreter while watching, tracing, profiling,
+    /// recording coverage or detecting cycles.
+    Compiled,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub memory: Memory,
+    pub isa: Isa,
+    pub width: Width,
+    pub exec: Exec,
+    pub arith: Arith,
+    /// The number of cells growing memory may hold before failing.
+    pub max_memory: usize,
+}
+
+/// 128 MiB worth of cells.
+const MAX_MEMORY: usize = 1 << 24;
+
+impl Config {
+    pub const DAY02: Config = Config {
+        memory: Memory::Strict,
+        isa: Isa::Day02,
+        width: Width::W64,
+        exec: Exec::Interpreter,
+        arith: Arith::Checked,
+        max_memory: MAX_MEMORY,
+    };
+
+    pub const DAY05: Config = Config {
+        memory: Memory::Strict,
+        isa: Isa::Day05,
+        width: Width::W32,
+        exec: Exec::Interpreter,
+        arith: Arith::Checked,
+        max_memory: MAX_MEMORY,
+    };
+
+    pub const DAY09: Config = Config {
+        memory: Memory::Growing,
+        isa: Isa::Day09,
+        width: Width::W64,
+        exec: Exec::Interpreter,
+        arith: Arith::Checked,
+        max_memory: MAX_MEMORY,
+    };
+}
+
+impl Config {
+    /// The same configuration with the compiled execution tier.
+    pub fn compiled(self) -> Config {
+        Config {
+            exec: Exec::Compiled,
+            ..self
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::DAY09
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    fn isa(self) -> Isa {
+        match self {
+            Mode::Position => Isa::Day02,
+            Mode::Immediate => Isa::Day05,
+            Mode::Relative => Isa::Day09,
+        }
+    }
+}
+
+impl From<Mode> for Value {
+    fn from(m: Mode) -> Self {
+        match m {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+impl TryFrom<usize> for Mode {
+    type Error = Box<crate::Error<Error>>;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Mode::Position),
+            1 => Ok(Mode::Immediate),
+            2 => Ok(Mode::Relative),
+            _ => Err(crate::Error::boxed(Error::IllegalInstruction)),
+        }
+    }
+}
+
+const MAX_INSN_VALUE: usize = 33399;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Insn {
+    Add([Mode; 3]),
+    Mul([Mode; 3]),
+    In([Mode; 1]),
+    Out([Mode; 1]),
+    Jit([Mode; 2]),
+    Jif([Mode; 2]),
+    Lt([Mode; 3]),
+    Equ([Mode; 3]),
+    Rbo([Mode; 1]),
+    Halt,
+}
+
+impl Insn {
+    pub fn decode(value: Value) -> crate::Result<Insn> {
+        if value < 0 || value as usize > MAX_INSN_VALUE {
+            return Err(crate::Error::boxed(Error::IllegalInstruction));
+        }
+        Ok(Insn::try_from(value as usize)?)
+    }
+
+    pub fn encode(&self) -> Value {
+        let opcode = match self {
+            Insn::Add(_) => 1,
+            Insn::Mul(_) => 2,
+            Insn::In(_) => 3,
+            Insn::Out(_) => 4,
+            Insn::Jit(_) => 5,
+            Insn::Jif(_) => 6,
+            Insn::Lt(_) => 7,
+            Insn::Equ(_) => 8,
+            Insn::Rbo(_) => 9,
+            Insn::Halt => 99,
+        };
+        let modes = self
+            .modes()
+            .iter()
+            .rev()
+            .fold(0, |acc, &m| acc * 10 + Value::from(m));
+        modes * 100 + opcode
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Insn::Add(_) => "add",
+            Insn::Mul(_) => "mul",
+            Insn::In(_) => "in",
+            Insn::Out(_) => "out",
+            Insn::Jit(_) => "jit",
+            Insn::Jif(_) => "jif",
+            Insn::Lt(_) => "lt",
+            Insn::Equ(_) => "eq",
+            Insn::Rbo(_) => "rbo",
+            Insn::Halt => "hlt",
+        }
+    }
+
+    /// The number of memory cells the instruction occupies, including the
+    /// opcode.
+    pub fn len(&self) -> usize {
+        self.modes().len() + 1
+    }
+
+    pub fn modes(&self) -> &[Mode] {
+        match self {
+            Insn::Add(m) | Insn::Mul(m) | Insn::Lt(m) | Insn::Equ(m) => m,
+            Insn::Jit(m) | Insn::Jif(m) => m,
+            Insn::In(m) | Insn::Out(m) | Insn::Rbo(m) => m,
+            Insn::Halt => &[],
+        }
+    }
+
+    /// The smallest instruction set that contains this instruction.
+    fn isa(&self) -> Isa {
+        let isa = match self {
+            Insn::Add(_) | Insn::Mul(_) | Insn::Halt => Isa::Day02,
+            Insn::Rbo(_) => Isa::Day09,
+            _ => Isa::Day05,
+        };
+        self.modes()
+            .iter()
+            .map(|m| m.isa())
+            .fold(isa, std::cmp::max)
+    }
+}
+
+impl TryFrom<usize> for Insn {
+    type Error = Box<crate::Error<Error>>;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        let opcode = value % 100;
+
+        let mode = |param: usize| {
+            let mode = value / 10usize.pow(param as u32 + 1) % 10;
+            Mode::try_from(mode).map_err(|_| {
+                crate::Error::boxed(Error::IllegalMode {
+                    param,
+                    mode: mode as Value,
+                })
+            })
+        };
+        let (m1, m2, m3) = (mode(1)?, mode(2)?, mode(3)?);
+
+        match opcode {
+            1 => Ok(Insn::Add([m1, m2, m3])),
+            2 => Ok(Insn::Mul([m1, m2, m3])),
+            3 => Ok(Insn::In([m1])),
+            4 => Ok(Insn::Out([m1])),
+            5 => Ok(Insn::Jit([m1, m2])),
+            6 => Ok(Insn::Jif([m1, m2])),
+            7 => Ok(Insn::Lt([m1, m2, m3])),
+            8 => Ok(Insn::Equ([m1, m2, m3])),
+            9 => Ok(Insn::Rbo([m1])),
+            99 => Ok(Insn::Halt),
+            _ => Err(crate::Error::boxed(Error::IllegalInstruction)),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Icache {
+    cache: Rc<RefCell<Vec<Option<Insn>>>>,
+    isa: Isa,
+}
+
+impl Icache {
+    fn new(isa: Isa) -> Self {
+        Icache {
+            cache: Rc::new(RefCell::new(Vec::new())),
+            isa,
+        }
+    }
+
+    fn fetch_insn(&self, value: Value) -> crate::Result<Insn> {
+        if value < 0 || value as usize > MAX_INSN_VALUE {
+            return Err(crate::Error::boxed(Error::IllegalInstruction));
+        }
+        let value = value as usize;
+
+        let mut cache = self.cache.borrow_mut();
+        if value + 1 > cache.len() {
+            cache.resize(value + 1, None);
+        }
+
+        if let Some(insn) = &cache[value] {
+            Ok(*insn)
+        } else {
+            let insn = Insn::try_from(value)?;
+            if insn.isa() > self.isa {
+                let modes = insn.modes().iter();
+                let error =
+                    match modes.zip(1..).find(|(m, _)| m.isa() > self.isa) {
+                        Some((&m, param)) => Error::IllegalMode {
+                            param,
+                            mode: m.into(),
+                        },
+                        None => Error::IllegalInstruction,
+                    };
+                return Err(crate::Error::boxed(error));
+            }
+            cache[value] = Some(insn);
+            Ok(insn)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Output(Value),
+    OutOfInput,
+    Halted,
+    /// A watched cell was accessed by the instruction at `pc`. For reads,
+    /// `old` and `new` are the value read.
+    Watchpoint {
+        addr: usize,
+        old: Value,
+        new: Value,
+        pc: usize,
+    },
+    /// The budget ran out before the instruction at the current `pc`.
+    BudgetExhausted,
+    /// The machine jumped back to `pc` in the same state it was in
+    /// `period` instructions earlier, without input or output in between,
+    /// and would do so forever.
+    InfiniteLoop {
+        pc: usize,
+        period: u64,
+    },
+}
+
+/// Limits on how much `run` executes before returning
+/// `StopReason::BudgetExhausted`. A machine out of budget can be resumed
+/// with a new one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// The number of instructions left to execute.
+    pub steps: Option<u64>,
+    /// When to stop. Only checked every so many instructions.
+    pub deadline: Option<Instant>,
+}
+
+/// The kind of memory access a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+pub type WatchId = usize;
+
+/// Copies a machine to explore from, like `Iss::fork` does.
+pub type Fork = fn(&Iss) -> Iss;
+
+#[derive(Clone)]
+struct Watchpoint {
+    id: WatchId,
+    range: Range<usize>,
+    access: Access,
+    predicate: Option<Rc<dyn Fn(Value) -> bool>>,
+}
+
+impl Watchpoint {
+    fn matches(&self, addr: usize, access: Access, new: Value) -> bool {
+        self.range.contains(&addr)
+            && (self.access == Access::Any || self.access == access)
+            && self.predicate.as_ref().is_none_or(|p| p(new))
+    }
+}
+
+#[derive(Clone)]
+pub struct Iss {
+    mem: Pages,
+    pc: usize,
+    rb: Value,
+    input: VecDeque<Value>,
+    icache: Icache,
+    blocks: Option<jit::Blocks>,
+    config: Config,
+    watches: Vec<Watchpoint>,
+    next_watch: WatchId,
+    watch_hit: Option<StopReason>,
+    pending_output: Option<Value>,
+    trace: Option<Trace>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    meter: Meter,
+    cycles: Option<Cycles>,
+}
+
+impl Iss {
+    pub fn new(mem: Vec<Value>) -> Self {
+        Iss::with_config(mem, Config::default())
+    }
+
+    pub fn with_input(mem: Vec<Value>, input: Vec<Value>) -> Self {
+        let mut iss = Iss::new(mem);
+        iss.input = input.into();
+        iss
+    }
+
+    pub fn with_config(mem: Vec<Value>, config: Config) -> Self {
+        Iss {
+            mem: mem.into(),
+            pc: 0,
+            rb: 0,
+            input: VecDeque::new(),
+            icache: Icache::new(config.isa),
+            blocks: match config.exec {
+                Exec::Interpreter => None,
+                Exec::Compiled => Some(jit::Blocks::default()),
+            },
+            config,
+            watches: Vec::new(),
+            next_watch: 0,
+            watch_hit: None,
+            pending_output: None,
+            trace: None,
+            profile: None,
+            coverage: None,
+            meter: Meter::new(),
+            cycles: None,
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+        self.disturb(false);
+    }
+
+    pub fn rb(&self) -> Value {
+        self.rb
+    }
+
+    pub fn set_rb(&mut self, rb: Value) {
+        self.rb = rb;
+        self.disturb(false);
+    }
+
+    /// The memory touched so far.
+    pub fn mem(&self) -> &Pages {
+        &self.mem
+    }
+
+    /// Input fed but not yet consumed.
+    pub fn pending_input(&self) -> impl Iterator<Item = &Value> {
+        self.input.iter()
+    }
+
+    /// Stops the machine after an instruction accesses a cell in `range`.
+    pub fn watch(&mut self, range: Range<usize>, access: Access) -> WatchId {
+        self.add_watch(range, access, None)
+    }
+
+    /// Like `watch`, but only stops if the value read or written satisfies
+    /// `predicate`.
+    pub fn watch_if(
+        &mut self,
+        range: Range<usize>,
+        access: Access,
+        predicate: impl Fn(Value) -> bool + 'static,
+    ) -> WatchId {
+        self.add_watch(range, access, Some(Rc::new(predicate)))
+    }
+
+    fn add_watch(
+        &mut self,
+        range: Range<usize>,
+        access: Access,
+        predicate: Option<Rc<dyn Fn(Value) -> bool>>,
+    ) -> WatchId {
+        let id = self.next_watch;
+        self.next_watch += 1;
+        self.watches.push(Watchpoint {
+            id,
+            range,
+            access,
+            predicate,
+        });
+        id
+    }
+
+    /// Removes a watchpoint. Returns whether it existed.
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|w| w.id != id);
+        self.watches.len() != len
+    }
+
+    /// Active watchpoints with their ranges and access kinds.
+    pub fn watches(
+        &self,
+    ) -> impl Iterator<Item = (WatchId, Range<usize>, Access)> + '_ {
+        self.watches
+            .iter()
+            .map(|w| (w.id, w.range.clone(), w.access))
+    }
+
+    fn check_watches(
+        &mut self,
+        addr: usize,
+        access: Access,
+        old: Value,
+        new: Value,
+    ) {
+        if self.watch_hit.is_none()
+            && self.watches.iter().any(|w| w.matches(addr, access, new))
+        {
+            self.watch_hit = Some(StopReason::Watchpoint {
+                addr,
+                old,
+                new,
+                pc: self.pc,
+            });
+        }
+    }
+
+    /// Starts recording executed instructions, discarding any previous
+    /// trace. The recording also serves as undo log for `step_back`.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::new(self.mem.to_vec()));
+    }
+
+    /// Forgets all but the last `keep` recorded instructions, so that a
+    /// recording used as undo log stays bounded. Returns how many were
+    /// forgotten. A trace cut short no longer replays from its memory image.
+    pub fn truncate_trace(&mut self, keep: usize) -> usize {
+        match &mut self.trace {
+            Some(trace) if trace.records.len() > keep => {
+                let n = trace.records.len() - keep;
+                trace.records.drain(..n);
+                n
+            }
+            _ => 0,
+        }
+    }
+
+    /// Stops recording and returns the trace, if one was started.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// Starts counting executed instructions, discarding any previous
+    /// profile.
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::new(self.mem.to_vec()));
+    }
+
+    /// Stops counting and returns the profile, if one was started.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Starts recording which instructions and branches are executed,
+    /// discarding any previous coverage.
+    pub fn start_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(self.mem.to_vec()));
+    }
+
+    /// Stops recording and returns the coverage, if recording was started.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// What is left of the budget.
+    #[cfg(test)]
+    pub fn budget(&self) -> Budget {
+        self.meter.budget()
+    }
+
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.meter.set_budget(budget);
+    }
+
+    /// Makes `run` stop with `StopReason::InfiniteLoop` once the machine
+    /// provably loops forever, or stops looking.
+    pub fn detect_cycles(&mut self, on: bool) {
+        self.cycles = if on { Some(Cycles::new()) } else { None };
+    }
+
+    /// Forgets what the cycle detector saw, after the state was changed
+    /// other than by executing. `mem` tells whether memory changed.
+    fn disturb(&mut self, mem: bool) {
+        if let Some(cycles) = &mut self.cycles {
+            if mem {
+                cycles.invalidate();
+            } else {
+                cycles.reset();
+            }
+        }
+    }
+
+    fn record(&mut self) -> Option<&mut Record> {
+        self.trace.as_mut().and_then(|t| t.records.last_mut())
+    }
+
+    /// A copy of the machine sharing its memory until either of them writes
+    /// to it. Unlike a clone, the copy does not take the recording, the
+    /// profile, the coverage or the cycle detector along.
+    pub fn fork(&self) -> Iss {
+        Iss {
+            mem: self.mem.clone(),
+            pc: self.pc,
+            rb: self.rb,
+            input: self.input.clone(),
+            icache: self.icache.clone(),
+            blocks: self.blocks.clone(),
+            config: self.config,
+            watches: self.watches.clone(),
+            next_watch: self.next_watch,
+            watch_hit: self.watch_hit,
+            pending_output: self.pending_output,
+            trace: None,
+            profile: None,
+            coverage: None,
+            meter: self.meter.clone(),
+            cycles: None,
+        }
+    }
+
+    /// Gives the machine its own copy of all memory shared with forks.
+    pub fn unshare(&mut self) {
+        self.mem.unshare();
+    }
+
+    pub fn access(&mut self, addr: usize) -> crate::Result<&mut Value> {
+        reach(&mut self.mem, &self.config, addr)?;
+        self.disturb(true);
+        Ok(self.mem.cell_mut(addr))
+    }
+
+    pub fn feed_str(&mut self, s: &str) -> crate::Result<()> {
+        for c in s.chars() {
+            self.input.push_back(u32::from(c).into());
+        }
+        Ok(())
+    }
+
+    pub fn feed_input(&mut self, i: Value) {
+        self.input.push_back(i);
+    }
+
+    /// Executes a single instruction. Returns why the machine stopped, if
+    /// it did. A machine stopped for lack of input or by `hlt` does not
+    /// advance its `pc`.
+    ///
+    /// Watchpoints are reported once the accessing instruction completed. If
+    /// that instruction also produced output, the output is returned by the
+    /// next call instead of executing another instruction.
+    pub fn step(&mut self) -> crate::Result<Option<StopReason>> {
+        if let Some(o) = self.pending_output.take() {
+            return Ok(Some(StopReason::Output(o)));
+        }
+
+        let reason = self.execute();
+        let hit = self.watch_hit.take();
+        let reason = match reason {
+            Ok(event) => event.map(StopReason::from),
+            Err(e) => return Err(self.fault(e)),
+        };
+        if let Some(hit) = hit {
+            if let Some(StopReason::Output(o)) = reason {
+                self.pending_output = Some(o);
+            }
+            return Ok(Some(hit));
+        }
+        Ok(reason)
+    }
+
+    pub fn run(&mut self) -> crate::Result<StopReason> {
+        loop {
+            if self.blocks.is_some()
+                && self.pending_output.is_none()
+                && self.watches.is_empty()
+                && self.trace.is_none()
+                && self.profile.is_none()
+                && self.coverage.is_none()
+                && self.cycles.is_none()
+            {
+                if let Some(reason) = self.run_compiled() {
+                    return Ok(reason);
+                }
+            }
+            if let Some(reason) = self.step()? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Runs until the machine stops for any reason other than output,
+    /// collecting all outputs on the way.
+    pub fn run_continuous(&mut self) -> crate::Result<Vec<Value>> {
+        let mut output = Vec::new();
+        while let StopReason::Output(o) = self.run()? {
+            output.push(o);
+        }
+        Ok(output)
+    }
+
+    /// Undoes the last recorded instruction and returns its record. Returns
+    /// `None` if nothing is being recorded or the recording is exhausted.
+    pub fn step_back(&mut self) -> Option<Record> {
+        let record = self.trace.as_mut()?.records.pop()?;
+        self.disturb(true);
+        for w in record.writes.iter().rev() {
+            if let Some(cell) = self.mem.get_mut(w.addr) {
+                *cell = w.old;
+            }
+        }
+        match record.io {
+            Some(Io::Input(i)) => self.input.push_front(i),
+            Some(Io::Output(_)) => self.pending_output = None,
+            None => (),
+        }
+        self.pc = record.pc;
+        self.rb = record.rb;
+        Some(record)
+    }
+
+    /// Steps back until an instruction that produced output or wrote a
+    /// watched cell is undone, and returns what it reported. Read
+    /// watchpoints are not considered. Also stops, returning `None`, once
+    /// `until` holds for the rewound machine or the recording is exhausted.
+    pub fn reverse_continue(
+        &mut self,
+        mut until: impl FnMut(&Iss) -> bool,
+    ) -> Option<StopReason> {
+        while let Some(record) = self.step_back() {
+            if let Some(Io::Output(o)) = record.io {
+                return Some(StopReason::Output(o));
+            }
+            let watched = record.writes.iter().find(|w| {
+                self.watches
+                    .iter()
+                    .any(|watch| watch.matches(w.addr, Access::Write, w.new))
+            });
+            if let Some(w) = watched {
+                return Some(StopReason::Watchpoint {
+                    addr: w.addr,
+                    old: w.old,
+                    new: w.new,
+                    pc: record.pc,
+                });
+            }
+            if until(self) {
+                break;
+            }
+        }
+        None
+    }
+}
+
+impl From<Event<Value>> for StopReason {
+    fn from(event: Event<Value>) -> Self {
+        match event {
+            Event::Output(o) => StopReason::Output(o),
+            Event::Stop(reason) => reason,
+        }
+    }
+}
+
+impl Core for Iss {
+    type N = Value;
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn meter(&self) -> &Meter {
+        &self.meter
+    }
+
+    fn meter_mut(&mut self) -> &mut Meter {
+        &mut self.meter
+    }
+
+    fn mem(&self) -> &Pages {
+        &self.mem
+    }
+
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn rb(&self) -> Value {
+        self.rb
+    }
+
+    fn read(&mut self, addr: usize) -> crate::Result<Value> {
+        reach(&mut self.mem, &self.config, addr)?;
+        Ok(self.mem.get(addr).unwrap_or_default())
+    }
+
+    fn fetch(&mut self) -> crate::Result<Insn> {
+        let val = self.read(self.pc)?;
+        let insn = self.icache.fetch_insn(val)?;
+        if let Some(trace) = &mut self.trace {
+            trace.records.push(Record {
+                pc: self.pc,
+                rb: self.rb,
+                insn,
+                operands: Vec::new(),
+                writes: Vec::new(),
+                io: None,
+            });
+        }
+        Ok(insn)
+    }
+
+    fn load(&mut self, m: &[Mode], n: usize) -> crate::Result<Value> {
+        let addr = self.addr(m, n)?;
+        let v = self.read(addr)?;
+        // immediate operands are part of the instruction fetch
+        if !self.watches.is_empty() && m[n - 1] != Mode::Immediate {
+            self.check_watches(addr, Access::Read, v, v);
+        }
+        if let Some(record) = self.record() {
+            record.operands.push(v);
+        }
+        Ok(v)
+    }
+
+    fn store(&mut self, m: &[Mode], n: usize, v: Value) -> crate::Result<()> {
+        let addr = self.addr(m, n)?;
+        reach(&mut self.mem, &self.config, addr)?;
+        let old = std::mem::replace(self.mem.cell_mut(addr), v);
+        if let Some(cycles) = &mut self.cycles {
+            cycles.write(addr, old, v);
+        }
+        if !self.watches.is_empty() {
+            self.check_watches(addr, Access::Write, old, v);
+        }
+        if let Some(record) = self.record() {
+            record.writes.push(MemWrite { addr, old, new: v });
+        }
+        Ok(())
+    }
+
+    fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn input(&mut self) -> Value {
+        let i = self.input.pop_front().expect("input checked by has_input");
+        if let Some(record) = self.record() {
+            record.io = Some(Io::Input(i));
+        }
+        i
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.pc += len;
+    }
+
+    /// Takes a jump to `target`, looking for cycles at jumps backwards.
+    fn jump(&mut self, target: usize) -> Option<StopReason> {
+        let backwards = target <= self.pc;
+        self.pc = target;
+        let cycles = self.cycles.as_mut().filter(|_| backwards)?;
+        cycles
+            .jump(target, self.rb, &self.mem)
+            .map(|period| StopReason::InfiniteLoop { pc: target, period })
+    }
+
+    fn rebase(&mut self, rb: Value) {
+        if let Some(profile) = &mut self.profile {
+            profile.rebase(self.rb, rb);
+        }
+        self.rb = rb;
+    }
+
+    // input instructions waiting for input are executed later
+    fn wait(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.records.pop();
+        }
+    }
+
+    fn enter(&mut self, insn: &Insn) {
+        if let Some(cycles) = &mut self.cycles {
+            match insn {
+                Insn::In(_) | Insn::Out(_) => cycles.reset(),
+                _ => cycles.step(),
+            }
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.count(self.pc, insn);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.execute(self.pc);
+        }
+    }
+
+    fn output(&mut self, o: &Value) {
+        if let Some(record) = self.record() {
+            record.io = Some(Io::Output(*o));
+        }
+    }
+
+    fn branch(&mut self, taken: bool) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.branch(self.pc, taken);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fault::Fault;
+    use super::*;
+
+    #[test]
+    fn day02_ex1() {
+        let mut iss = Iss::with_config(
+            vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50],
+            Config::DAY02,
+        );
+        assert!(matches!(iss.run().unwrap(), StopReason::Halted));
+        assert_eq!(*iss.access(0).unwrap(), 3500);
+    }
+
+    #[test]
+    fn day02_rejects_immediate_mode() {
+        let mut iss = Iss::with_config(vec![1002, 4, 3, 4, 33], Config::DAY02);
+        assert!(iss.run().is_err());
+
+        let mut iss = Iss::with_config(vec![1002, 4, 3, 4, 33], Config::DAY05);
+        assert!(matches!(iss.run().unwrap(), StopReason::Halted));
+        assert_eq!(*iss.access(4).unwrap(), 99);
+    }
+
+    #[test]
+    fn strict_memory() {
+        let mut iss = Iss::with_config(vec![1, 0, 0, 10, 99], Config::DAY05);
+        assert!(iss.run().is_err());
+
+        let mut iss = Iss::with_config(vec![1, 0, 0, 10, 99], Config::DAY09);
+        assert!(matches!(iss.run().unwrap(), StopReason::Halted));
+        assert_eq!(*iss.access(10).unwrap(), 2);
+    }
+
+    #[test]
+    fn sparse_memory() {
+        let src = "\
+            add #1, [1000000000000], [1000000000000]
+            out [1000000000000]
+            add #2, [1000000], [1000000]
+            out [1000000]
+            hlt
+";
+        let mem = crate::intcode::asm::assemble(src).unwrap();
+        for &config in &[Config::DAY09, Config::DAY09.compiled()] {
+            let mut iss = Iss::with_config(mem.clone(), config);
+            assert_eq!(iss.run_continuous().unwrap(), vec![1, 2]);
+            assert_eq!(iss.mem().len(), mem.len());
+            assert_eq!(iss.mem().cells(), mem.len() + 2);
+        }
+    }
+
+    #[test]
+    fn memory_errors() {
+        let error = |mem: Vec<Value>, config: Config| {
+            let mut iss = Iss::with_config(mem, config);
+            iss.run().unwrap_err().to_string()
+        };
+        assert_eq!(
+            error(vec![204, -1, 99], Config::DAY09),
+            "fault at pc 0 with rb 0, opcode 204: NegativeAddress"
+        );
+        assert_eq!(
+            error(vec![1105, 1, -3], Config::DAY09),
+            "fault at pc 0 with rb 0, opcode 1105: NegativeAddress"
+        );
+
+        let config = Config {
+            max_memory: 1000,
+            ..Config::DAY09
+        };
+        assert_eq!(
+            error(vec![4, 1000, 99], config),
+            "fault at pc 0 with rb 0, opcode 4: MemoryLimit"
+        );
+        let mut iss = Iss::with_config(vec![4, 999, 99], config);
+        assert_eq!(iss.run().unwrap(), StopReason::Output(0));
+    }
+
+    #[test]
+    fn arith() {
+        // squares 2^32
+        let mem = vec![1002, 7, 4_294_967_296, 7, 4, 7, 99, 4_294_967_296];
+        for &exec in &[Exec::Interpreter, Exec::Compiled] {
+            let config = Config {
+                exec,
+                ..Config::DAY09
+            };
+            let mut iss = Iss::with_config(mem.clone(), config);
+            let err = iss.run().unwrap_err().to_string();
+            assert_eq!(
+                err,
+                "fault at pc 0 with rb 0, opcode 1002: Overflow at pc 0"
+            );
+
+            let config = Config {
+                arith: Arith::Wrapping,
+                ..config
+            };
+            let mut iss = Iss::with_config(mem.clone(), config);
+            assert_eq!(iss.run_continuous().unwrap(), vec![0]);
+        }
+    }
+
+    #[test]
+    fn day05_rejects_relative_mode() {
+        let mut iss = Iss::with_config(vec![109, 1, 99], Config::DAY05);
+        assert!(iss.run().is_err());
+    }
+
+    #[test]
+    fn width() {
+        let mem = vec![1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0];
+
+        let mut iss = Iss::with_config(mem.clone(), Config::DAY09);
+        assert_eq!(iss.run_continuous().unwrap(), vec![1_219_070_632_396_864]);
+
+        let config = Config {
+            width: Width::W32,
+            ..Config::DAY09
+        };
+        let mut iss = Iss::with_config(mem, config);
+        assert!(iss.run().is_err());
+    }
+
+    #[test]
+    fn day09_quine() {
+        let mem = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101,
+            0, 99,
+        ];
+        let mut iss = Iss::new(mem.clone());
+        assert_eq!(iss.run_continuous().unwrap(), mem);
+    }
+
+    #[test]
+    fn watch_writes() {
+        // counts [20] up to 3, then halts
+        let mem = vec![1001, 20, 1, 20, 1008, 20, 3, 21, 1006, 21, 0, 99];
+        let mut iss = Iss::new(mem.clone());
+        iss.watch(20..21, Access::Write);
+        for i in 0..3 {
+            assert_eq!(
+                iss.run().unwrap(),
+                StopReason::Watchpoint {
+                    addr: 20,
+                    old: i,
+                    new: i + 1,
+                    pc: 0
+                }
+            );
+        }
+        assert_eq!(iss.run().unwrap(), StopReason::Halted);
+
+        let mut iss = Iss::new(mem);
+        let id = iss.watch_if(20..22, Access::Write, |v| v == 1);
+        assert_eq!(
+            iss.run().unwrap(),
+            StopReason::Watchpoint {
+                addr: 20,
+                old: 0,
+                new: 1,
+                pc: 0
+            }
+        );
+        assert_eq!(
+            iss.run().unwrap(),
+            StopReason::Watchpoint {
+                addr: 21,
+                old: 0,
+                new: 1,
+                pc: 4
+            }
+        );
+        assert!(iss.unwatch(id));
+        assert!(!iss.unwatch(id));
+        assert_eq!(iss.run().unwrap(), StopReason::Halted);
+    }
+
+    #[test]
+    fn watch_reads() {
+        // outputs the `hlt` at 5, the immediate operands of `jit` do not
+        // count as reads
+        let mem = vec![4, 5, 1105, 0, 0, 99];
+        let mut iss = Iss::new(mem);
+        iss.watch(0..6, Access::Read);
+        assert_eq!(
+            iss.run().unwrap(),
+            StopReason::Watchpoint {
+                addr: 5,
+                old: 99,
+                new: 99,
+                pc: 0
+            }
+        );
+        assert_eq!(iss.pc(), 2);
+        assert_eq!(iss.run().unwrap(), StopReason::Output(99));
+        assert_eq!(iss.run().unwrap(), StopReason::Halted);
+    }
+
+    #[test]
+    fn reverse() {
+        // sums input until it reads a 0, outputting the partial sums
+        let mem = vec![
+            3, 15, 1006, 15, 14, 1, 15, 16, 16, 4, 16, 1105, 1, 0, 99, 0, 0,
+        ];
+        let mut iss = Iss::with_input(mem.clone(), vec![3, 4, 0]);
+        iss.start_trace();
+        assert_eq!(iss.run_continuous().unwrap(), vec![3, 7]);
+
+        assert_eq!(
+            iss.reverse_continue(|_| false),
+            Some(StopReason::Output(7))
+        );
+        assert_eq!(iss.pc(), 9);
+        assert_eq!(iss.pending_input().collect::<Vec<_>>(), vec![&0]);
+
+        iss.watch(16..17, Access::Write);
+        assert_eq!(
+            iss.reverse_continue(|_| false),
+            Some(StopReason::Watchpoint {
+                addr: 16,
+                old: 3,
+                new: 7,
+                pc: 5
+            })
+        );
+        assert_eq!(iss.reverse_continue(|iss| iss.pc() == 0), None);
+        assert_eq!(iss.pending_input().collect::<Vec<_>>(), vec![&4, &0]);
+        assert_eq!(
+            iss.run().unwrap(),
+            StopReason::Watchpoint {
+                addr: 16,
+                old: 3,
+                new: 7,
+                pc: 5
+            }
+        );
+
+        while iss.step_back().is_some() {}
+        assert_eq!(iss.mem().to_vec(), mem);
+        assert_eq!(iss.pc(), 0);
+        assert_eq!(iss.pending_input().count(), 3);
+    }
+
+    #[test]
+    fn fork() {
+        // echoes its input
+        let mut iss = Iss::new(vec![3, 7, 4, 7, 1105, 1, 0, 0]);
+        iss.start_trace();
+        iss.feed_input(1);
+        assert_eq!(iss.run().unwrap(), StopReason::Output(1));
+
+        let mut fork = iss.fork();
+        assert_eq!(fork.mem().shared_with(iss.mem()), 1);
+        assert!(fork.take_trace().is_none());
+        fork.feed_input(2);
+        assert_eq!(fork.run().unwrap(), StopReason::Output(2));
+        assert_eq!(fork.mem().shared_with(iss.mem()), 0);
+        assert_eq!(iss.mem().get(7), Some(1));
+        assert!(iss.take_trace().is_some());
+    }
+
+    // counts to 1000 in 3000 instructions
+    const COUNT: &str = "\
+loop:   add [n], #1, [n]
+        lt [n], #1000, [c]
+        jit [c], #loop
+        out [n]
+        hlt
+n:      .data 0
+c:      .data 0
+";
+
+    #[test]
+    fn budget() {
+        let mem = crate::intcode::asm::assemble(COUNT).unwrap();
+        for &config in &[Config::DAY09, Config::DAY09.compiled()] {
+            let mut iss = Iss::with_config(mem.clone(), config);
+            iss.set_budget(Budget {
+                steps: Some(100),
+                ..Budget::default()
+            });
+            assert_eq!(iss.run().unwrap(), StopReason::BudgetExhausted);
+            assert_eq!(iss.budget().steps, Some(0));
+            assert_eq!((iss.pc(), iss.mem().get(14)), (4, Some(34)));
+            assert_eq!(iss.run().unwrap(), StopReason::BudgetExhausted);
+
+            iss.set_budget(Budget::default());
+            assert_eq!(iss.run().unwrap(), StopReason::Output(1000));
+        }
+
+        let mem = crate::intcode::asm::assemble("loop: jit #1, #loop").unwrap();
+        for &config in &[Config::DAY09, Config::DAY09.compiled()] {
+            let mut iss = Iss::with_config(mem.clone(), config);
+            iss.set_budget(Budget {
+                steps: None,
+                deadline: Some(Instant::now()),
+            });
+            assert_eq!(iss.run().unwrap(), StopReason::BudgetExhausted);
+        }
+    }
+
+    #[test]
+    fn cycles() {
+        let run = |src: &str, input: &[Value]| {
+            let mem = crate::intcode::asm::assemble(src).unwrap();
+            let mut iss = Iss::with_config(mem, Config::DAY09.compiled());
+            input.iter().for_each(|&i| iss.feed_input(i));
+            iss.detect_cycles(true);
+            iss.run().unwrap()
+        };
+        let infinite = |pc, period| StopReason::InfiniteLoop { pc, period };
+
+        assert_eq!(run("loop: jit #1, #loop", &[]), infinite(0, 1));
+        // flips a cell back and forth
+        let flip = "\
+        out #1
+loop:   eq [x], #0, [x]
+        jit #1, #loop
+x:      .data 0
+";
+        assert_eq!(run(COUNT, &[]), StopReason::Output(1000));
+        let echo = "loop: in [9]\n out [9]\n jit #1, #loop";
+        assert_eq!(run(echo, &[7]), StopReason::Output(7));
+
+        let mem = crate::intcode::asm::assemble(flip).unwrap();
+        let mut iss = Iss::new(mem);
+        iss.detect_cycles(true);
+        assert_eq!(iss.run().unwrap(), StopReason::Output(1));
+        assert_eq!(iss.run().unwrap(), infinite(2, 4));
+
+        // waits for a cell to change
+        let wait = "loop: jif [x], #loop\n out [x]\n x: .data 0";
+        assert_eq!(run(wait, &[]), infinite(0, 1));
+        let mem = crate::intcode::asm::assemble(wait).unwrap();
+        let mut iss = Iss::new(mem);
+        iss.detect_cycles(true);
+        iss.set_budget(Budget {
+            steps: Some(1),
+            ..Budget::default()
+        });
+        assert_eq!(iss.run().unwrap(), StopReason::BudgetExhausted);
+        *iss.access(5).unwrap() = 3;
+        iss.set_budget(Budget::default());
+        assert_eq!(iss.run().unwrap(), StopReason::Output(3));
+    }
+
+    #[test]
+    fn faults() {
+        let src = "\
+        add #1, #2, [x]
+        mul [x], #2, [x]
+        .data 30001, 0, 0, 0
+x:      .data 0
+";
+        let mem = crate::intcode::asm::assemble(src).unwrap();
+        for &config in &[Config::DAY09, Config::DAY09.compiled()] {
+            let e = Iss::with_config(mem.clone(), config).run().unwrap_err();
+            let fault = &e.downcast_ref::<crate::Error<Fault>>().unwrap().err;
+            assert_eq!((fault.pc, fault.opcode), (8, Some(30001)));
+            let recent: Vec<usize> =
+                fault.recent.iter().map(|&(pc, _)| pc).collect();
+            assert_eq!(recent, vec![0, 4]);
+            assert_eq!((fault.excerpt.0, fault.excerpt.1.len()), (0, 13));
+
+            assert_eq!(
+                e.to_string(),
+                "fault at pc 8 with rb 0, opcode 30001: \
+                 IllegalMode 3 for parameter 3"
+            );
+            assert_eq!(
+                format!("{:?}", e),
+                "fault at pc 8 with rb 0, opcode 30001\n\
+                 last executed:\n    \
+                 0000: add #1, #2, [12]\n    \
+                 0004: mul [12], #2, [12]\n\
+                 memory:\n    \
+                 0000: 1101 1 2 12 1002 12 2 12\n    \
+                 0008: 30001 0 0 0 6\n\
+                 caused by: IllegalMode 3 for parameter 3"
+            );
+        }
+
+        // modes the instruction set lacks
+        let mut iss = Iss::with_config(vec![1102, 0, 0, 0, 99], Config::DAY02);
+        let e = iss.run().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "fault at pc 0 with rb 0, opcode 1102: \
+             IllegalMode 1 for parameter 1"
+        );
+    }
+}
@@ -0,0 +1,646 @@
+use super::device::{Framed, Framer};
+use super::{Iss, Value};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+/// How often in a row a host has to find its queue empty, without sending
+/// anything in between, to count as blocked on input.
+const IDLE_POLLS: usize = 2;
+
+enum Error {
+    InvalidAddress(Value),
+    /// The NAT sent a packet to an address without a host.
+    UnknownHost(usize),
+    Deadlock,
+    HostFailed(usize, String),
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidAddress(a) => write!(f, "InvalidAddress {}", a),
+            Error::UnknownHost(a) => write!(f, "UnknownHost {}", a),
+            Error::Deadlock => write!(f, "Deadlock"),
+            Error::HostFailed(a, e) => write!(f, "HostFailed {}: {}", a, e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub src: usize,
+    pub dst: usize,
+    pub x: Value,
+    pub y: Value,
+}
+
+/// What the network should do after the NAT saw a packet or idleness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Send(Packet),
+    Stop(Value),
+}
+
+/// The device listening on the NAT address.
+pub trait Nat {
+    fn receive(&mut self, packet: &Packet) -> Action;
+
+    /// Called whenever all hosts are blocked on input with nothing to
+    /// deliver. Continuing at this point is a deadlock.
+    fn idle(&mut self) -> Action;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    /// Every machine runs on its own thread.
+    Threaded,
+    /// Machines take turns on the calling thread, in order.
+    Deterministic,
+}
+
+/// Hosts running the same program, each fed its address on startup, which
+/// exchange `(dst, x, y)` packets. Hosts read `-1` when they have nothing to
+/// receive.
+pub struct Network {
+    mem: Vec<Value>,
+    hosts: usize,
+    nat: usize,
+    scheduler: Scheduler,
+}
+
+impl Network {
+    pub fn new(mem: Vec<Value>, hosts: usize) -> Self {
+        Network {
+            mem,
+            hosts,
+            nat: 255,
+            scheduler: Scheduler::Threaded,
+        }
+    }
+
+    pub fn with_nat_address(mut self, nat: usize) -> Self {
+        self.nat = nat;
+        self
+    }
+
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Runs the network until the NAT stops it.
+    pub fn run(&self, nat: &mut impl Nat) -> crate::Result<Value> {
+        self.run_with(nat, None)
+    }
+
+    /// Like `run`, recording every packet to `capture`. The capture is kept
+    /// when the run fails.
+    pub fn run_captured(
+        &self,
+        nat: &mut impl Nat,
+        capture: &mut Capture,
+    ) -> crate::Result<Value> {
+        capture.nat = self.nat;
+        self.run_with(nat, Some(capture))
+    }
+
+    fn run_with(
+        &self,
+        nat: &mut impl Nat,
+        capture: Option<&mut Capture>,
+    ) -> crate::Result<Value> {
+        match self.scheduler {
+            Scheduler::Threaded => self.run_threaded(nat, capture),
+            Scheduler::Deterministic => self.run_deterministic(nat, capture),
+        }
+    }
+
+    fn packet(&self, src: usize, frame: &[Value]) -> crate::Result<Packet> {
+        let dst = usize::try_from(frame[0])
+            .ok()
+            .filter(|&dst| dst < self.hosts || dst == self.nat)
+            .ok_or_else(|| {
+                crate::Error::boxed(Error::InvalidAddress(frame[0]))
+            })?;
+        Ok(Packet {
+            src,
+            dst,
+            x: frame[1],
+            y: frame[2],
+        })
+    }
+
+    /// Hands `packet` to the NAT or calls `deliver` for it, following up on
+    /// whatever the NAT answers. Returns the value to stop with, if any.
+    fn route(
+        &self,
+        packet: Packet,
+        nat: &mut impl Nat,
+        (tick, capture): (usize, Option<&mut Capture>),
+        mut deliver: impl FnMut(&Packet) -> crate::Result<()>,
+    ) -> crate::Result<Option<Value>> {
+        let mut packet = packet;
+        let mut capture = capture;
+        loop {
+            // packets from hosts are checked as they are framed
+            if packet.dst >= self.hosts && packet.dst != self.nat {
+                return Err(crate::Error::boxed(Error::UnknownHost(
+                    packet.dst,
+                )));
+            }
+            if let Some(capture) = capture.as_deref_mut() {
+                capture.packets.push(Captured { tick, packet });
+            }
+            if packet.dst != self.nat {
+                deliver(&packet)?;
+                return Ok(None);
+            }
+            match nat.receive(&packet) {
+                Action::Continue => return Ok(None),
+                Action::Send(next) => packet = next,
+                Action::Stop(v) => return Ok(Some(v)),
+            }
+        }
+    }
+
+    fn run_deterministic(
+        &self,
+        nat: &mut impl Nat,
+        mut capture: Option<&mut Capture>,
+    ) -> crate::Result<Value> {
+        let mut hosts: Vec<(Iss, Framer<Nic>)> = (0..self.hosts)
+            .map(|address| {
+                let mut iss = Iss::new(self.mem.clone());
+                iss.feed_input(Value::try_from(address)?);
+                Ok((iss, Framer::new(3, Nic::default())))
+            })
+            .collect::<crate::Result<_>>()?;
+
+        // host turns taken
+        let mut tick = 0;
+        loop {
+            for src in 0..hosts.len() {
+                let (iss, nic) = &mut hosts[src];
+                if nic.inner.is_idle() {
+                    continue;
+                }
+                tick += 1;
+                nic.inner.polled = false;
+                iss.drive(nic)?;
+
+                let sent: Vec<_> = nic.inner.sent.drain(..).collect();
+                for frame in sent {
+                    let packet = self.packet(src, &frame)?;
+                    let at = (tick, capture.as_deref_mut());
+                    let stop = self.route(packet, nat, at, |p| {
+                        let queue = &mut hosts[p.dst].1.inner.queue;
+                        queue.push_back(p.x);
+                        queue.push_back(p.y);
+                        Ok(())
+                    })?;
+                    if let Some(v) = stop {
+                        return Ok(v);
+                    }
+                }
+            }
+
+            if hosts.iter().all(|(_, nic)| nic.inner.is_idle()) {
+                let packet = match nat.idle() {
+                    Action::Continue => {
+                        return Err(crate::Error::boxed(Error::Deadlock))
+                    }
+                    Action::Send(packet) => packet,
+                    Action::Stop(v) => return Ok(v),
+                };
+                let at = (tick, capture.as_deref_mut());
+                let stop = self.route(packet, nat, at, |p| {
+                    let queue = &mut hosts[p.dst].1.inner.queue;
+                    queue.push_back(p.x);
+                    queue.push_back(p.y);
+                    Ok(())
+                })?;
+                if let Some(v) = stop {
+                    return Ok(v);
+                }
+            }
+        }
+    }
+
+    fn run_threaded(
+        &self,
+        nat: &mut impl Nat,
+        capture: Option<&mut Capture>,
+    ) -> crate::Result<Value> {
+        let (events_tx, events) = mpsc::channel();
+        let mut links = Vec::new();
+        let mut handles = Vec::new();
+        for address in 0..self.hosts {
+            let (tx, rx) = mpsc::channel();
+            links.push(tx);
+            let mem = self.mem.clone();
+            let events = events_tx.clone();
+            handles.push(thread::spawn(move || {
+                let mut nic = Framer::new(
+                    3,
+                    ThreadNic {
+                        address,
+                        rx,
+                        events: events.clone(),
+                        pending: VecDeque::new(),
+                        empty_polls: 0,
+                        received: 0,
+                    },
+                );
+                let mut iss = Iss::new(mem);
+                let res = Value::try_from(address)
+                    .map_err(|e| e.into())
+                    .and_then(|a| {
+                        iss.feed_input(a);
+                        iss.drive(&mut nic)
+                    });
+                if let Err(e) = res {
+                    let _ = events.send(Event::Failed(address, e.to_string()));
+                }
+            }));
+        }
+        drop(events_tx);
+
+        let res = self.route_threaded(nat, capture, &events, &links);
+
+        // hosts notice the closed links and stop
+        drop(links);
+        for handle in handles {
+            let _ = handle.join();
+        }
+        res
+    }
+
+    fn route_threaded(
+        &self,
+        nat: &mut impl Nat,
+        mut capture: Option<&mut Capture>,
+        events: &Receiver<Event>,
+        links: &[Sender<(Value, Value)>],
+    ) -> crate::Result<Value> {
+        let mut delivered = vec![0; self.hosts];
+        let mut idle = vec![false; self.hosts];
+        let deliver =
+            |delivered: &mut [usize], idle: &mut [bool], p: &Packet| {
+                // a host that already quit will not be waited for
+                let _ = links[p.dst].send((p.x, p.y));
+                delivered[p.dst] += 1;
+                idle[p.dst] = false;
+                Ok(())
+            };
+
+        // events handled
+        let mut tick = 0;
+        loop {
+            let event = events.recv()?;
+            tick += 1;
+            let stop = match event {
+                Event::Packet(src, frame) => {
+                    let packet = self.packet(src, &frame)?;
+                    let at = (tick, capture.as_deref_mut());
+                    self.route(packet, nat, at, |p| {
+                        deliver(&mut delivered, &mut idle, p)
+                    })?
+                }
+                Event::Idle(address, received) => {
+                    // packets may have been delivered since it reported
+                    if received == delivered[address] {
+                        idle[address] = true;
+                    }
+                    None
+                }
+                Event::Failed(address, e) => {
+                    return Err(crate::Error::boxed(Error::HostFailed(
+                        address, e,
+                    )))
+                }
+            };
+            if let Some(v) = stop {
+                return Ok(v);
+            }
+
+            if idle.iter().all(|&idle| idle) {
+                let packet = match nat.idle() {
+                    Action::Continue => {
+                        return Err(crate::Error::boxed(Error::Deadlock))
+                    }
+                    Action::Send(packet) => packet,
+                    Action::Stop(v) => return Ok(v),
+                };
+                let at = (tick, capture.as_deref_mut());
+                let stop = self.route(packet, nat, at, |p| {
+                    deliver(&mut delivered, &mut idle, p)
+                })?;
+                if let Some(v) = stop {
+                    return Ok(v);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Captured {
+    /// The scheduler's clock: host turns taken by the deterministic
+    /// scheduler, events handled by the threaded one.
+    pub tick: usize,
+    pub packet: Packet,
+}
+
+/// The packets routed through a network, in order.
+#[derive(Debug, Default)]
+pub struct Capture {
+    pub packets: Vec<Captured>,
+    nat: usize,
+}
+
+impl Capture {
+    /// Writes the packets sent from or to `address`, or all of them, as a
+    /// log with one `tick src dst x y` line per packet.
+    pub fn write(
+        &self,
+        address: Option<usize>,
+        w: &mut impl Write,
+    ) -> crate::Result<()> {
+        writeln!(w, "# tick src dst x y")?;
+        for Captured { tick, packet: p } in &self.packets {
+            if address.is_none_or(|a| p.src == a || p.dst == a) {
+                writeln!(w, "{} {} {} {} {}", tick, p.src, p.dst, p.x, p.y)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes how many packets each address sent and received, followed by
+    /// the packets the NAT sent, in order.
+    pub fn summary(&self, w: &mut impl Write) -> crate::Result<()> {
+        let mut counts: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+        for Captured { packet, .. } in &self.packets {
+            counts.entry(packet.src).or_default().0 += 1;
+            counts.entry(packet.dst).or_default().1 += 1;
+        }
+        writeln!(w, "# address sent received")?;
+        for (address, (sent, received)) in counts {
+            writeln!(w, "{} {} {}", address, sent, received)?;
+        }
+
+        writeln!(w, "# nat deliveries: tick dst x y")?;
+        for Captured { tick, packet: p } in &self.packets {
+            if p.src == self.nat {
+                writeln!(w, "{} {} {} {}", tick, p.dst, p.x, p.y)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A network interface for the deterministic scheduler. A turn ends when
+/// the host polls its empty queue for the second time.
+#[derive(Default)]
+struct Nic {
+    queue: VecDeque<Value>,
+    polled: bool,
+    empty_polls: usize,
+    sent: Vec<Vec<Value>>,
+}
+
+impl Nic {
+    fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.empty_polls >= IDLE_POLLS
+    }
+}
+
+impl Framed for Nic {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        if let Some(v) = self.queue.pop_front() {
+            self.empty_polls = 0;
+            Ok(Some(v))
+        } else if self.polled {
+            Ok(None)
+        } else {
+            self.polled = true;
+            self.empty_polls += 1;
+            Ok(Some(-1))
+        }
+    }
+
+    fn frame(&mut self, frame: &[Value]) -> crate::Result<()> {
+        self.empty_polls = 0;
+        self.sent.push(frame.to_vec());
+        Ok(())
+    }
+}
+
+enum Event {
+    Packet(usize, Vec<Value>),
+    /// A host blocked on input after receiving the given number of packets.
+    Idle(usize, usize),
+    Failed(usize, String),
+}
+
+/// A network interface for a host on its own thread. Once idle, it reports
+/// so and blocks until the next packet arrives. The machine stops when its
+/// link closes.
+struct ThreadNic {
+    address: usize,
+    rx: Receiver<(Value, Value)>,
+    events: Sender<Event>,
+    pending: VecDeque<Value>,
+    empty_polls: usize,
+    received: usize,
+}
+
+impl Framed for ThreadNic {
+    fn input(&mut self) -> crate::Result<Option<Value>> {
+        if let Some(v) = self.pending.pop_front() {
+            return Ok(Some(v));
+        }
+
+        let packet = if self.empty_polls >= IDLE_POLLS {
+            match self.rx.try_recv() {
+                Ok(packet) => Some(packet),
+                Err(TryRecvError::Empty) => {
+                    // the router is gone if this fails, and the link with it
+                    let idle = Event::Idle(self.address, self.received);
+                    let _ = self.events.send(idle);
+                    match self.rx.recv() {
+                        Ok(packet) => Some(packet),
+                        Err(_) => return Ok(None),
+                    }
+                }
+                Err(TryRecvError::Disconnected) => return Ok(None),
+            }
+        } else {
+            match self.rx.try_recv() {
+                Ok(packet) => Some(packet),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(None),
+            }
+        };
+
+        if let Some((x, y)) = packet {
+            self.received += 1;
+            self.empty_polls = 0;
+            self.pending.push_back(y);
+            Ok(Some(x))
+        } else {
+            self.empty_polls += 1;
+            Ok(Some(-1))
+        }
+    }
+
+    fn frame(&mut self, frame: &[Value]) -> crate::Result<()> {
+        self.empty_polls = 0;
+        let _ = self
+            .events
+            .send(Event::Packet(self.address, frame.to_vec()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::parse;
+
+    // reads forever, never sends
+    const SILENT: &str = "\
+loop:   in [v]
+        jit #1, #loop
+v:      .data 0
+";
+
+    /// Stops with the first packet it receives.
+    struct First;
+
+    impl Nat for First {
+        fn receive(&mut self, packet: &Packet) -> Action {
+            Action::Stop(packet.y)
+        }
+
+        fn idle(&mut self) -> Action {
+            Action::Continue
+        }
+    }
+
+    /// Counts how often the network went idle.
+    struct Idle(Value);
+
+    impl Nat for Idle {
+        fn receive(&mut self, _: &Packet) -> Action {
+            Action::Continue
+        }
+
+        fn idle(&mut self) -> Action {
+            self.0 += 1;
+            if self.0 == 3 {
+                Action::Stop(self.0)
+            } else {
+                Action::Send(Packet {
+                    src: 255,
+                    dst: 1,
+                    x: 1,
+                    y: 2,
+                })
+            }
+        }
+    }
+
+    #[test]
+    fn schedulers() {
+        let mem = parse(include_str!("../../input/day23")).unwrap();
+        for &scheduler in &[Scheduler::Deterministic, Scheduler::Threaded] {
+            let network =
+                Network::new(mem.clone(), 50).with_scheduler(scheduler);
+            assert_eq!(network.run(&mut First).unwrap(), 19_530);
+            assert_eq!(network.run(&mut Idle(0)).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn capture() {
+        let mem = parse(include_str!("../../input/day23")).unwrap();
+        let network =
+            Network::new(mem, 50).with_scheduler(Scheduler::Deterministic);
+        let mut capture = Capture::default();
+        assert_eq!(
+            network.run_captured(&mut First, &mut capture).unwrap(),
+            19_530
+        );
+        let last = capture.packets.last().unwrap();
+        assert_eq!((last.packet.dst, last.packet.y), (255, 19_530));
+        assert!(capture.packets.windows(2).all(|w| w[0].tick <= w[1].tick));
+
+        let mut out = Vec::new();
+        capture.write(Some(255), &mut out).unwrap();
+        let log = String::from_utf8(out).unwrap();
+        let expected = format!(
+            "# tick src dst x y\n{} {} 255 {} 19530\n",
+            last.tick, last.packet.src, last.packet.x
+        );
+        assert_eq!(log, expected);
+    }
+
+    #[test]
+    fn summary() {
+        let network = Network::new(assemble(SILENT).unwrap(), 3)
+            .with_scheduler(Scheduler::Deterministic);
+        let mut capture = Capture::default();
+        network.run_captured(&mut Idle(0), &mut capture).unwrap();
+        let mut out = Vec::new();
+        capture.summary(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# address sent received\n\
+             1 0 2\n\
+             255 2 0\n\
+             # nat deliveries: tick dst x y\n\
+             6 1 1 2\n\
+             8 1 1 2\n"
+        );
+    }
+
+    /// Sends to a host that does not exist once the network is idle.
+    struct Stray;
+
+    impl Nat for Stray {
+        fn receive(&mut self, _: &Packet) -> Action {
+            Action::Continue
+        }
+
+        fn idle(&mut self) -> Action {
+            Action::Send(Packet {
+                src: 255,
+                dst: 99,
+                x: 1,
+                y: 2,
+            })
+        }
+    }
+
+    #[test]
+    fn idle_hosts() {
+        let mem = assemble(SILENT).unwrap();
+        for &scheduler in &[Scheduler::Deterministic, Scheduler::Threaded] {
+            let network =
+                Network::new(mem.clone(), 3).with_scheduler(scheduler);
+            assert!(network.run(&mut First).is_err());
+            assert_eq!(network.run(&mut Idle(0)).unwrap(), 3);
+            assert_eq!(
+                network.run(&mut Stray).unwrap_err().to_string(),
+                "UnknownHost 99"
+            );
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Paged memory, shared between copies until written.
+
+use super::Value;
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+const PAGE_BITS: usize = 8;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+/// The dense part grows up to this size, or to twice its size, on demand.
+const MIN_DENSE: usize = 1 << 16;
+
+type Page<T> = [T; PAGE_SIZE];
+
+/// Memory made of fixed size pages, followed by a sparse map for cells far
+/// beyond them. Cloning only copies the page table, a page is copied once it
+/// is written through one of the clones. Cells are machine words unless
+/// stated otherwise.
+#[derive(Clone, Default)]
+pub struct Pages<T = Value> {
+    pages: Vec<Rc<Page<T>>>,
+    len: usize,
+    far: Rc<BTreeMap<usize, T>>,
+}
+
+impl<T: Clone + Default + PartialEq> Pages<T> {
+    /// The size of the dense part.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The number of cells held, dense or not.
+    pub fn cells(&self) -> usize {
+        self.len + self.far.len()
+    }
+
+    #[inline]
+    pub fn get(&self, addr: usize) -> Option<T> {
+        if addr < self.len {
+            Some(self.pages[addr >> PAGE_BITS][addr % PAGE_SIZE].clone())
+        } else {
+            self.far.get(&addr).cloned()
+        }
+    }
+
+    /// The cell at `addr`, after copying its page if it is shared.
+    #[inline]
+    pub fn get_mut(&mut self, addr: usize) -> Option<&mut T> {
+        if addr < self.len {
+            let page = Rc::make_mut(&mut self.pages[addr >> PAGE_BITS]);
+            Some(&mut page[addr % PAGE_SIZE])
+        } else if self.far.contains_key(&addr) {
+            Rc::make_mut(&mut self.far).get_mut(&addr)
+        } else {
+            None
+        }
+    }
+
+    /// Like `get_mut`, but creates the cell if needed.
+    pub fn cell_mut(&mut self, addr: usize) -> &mut T {
+        self.touch(addr);
+        if addr < self.len {
+            let page = Rc::make_mut(&mut self.pages[addr >> PAGE_BITS]);
+            &mut page[addr % PAGE_SIZE]
+        } else {
+            Rc::make_mut(&mut self.far).entry(addr).or_default()
+        }
+    }
+
+    fn is_near(&self, addr: usize) -> bool {
+        addr < (2 * self.len).max(MIN_DENSE)
+    }
+
+    /// The number of cells creating `addr` adds.
+    pub fn cost(&self, addr: usize) -> usize {
+        if addr < self.len || self.far.contains_key(&addr) {
+            0
+        } else if self.is_near(addr) {
+            addr + 1 - self.len
+        } else {
+            1
+        }
+    }
+
+    /// Grows the dense part to include `addr` if it is near enough. Far
+    /// cells are only created by writes.
+    pub fn touch(&mut self, addr: usize) {
+        if addr >= self.len && self.is_near(addr) {
+            self.grow(addr + 1);
+        }
+    }
+
+    /// Extends the dense part to `len` cells, which read as zero unless they
+    /// were far cells before.
+    pub fn grow(&mut self, len: usize) {
+        if len > self.len {
+            let pages = (len + PAGE_SIZE - 1) >> PAGE_BITS;
+            self.pages.resize_with(pages, || {
+                Rc::new(std::array::from_fn(|_| T::default()))
+            });
+            self.len = len;
+
+            let near: Vec<(usize, T)> = self
+                .far
+                .range(..len)
+                .map(|(&a, v)| (a, v.clone()))
+                .collect();
+            for (addr, v) in near {
+                Rc::make_mut(&mut self.far).remove(&addr);
+                *self.cell_mut(addr) = v;
+            }
+        }
+    }
+
+    /// Whether the cells starting at `addr` hold `values`.
+    pub fn matches(&self, addr: usize, values: &[T]) -> bool {
+        if addr + values.len() > self.len {
+            return values
+                .iter()
+                .enumerate()
+                .all(|(n, v)| self.get(addr + n).as_ref() == Some(v));
+        }
+        let (mut addr, mut values) = (addr, values);
+        while !values.is_empty() {
+            let offset = addr % PAGE_SIZE;
+            let n = values.len().min(PAGE_SIZE - offset);
+            let page = &self.pages[addr >> PAGE_BITS];
+            if page[offset..offset + n] != values[..n] {
+                return false;
+            }
+            addr += n;
+            values = &values[n..];
+        }
+        true
+    }
+
+    /// The cells of the dense part.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.pages
+            .iter()
+            .flat_map(|p| p.iter())
+            .take(self.len)
+            .cloned()
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+
+    /// The cells beyond the dense part, by address.
+    pub fn far(&self) -> impl Iterator<Item = (usize, T)> + '_ {
+        self.far.iter().map(|(&addr, v)| (addr, v.clone()))
+    }
+
+    /// Whether both hold the same cells and have dense parts of the same
+    /// size. Pages still shared are not compared.
+    pub fn same(&self, other: &Pages<T>) -> bool {
+        self.len == other.len
+            && (Rc::ptr_eq(&self.far, &other.far) || self.far == other.far)
+            && self
+                .pages
+                .iter()
+                .zip(&other.pages)
+                .all(|(a, b)| Rc::ptr_eq(a, b) || a[..] == b[..])
+    }
+
+    /// Copies every page still shared with a clone.
+    pub fn unshare(&mut self) {
+        for page in &mut self.pages {
+            Rc::make_mut(page);
+        }
+    }
+
+    /// The number of pages shared with `other`.
+    #[cfg(test)]
+    pub fn shared_with(&self, other: &Pages<T>) -> usize {
+        self.pages
+            .iter()
+            .zip(&other.pages)
+            .filter(|(a, b)| Rc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+impl<T: Clone + Default + PartialEq> From<Vec<T>> for Pages<T> {
+    fn from(mem: Vec<T>) -> Self {
+        let mut pages = Pages::default();
+        pages.grow(mem.len());
+        for (page, chunk) in pages.pages.iter_mut().zip(mem.chunks(PAGE_SIZE)) {
+            Rc::make_mut(page)[..chunk.len()].clone_from_slice(chunk);
+        }
+        pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_on_write() {
+        let mem: Vec<Value> = (0..1000).collect();
+        let mut a = Pages::from(mem.clone());
+        assert_eq!(a.len(), 1000);
+        assert_eq!(a.to_vec(), mem);
+        assert_eq!(a.get(999), Some(999));
+        assert_eq!(a.get(1000), None);
+
+        let mut b = a.clone();
+        assert_eq!(a.shared_with(&b), 4);
+        assert!(a.same(&b));
+        *b.get_mut(300).unwrap() = -1;
+        assert_eq!(a.shared_with(&b), 3);
+        assert_eq!((a.get(300), b.get(300)), (Some(300), Some(-1)));
+        assert_ne!(a.to_vec(), b.to_vec());
+        assert!(!a.same(&b));
+        assert!(b.matches(299, &[299, -1, 301]));
+        assert!(!b.matches(999, &[999, 0]));
+
+        a.grow(1100);
+        assert_eq!((a.len(), a.get(1050)), (1100, Some(0)));
+        a.unshare();
+        assert_eq!(a.shared_with(&b), 0);
+    }
+
+    #[test]
+    fn sparse() {
+        let mut mem = Pages::from(vec![1, 2, 3]);
+        assert_eq!(mem.cost(10), 8);
+        assert_eq!(mem.cost(1 << 40), 1);
+        *mem.cell_mut(1 << 40) = 7;
+        *mem.cell_mut(100_000) = 8;
+        mem.touch(1 << 50);
+        assert_eq!((mem.len(), mem.cells()), (3, 5));
+        assert_eq!(mem.get(1 << 40), Some(7));
+        assert_eq!(mem.get(1 << 50), None);
+        assert_eq!(mem.cost(1 << 40), 0);
+
+        // growing the dense part takes in far cells
+        mem.touch(60_000);
+        mem.touch(100_001);
+        assert_eq!(mem.len(), 100_002);
+        assert_eq!(mem.get(100_000), Some(8));
+        assert_eq!(mem.far().collect::<Vec<_>>(), vec![(1 << 40, 7)]);
+        assert!(mem.matches(99_999, &[0, 8, 0]));
+    }
+}
@@ -0,0 +1,427 @@
+use super::device::{Channel, Queue};
+use super::network::Scheduler;
+use super::{Config, Iss, StopReason, Value};
+
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+enum Error {
+    NoSuchStage(usize),
+    /// Threaded stages wired in a ring could wait on each other forever.
+    Cyclic,
+    StageFailed(usize, String),
+    WorkerPanicked,
+    NothingToSearch,
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoSuchStage(s) => write!(f, "NoSuchStage {}", s),
+            Error::Cyclic => write!(f, "Cyclic"),
+            Error::StageFailed(s, e) => write!(f, "StageFailed {}: {}", s, e),
+            Error::WorkerPanicked => write!(f, "WorkerPanicked"),
+            Error::NothingToSearch => write!(f, "NothingToSearch"),
+        }
+    }
+}
+
+struct Stage {
+    inputs: Vec<Value>,
+    to: Vec<usize>,
+}
+
+/// Machines running the same program, with the output of each stage fed to
+/// the input of the stages it is connected to. Any wiring goes: chains,
+/// rings, fan-out and fan-in, where inputs from several stages are
+/// interleaved in the order they were produced.
+pub struct Pipeline {
+    mem: Vec<Value>,
+    config: Config,
+    stages: Vec<Stage>,
+    scheduler: Scheduler,
+}
+
+impl Pipeline {
+    pub fn new(mem: Vec<Value>, config: Config) -> Self {
+        Pipeline {
+            mem,
+            config,
+            stages: Vec::new(),
+            scheduler: Scheduler::Deterministic,
+        }
+    }
+
+    /// Threaded stages are linked by channels. Only acyclic wiring runs this
+    /// way, and inputs from several stages arrive in no particular order.
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// One stage per phase setting, each feeding the next. The first stage
+    /// receives `signal` after its phase setting. With `feedback` the last
+    /// stage feeds the first one.
+    pub fn chain(
+        mem: Vec<Value>,
+        config: Config,
+        phases: &[Value],
+        signal: Value,
+        feedback: bool,
+    ) -> crate::Result<Self> {
+        let mut pipeline = Pipeline::new(mem, config);
+        for (idx, &phase) in phases.iter().enumerate() {
+            let stage = if idx == 0 {
+                pipeline.stage(&[phase, signal])
+            } else {
+                pipeline.stage(&[phase])
+            };
+            if stage > 0 {
+                pipeline.connect(stage - 1, stage)?;
+            }
+        }
+        if feedback && !phases.is_empty() {
+            pipeline.connect(phases.len() - 1, 0)?;
+        }
+        Ok(pipeline)
+    }
+
+    /// Adds a stage starting out with `inputs` queued, and returns its index.
+    pub fn stage(&mut self, inputs: &[Value]) -> usize {
+        self.stages.push(Stage {
+            inputs: inputs.to_vec(),
+            to: Vec::new(),
+        });
+        self.stages.len() - 1
+    }
+
+    /// Feeds the output of stage `from` to stage `to`.
+    pub fn connect(&mut self, from: usize, to: usize) -> crate::Result<()> {
+        if let Some(bad) = [from, to].iter().find(|&&s| s >= self.stages.len())
+        {
+            return Err(crate::Error::boxed(Error::NoSuchStage(*bad)));
+        }
+        self.stages[from].to.push(to);
+        Ok(())
+    }
+
+    /// Runs the stages until all of them halted or wait for input that is
+    /// never coming. Returns the output of each stage. Output sent to a
+    /// halted stage is dropped.
+    pub fn run(&self) -> crate::Result<Vec<Vec<Value>>> {
+        match self.scheduler {
+            Scheduler::Deterministic => self.run_deterministic(),
+            Scheduler::Threaded => self.run_threaded(),
+        }
+    }
+
+    /// Gives the stages turns in order.
+    fn run_deterministic(&self) -> crate::Result<Vec<Vec<Value>>> {
+        let mut vms: Vec<(Iss, Queue)> = self
+            .stages
+            .iter()
+            .map(|stage| {
+                let iss = Iss::with_config(self.mem.clone(), self.config);
+                (iss, Queue::new(stage.inputs.iter().copied()))
+            })
+            .collect();
+        let mut stopped = vec![None; self.stages.len()];
+        let mut outputs = vec![Vec::new(); self.stages.len()];
+
+        loop {
+            let mut ran = false;
+            for (idx, stage) in self.stages.iter().enumerate() {
+                let (iss, queue) = &mut vms[idx];
+                match stopped[idx] {
+                    Some(StopReason::OutOfInput) if !queue.input.is_empty() => {
+                    }
+                    Some(_) => continue,
+                    None => (),
+                }
+                ran = true;
+                stopped[idx] = Some(iss.drive(queue)?);
+                let sent: Vec<Value> = queue.output.drain(..).collect();
+
+                for &to in &stage.to {
+                    if stopped[to] != Some(StopReason::Halted) {
+                        vms[to].1.input.extend(&sent);
+                    }
+                }
+                outputs[idx].extend(sent);
+            }
+            if !ran {
+                return Ok(outputs);
+            }
+        }
+    }
+
+    /// Runs every stage on its own thread, with another thread passing its
+    /// output on. A stage stops once it halted or all stages feeding it
+    /// stopped.
+    fn run_threaded(&self) -> crate::Result<Vec<Vec<Value>>> {
+        if self.is_cyclic() {
+            return Err(crate::Error::boxed(Error::Cyclic));
+        }
+        let (inputs, rxs): (Vec<Sender<Value>>, Vec<Receiver<Value>>) =
+            self.stages.iter().map(|_| mpsc::channel()).unzip();
+        for (stage, tx) in self.stages.iter().zip(&inputs) {
+            for &i in &stage.inputs {
+                // the receiver is still around
+                let _ = tx.send(i);
+            }
+        }
+
+        let mut handles = Vec::new();
+        for (stage, rx) in self.stages.iter().zip(rxs) {
+            let (tx, sent) = mpsc::channel();
+            let (mem, config) = (self.mem.clone(), self.config);
+            let run = thread::spawn(move || {
+                Iss::with_config(mem, config)
+                    .drive(&mut Channel { rx, tx })
+                    .map_err(|e| e.to_string())
+            });
+            let to: Vec<Sender<Value>> =
+                stage.to.iter().map(|&to| inputs[to].clone()).collect();
+            let forward = thread::spawn(move || {
+                let mut output = Vec::new();
+                for value in sent {
+                    for tx in &to {
+                        // a stage that stopped takes no more input
+                        let _ = tx.send(value);
+                    }
+                    output.push(value);
+                }
+                output
+            });
+            handles.push((run, forward));
+        }
+        drop(inputs);
+
+        let panicked = || crate::Error::boxed(Error::WorkerPanicked);
+        let mut outputs = Vec::new();
+        for (idx, (run, forward)) in handles.into_iter().enumerate() {
+            if let Err(e) = run.join().map_err(|_| panicked())? {
+                return Err(crate::Error::boxed(Error::StageFailed(idx, e)));
+            }
+            outputs.push(forward.join().map_err(|_| panicked())?);
+        }
+        Ok(outputs)
+    }
+
+    /// Whether some stage feeds itself, directly or through others.
+    fn is_cyclic(&self) -> bool {
+        let mut feeding = vec![0; self.stages.len()];
+        for stage in &self.stages {
+            for &to in &stage.to {
+                feeding[to] += 1;
+            }
+        }
+        // take away stages nothing feeds until only rings are left
+        let mut unfed: Vec<usize> =
+            (0..feeding.len()).filter(|&s| feeding[s] == 0).collect();
+        let mut left = self.stages.len();
+        while let Some(stage) = unfed.pop() {
+            left -= 1;
+            for &to in &self.stages[stage].to {
+                feeding[to] -= 1;
+                if feeding[to] == 0 {
+                    unfed.push(to);
+                }
+            }
+        }
+        left > 0
+    }
+}
+
+fn permutations(items: &[Value]) -> Vec<Vec<Value>> {
+    if items.is_empty() {
+        return vec![Vec::new()];
+    }
+    let mut res = Vec::new();
+    for idx in 0..items.len() {
+        let mut rest = items.to_vec();
+        let first = rest.remove(idx);
+        for mut perm in permutations(&rest) {
+            perm.insert(0, first);
+            res.push(perm);
+        }
+    }
+    res
+}
+
+/// Scores every ordering of `phases` and returns the best one along with
+/// its score. With `parallel`, the orderings are split across threads.
+pub fn search<F>(
+    phases: &[Value],
+    parallel: bool,
+    score: F,
+) -> crate::Result<(Vec<Value>, Value)>
+where
+    F: Fn(&[Value]) -> crate::Result<Value> + Sync,
+{
+    let perms = permutations(phases);
+    let best = |perms: &[Vec<Value>]| -> Result<_, String> {
+        let mut best: Option<(Vec<Value>, Value)> = None;
+        for perm in perms {
+            let s = score(perm).map_err(|e| e.to_string())?;
+            if best.as_ref().is_none_or(|(_, b)| s > *b) {
+                best = Some((perm.clone(), s));
+            }
+        }
+        Ok(best)
+    };
+
+    let results = if parallel {
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = perms.len().div_ceil(workers).max(1);
+        thread::scope(|scope| {
+            let handles: Vec<_> = perms
+                .chunks(chunk)
+                .map(|perms| scope.spawn(move || best(perms)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().map_err(|_| {
+                        crate::Error::boxed(Error::WorkerPanicked).into()
+                    })
+                })
+                .collect::<crate::Result<Vec<_>>>()
+        })?
+    } else {
+        vec![best(&perms)]
+    };
+
+    let mut best: Option<(Vec<Value>, Value)> = None;
+    for res in results {
+        if let Some((perm, s)) = res? {
+            if best.as_ref().is_none_or(|(_, b)| s > *b) {
+                best = Some((perm, s));
+            }
+        }
+    }
+    best.ok_or_else(|| crate::Error::boxed(Error::NothingToSearch).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // day07 part 2, example 1
+    const RING: &[Value] = &[
+        3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27,
+        1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+    ];
+
+    // adds its phase setting to every input
+    const ADD: &str = "\
+        in [p]
+loop:   in [v]
+        add [v], [p], [v]
+        out [v]
+        jit #1, #loop
+p:      .data 0
+v:      .data 0
+";
+
+    fn signal(pipeline: &Pipeline) -> Value {
+        *pipeline.run().unwrap().last().unwrap().last().unwrap()
+    }
+
+    #[test]
+    fn chain_and_ring() {
+        let mem = assemble(ADD).unwrap();
+        let chain =
+            Pipeline::chain(mem, Config::DAY05, &[1, 2, 3], 10, false).unwrap();
+        assert_eq!(chain.run().unwrap(), vec![vec![11], vec![13], vec![16]]);
+
+        let ring = Pipeline::chain(
+            RING.to_vec(),
+            Config::DAY05,
+            &[9, 8, 7, 6, 5],
+            0,
+            true,
+        )
+        .unwrap();
+        assert_eq!(signal(&ring), 139_629_729);
+    }
+
+    #[test]
+    fn dag() {
+        // 0 feeds 1 and 2, which both feed 3
+        let mut dag = Pipeline::new(assemble(ADD).unwrap(), Config::DAY05);
+        let src = dag.stage(&[1, 0, 10]);
+        let left = dag.stage(&[100]);
+        let right = dag.stage(&[1000]);
+        let sink = dag.stage(&[0]);
+        for &(from, to) in
+            &[(src, left), (src, right), (left, sink), (right, sink)]
+        {
+            dag.connect(from, to).unwrap();
+        }
+        let outputs = dag.run().unwrap();
+        assert_eq!(outputs[src], vec![1, 11]);
+        assert_eq!(outputs[sink], vec![101, 111, 1001, 1011]);
+        assert!(dag.connect(sink, 4).is_err());
+
+        let mut threaded =
+            dag.with_scheduler(Scheduler::Threaded).run().unwrap();
+        // fan-in arrives in no particular order
+        threaded[sink].sort_unstable();
+        assert_eq!(threaded, outputs);
+    }
+
+    #[test]
+    fn threaded() {
+        let mem = assemble(ADD).unwrap();
+        let chain = Pipeline::chain(mem, Config::DAY05, &[1, 2, 3], 10, false)
+            .unwrap()
+            .with_scheduler(Scheduler::Threaded);
+        assert_eq!(chain.run().unwrap(), vec![vec![11], vec![13], vec![16]]);
+
+        let ring = Pipeline::chain(
+            RING.to_vec(),
+            Config::DAY05,
+            &[9, 8, 7, 6, 5],
+            0,
+            true,
+        )
+        .unwrap()
+        .with_scheduler(Scheduler::Threaded);
+        assert_eq!(ring.run().unwrap_err().to_string(), "Cyclic");
+
+        // the second stage overflows
+        let mut failing = Pipeline::new(assemble(ADD).unwrap(), Config::DAY09)
+            .with_scheduler(Scheduler::Threaded);
+        let first = failing.stage(&[1, 2]);
+        let second = failing.stage(&[Value::MAX]);
+        failing.connect(first, second).unwrap();
+        assert!(failing
+            .run()
+            .unwrap_err()
+            .to_string()
+            .starts_with("StageFailed 1: "));
+    }
+
+    #[test]
+    fn search_phases() {
+        let best = |parallel| {
+            search(&[5, 6, 7, 8, 9], parallel, |phases| {
+                let mem = RING.to_vec();
+                Ok(signal(&Pipeline::chain(
+                    mem,
+                    Config::DAY05,
+                    phases,
+                    0,
+                    true,
+                )?))
+            })
+            .unwrap()
+        };
+        assert_eq!(best(false), (vec![9, 8, 7, 6, 5], 139_629_729));
+        assert_eq!(best(true), best(false));
+        assert!(search(&[1], false, |_| Err("no".into())).is_err());
+    }
+}
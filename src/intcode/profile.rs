@@ -0,0 +1,270 @@
+//! Counting where a program spends its time.
+//!
+//! A basic block is entered whenever execution reaches one of its leaders
+//! in the control flow graph of the initial memory image, or after a jump.
+//!
+//! Functions are told apart by the relative base: raising it opens a frame
+//! named after the basic block that raised it, lowering it again closes all
+//! frames opened at or above the new base.
+
+use super::cfg::Cfg;
+use super::disasm::{self, Item};
+use super::{Insn, Mode, Value};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+/// Number of hot spots listed in a report.
+const HOT_SPOTS: usize = 10;
+
+/// Execution counts of a single run.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// The memory image when profiling started.
+    pub mem: Vec<Value>,
+    pcs: HashMap<usize, u64>,
+    opcodes: BTreeMap<&'static str, u64>,
+    /// Operands executed, per addressing mode.
+    modes: [u64; 3],
+    blocks: HashMap<usize, u64>,
+    /// The first instructions of the basic blocks known statically.
+    leaders: HashSet<usize>,
+    /// Whether the last instruction was a jump, which may have gone where
+    /// no leader is known.
+    at_block: bool,
+    block: usize,
+    /// Open frames with the relative base they were opened at.
+    frames: Vec<(Value, usize)>,
+    folded: HashMap<Vec<usize>, u64>,
+    /// Instructions executed since the frames last changed.
+    pending: u64,
+}
+
+fn mode_idx(mode: Mode) -> usize {
+    match mode {
+        Mode::Position => 0,
+        Mode::Immediate => 1,
+        Mode::Relative => 2,
+    }
+}
+
+fn percent(n: u64, total: u64) -> f64 {
+    100.0 * n as f64 / total.max(1) as f64
+}
+
+impl Profile {
+    pub(super) fn new(mem: Vec<Value>) -> Self {
+        let leaders = Cfg::build(&mem).blocks.keys().copied().collect();
+        Profile {
+            mem,
+            pcs: HashMap::new(),
+            opcodes: BTreeMap::new(),
+            modes: [0; 3],
+            blocks: HashMap::new(),
+            leaders,
+            at_block: true,
+            block: 0,
+            frames: Vec::new(),
+            folded: HashMap::new(),
+            pending: 0,
+        }
+    }
+
+    /// Counts `insn` as executed at `pc`.
+    pub(super) fn count(&mut self, pc: usize, insn: &Insn) {
+        *self.pcs.entry(pc).or_default() += 1;
+        *self.opcodes.entry(insn.mnemonic()).or_default() += 1;
+        for &mode in insn.modes() {
+            self.modes[mode_idx(mode)] += 1;
+        }
+        if self.at_block || self.leaders.contains(&pc) {
+            *self.blocks.entry(pc).or_default() += 1;
+            self.block = pc;
+        }
+        self.at_block = matches!(insn, Insn::Jit(_) | Insn::Jif(_));
+        self.pending += 1;
+    }
+
+    /// Notes a change of the relative base from `old` to `new`.
+    pub(super) fn rebase(&mut self, old: Value, new: Value) {
+        let stack = self.stack();
+        *self.folded.entry(stack).or_default() += self.pending;
+        self.pending = 0;
+        if new > old {
+            self.frames.push((old, self.block));
+        } else {
+            while self.frames.last().is_some_and(|&(rb, _)| rb >= new) {
+                self.frames.pop();
+            }
+        }
+    }
+
+    fn stack(&self) -> Vec<usize> {
+        self.frames.iter().map(|&(_, block)| block).collect()
+    }
+
+    /// The number of instructions executed.
+    pub fn total(&self) -> u64 {
+        self.pcs.values().sum()
+    }
+
+    /// How often the instruction at `pc` was executed.
+    pub fn at(&self, pc: usize) -> u64 {
+        self.pcs.get(&pc).copied().unwrap_or_default()
+    }
+
+    /// How often the basic block starting at `pc` was entered.
+    pub fn entries(&self, pc: usize) -> u64 {
+        self.blocks.get(&pc).copied().unwrap_or_default()
+    }
+
+    /// Writes the instruction counts per call stack in the folded format
+    /// read by flamegraph tools, one `main;frame;frame count` line each.
+    pub fn write_folded(&self, w: &mut impl Write) -> crate::Result<()> {
+        let mut folded: BTreeMap<Vec<usize>, u64> =
+            self.folded.iter().map(|(s, &n)| (s.clone(), n)).collect();
+        *folded.entry(self.stack()).or_default() += self.pending;
+        for (stack, n) in folded {
+            if n == 0 {
+                continue;
+            }
+            write!(w, "main")?;
+            for frame in stack {
+                write!(w, ";{:04}", frame)?;
+            }
+            writeln!(w, " {}", n)?;
+        }
+        Ok(())
+    }
+
+    /// Writes totals per opcode and addressing mode, the hottest
+    /// instructions and the disassembly annotated with execution and block
+    /// entry counts.
+    pub fn write_report(&self, w: &mut impl Write) -> crate::Result<()> {
+        let total = self.total();
+        writeln!(w, "{} instructions", total)?;
+
+        writeln!(w, "\nopcodes:")?;
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1));
+        for (mnemonic, &n) in opcodes {
+            writeln!(
+                w,
+                "  {:<4} {:>12} {:>6.2}%",
+                mnemonic,
+                n,
+                percent(n, total)
+            )?;
+        }
+
+        writeln!(w, "\noperands:")?;
+        let operands: u64 = self.modes.iter().sum();
+        for (name, &n) in ["position", "immediate", "relative"]
+            .iter()
+            .zip(&self.modes)
+        {
+            writeln!(
+                w,
+                "  {:<9} {:>12} {:>6.2}%",
+                name,
+                n,
+                percent(n, operands)
+            )?;
+        }
+
+        let mut roots: Vec<usize> = self.pcs.keys().copied().collect();
+        roots.sort_unstable();
+        let listing = disasm::disassemble_from(&self.mem[..], &roots);
+
+        writeln!(w, "\nhot spots:")?;
+        let mut hot: Vec<&Item> = listing
+            .items
+            .iter()
+            .filter(|item| self.at(item.addr()) > 0)
+            .collect();
+        hot.sort_by_key(|item| std::cmp::Reverse(self.at(item.addr())));
+        for item in hot.into_iter().take(HOT_SPOTS) {
+            let n = self.at(item.addr());
+            writeln!(w, "  {:>12} {:>6.2}%  {}", n, percent(n, total), item)?;
+        }
+
+        writeln!(w, "\nlisting:")?;
+        for item in &listing.items {
+            let (n, entries) = match item {
+                Item::Insn { addr, .. } => {
+                    (self.at(*addr), self.entries(*addr))
+                }
+                Item::Data { .. } => (0, 0),
+            };
+            let show = |n: u64| {
+                if n > 0 {
+                    n.to_string()
+                } else {
+                    String::new()
+                }
+            };
+            writeln!(w, "  {:>12} {:>8}  {}", show(n), show(entries), item)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Config, Iss};
+    use crate::intcode::asm::assemble;
+
+    // calls a function counting down from 3
+    const CALL: &str = "\
+        rbo #stack
+        add #back, #0, rb+0
+        jit #1, #func
+back:   hlt
+func:   rbo #1
+loop:   add [n], #-1, [n]
+        jit [n], #loop
+        rbo #-1
+        jit #1, rb+0
+n:      .data 3
+stack:  .data 0
+";
+
+    fn profile() -> super::Profile {
+        let mut iss = Iss::with_config(assemble(CALL).unwrap(), Config::DAY09);
+        iss.start_profile();
+        iss.run_continuous().unwrap();
+        iss.take_profile().unwrap()
+    }
+
+    #[test]
+    fn counts() {
+        let profile = profile();
+        assert_eq!(profile.total(), 13);
+        assert_eq!((profile.at(12), profile.at(19)), (3, 1));
+        let entries: Vec<u64> = [0, 9, 10, 12, 16, 19]
+            .iter()
+            .map(|&pc| profile.entries(pc))
+            .collect();
+        assert_eq!(entries, vec![1, 1, 1, 3, 0, 1]);
+
+        let mut report = Vec::new();
+        profile.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("13 instructions\n"));
+        assert!(report.contains("  add             4  30.77%\n"));
+        assert!(report.contains("  relative             2   8.00%\n"));
+        assert!(report
+            .contains("             3        3  0012: add [24], #-1, [24]\n"));
+        assert!(report.contains("                         0024: .data 3, 0\n"));
+    }
+
+    #[test]
+    fn folded() {
+        let mut folded = Vec::new();
+        profile().write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "main 1\nmain;0000 5\nmain;0000;0010 7\n"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Saving and restoring machine state.
+//!
+//! A snapshot is a text file of `key value` lines in a fixed order:
+//!
+//! ```text
+//! intcode-snapshot 3
+//! isa day09
+//! memory growing
+//! width 64
+//! arith checked
+//! max-memory 16777216
+//! pc 4
+//! rb 0
+//! input 1,2
+//! output 7
+//! mem 3,9,4,9,1105,1,0,99,99,7
+//! far 1000000000=5
+//! ```
+//!
+//! The first line names the format version. `isa` is one of `day02`, `day05`
+//! and `day09`, `memory` one of `strict` and `growing`, and `width` is `32`
+//! or `64`. `arith` is one of `checked` and `wrapping`. `mem` holds the
+//! dense part of memory and `far` the cells beyond it as `address=value`
+//! pairs. `input` lists the queued input and `output` holds the output not
+//! yet returned by the machine, if any. Both may be empty. Watchpoints,
+//! recordings and the execution tier are not part of a snapshot; restored
+//! machines are interpreted.
+
+use super::{Arith, Config, Exec, Isa, Iss, Memory, Value, Width};
+
+use std::fmt;
+use std::io::{BufRead, Write};
+
+/// Bumped whenever the format changes.
+const VERSION: &str = "3";
+
+const KEYS: &[&str] = &[
+    "intcode-snapshot",
+    "isa",
+    "memory",
+    "width",
+    "arith",
+    "max-memory",
+    "pc",
+    "rb",
+    "input",
+    "output",
+    "mem",
+    "far",
+];
+
+enum Error {
+    Malformed { line: usize },
+    UnsupportedVersion(String),
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed { line } => write!(f, "Malformed line {}", line),
+            Error::UnsupportedVersion(v) => {
+                write!(f, "UnsupportedVersion {}", v)
+            }
+        }
+    }
+}
+
+fn malformed(line: usize) -> Box<crate::Error<Error>> {
+    crate::Error::boxed(Error::Malformed { line })
+}
+
+fn list(values: impl Iterator<Item = Value>) -> String {
+    let values: Vec<String> = values.map(|v| v.to_string()).collect();
+    values.join(",")
+}
+
+fn parse_list(s: &str) -> Result<Vec<Value>, std::num::ParseIntError> {
+    if s.is_empty() {
+        Ok(Vec::new())
+    } else {
+        s.split(',').map(|v| v.parse()).collect()
+    }
+}
+
+impl Iss {
+    /// Writes the machine state in the snapshot format.
+    pub fn save(&self, mut w: impl Write) -> crate::Result<()> {
+        let config = self.config;
+        writeln!(w, "intcode-snapshot {}", VERSION)?;
+        let isa = match config.isa {
+            Isa::Day02 => "day02",
+            Isa::Day05 => "day05",
+            Isa::Day09 => "day09",
+        };
+        writeln!(w, "isa {}", isa)?;
+        let memory = match config.memory {
+            Memory::Strict => "strict",
+            Memory::Growing => "growing",
+        };
+        writeln!(w, "memory {}", memory)?;
+        let width = match config.width {
+            Width::W32 => 32,
+            Width::W64 => 64,
+        };
+        writeln!(w, "width {}", width)?;
+        let arith = match config.arith {
+            Arith::Checked => "checked",
+            Arith::Wrapping => "wrapping",
+        };
+        writeln!(w, "arith {}", arith)?;
+        writeln!(w, "max-memory {}", config.max_memory)?;
+        writeln!(w, "pc {}", self.pc)?;
+        writeln!(w, "rb {}", self.rb)?;
+        writeln!(w, "input {}", list(self.input.iter().copied()))?;
+        writeln!(w, "output {}", list(self.pending_output.into_iter()))?;
+        writeln!(w, "mem {}", list(self.mem.iter()))?;
+        let far: Vec<String> = self
+            .mem
+            .far()
+            .map(|(addr, v)| format!("{}={}", addr, v))
+            .collect();
+        writeln!(w, "far {}", far.join(","))?;
+        Ok(())
+    }
+
+    /// Restores a machine written by `save`.
+    pub fn load(r: impl BufRead) -> crate::Result<Self> {
+        let mut values = Vec::new();
+        let mut lines = r.lines();
+        for (idx, &key) in KEYS.iter().enumerate() {
+            let line = idx + 1;
+            let text = lines.next().ok_or_else(|| malformed(line))??;
+            let value = match text.split_once(' ') {
+                Some((k, v)) if k == key => v.to_string(),
+                None if text == key => String::new(),
+                _ => return Err(malformed(line)),
+            };
+            if idx == 0 && value != VERSION {
+                return Err(crate::Error::boxed(Error::UnsupportedVersion(
+                    value,
+                )));
+            }
+            values.push(value);
+        }
+
+        let isa = match values[1].as_str() {
+            "day02" => Isa::Day02,
+            "day05" => Isa::Day05,
+            "day09" => Isa::Day09,
+            _ => return Err(malformed(2)),
+        };
+        let memory = match values[2].as_str() {
+            "strict" => Memory::Strict,
+            "growing" => Memory::Growing,
+            _ => return Err(malformed(3)),
+        };
+        let width = match values[3].as_str() {
+            "32" => Width::W32,
+            "64" => Width::W64,
+            _ => return Err(malformed(4)),
+        };
+        let arith = match values[4].as_str() {
+            "checked" => Arith::Checked,
+            "wrapping" => Arith::Wrapping,
+            _ => return Err(malformed(5)),
+        };
+        let max_memory = values[5].parse().map_err(|_| malformed(6))?;
+        let output = parse_list(&values[9]).map_err(|_| malformed(10))?;
+        if output.len() > 1 {
+            return Err(malformed(10));
+        }
+
+        let config = Config {
+            memory,
+            isa,
+            width,
+            exec: Exec::Interpreter,
+            arith,
+            max_memory,
+        };
+        let mut iss = Iss::with_config(
+            parse_list(&values[10]).map_err(|_| malformed(11))?,
+            config,
+        );
+        if !values[11].is_empty() {
+            for cell in values[11].split(',') {
+                let (addr, v) =
+                    cell.split_once('=').ok_or_else(|| malformed(12))?;
+                let addr: usize = addr.parse().map_err(|_| malformed(12))?;
+                if addr < iss.mem.len() {
+                    return Err(malformed(12));
+                }
+                *iss.mem.cell_mut(addr) =
+                    v.parse().map_err(|_| malformed(12))?;
+            }
+        }
+        iss.pc = values[6].parse().map_err(|_| malformed(7))?;
+        iss.rb = values[7].parse().map_err(|_| malformed(8))?;
+        iss.input = parse_list(&values[8]).map_err(|_| malformed(9))?.into();
+        iss.pending_output = output.first().copied();
+        Ok(iss)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::StopReason;
+    use super::*;
+
+    // echoes its input
+    const ECHO: &[Value] = &[3, 9, 4, 9, 1105, 1, 0, 99, 99, 0];
+
+    #[test]
+    fn round_trip() {
+        let mut iss = Iss::with_config(ECHO.to_vec(), Config::DAY05);
+        iss.feed_input(1);
+        iss.feed_input(2);
+        iss.watch(9..10, super::super::Access::Read);
+        assert!(matches!(iss.run().unwrap(), StopReason::Watchpoint { .. }));
+
+        let mut file = Vec::new();
+        iss.save(&mut file).unwrap();
+        assert_eq!(
+            String::from_utf8(file.clone()).unwrap(),
+            "intcode-snapshot 3\n\
+             isa day05\n\
+             memory strict\n\
+             width 32\n\
+             arith checked\n\
+             max-memory 16777216\n\
+             pc 4\n\
+             rb 0\n\
+             input 2\n\
+             output 1\n\
+             mem 3,9,4,9,1105,1,0,99,99,1\n\
+             far \n"
+        );
+
+        let mut loaded = Iss::load(file.as_slice()).unwrap();
+        assert_eq!(loaded.run().unwrap(), StopReason::Output(1));
+        assert_eq!(loaded.run().unwrap(), StopReason::Output(2));
+        assert_eq!(loaded.run().unwrap(), StopReason::OutOfInput);
+    }
+
+    #[test]
+    fn errors() {
+        let mut file = Vec::new();
+        Iss::new(ECHO.to_vec()).save(&mut file).unwrap();
+        let file = String::from_utf8(file).unwrap();
+        assert!(Iss::load(file.as_bytes()).is_ok());
+
+        for (from, to) in &[
+            ("snapshot 3", "snapshot 2"),
+            ("checked", "saturating"),
+            ("day09", "day10"),
+            ("width 64", "width 16"),
+            ("rb 0", "rb x"),
+            ("output ", "output 1,2"),
+            ("pc 0\nrb 0", "rb 0\npc 0"),
+            ("far ", "far 3=1"),
+            ("far ", "far 99"),
+        ] {
+            let broken = file.replace(from, to);
+            assert!(Iss::load(broken.as_bytes()).is_err(), "{}", to);
+        }
+        assert!(Iss::load(&b""[..]).is_err());
+    }
+
+    #[test]
+    fn far_cells() {
+        let mut iss = Iss::with_config(ECHO.to_vec(), Config::DAY09);
+        *iss.access(1 << 40).unwrap() = 5;
+        let mut file = Vec::new();
+        iss.save(&mut file).unwrap();
+        let file = String::from_utf8(file).unwrap();
+        assert!(file.ends_with("far 1099511627776=5\n"));
+
+        let mut loaded = Iss::load(file.as_bytes()).unwrap();
+        assert_eq!(*loaded.access(1 << 40).unwrap(), 5);
+        assert_eq!(loaded.mem().len(), ECHO.len());
+    }
+}
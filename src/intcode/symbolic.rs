@@ -0,0 +1,861 @@
+//! Running programs on unknown values.
+//!
+//! Chosen memory cells and every input are variables. Arithmetic on them
+//! builds expressions instead of values, and a jump on an unknown condition
+//! forks the path, each side remembering which way the condition went.
+//! Addresses, jump targets and relative base offsets must stay known: a read
+//! from an unknown address yields an opaque value, anything else computed
+//! from a variable ends the path.
+
+use super::{address, Arith, Config, Error, Insn, Memory, Mode, Value, Width};
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// The number of paths explored before giving up on the rest.
+const MAX_PATHS: usize = 1 << 10;
+/// The number of instructions a single path may execute.
+const MAX_STEPS: usize = 1 << 16;
+
+pub type Term = Rc<Expr>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Expr {
+    Const(Value),
+    Var(String),
+    /// The cell at an address computed from variables, whose value is not
+    /// tracked.
+    Load(Term),
+    Add(Term, Term),
+    Mul(Term, Term),
+    Lt(Term, Term),
+    Eq(Term, Term),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add,
+    Mul,
+    Lt,
+    Eq,
+}
+
+impl Expr {
+    pub fn constant(&self) -> Option<Value> {
+        match self {
+            Expr::Const(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The value of the expression with variables taken from `env`, if it
+    /// has one.
+    pub fn eval(&self, env: &dyn Fn(&str) -> Option<Value>) -> Option<Value> {
+        match self {
+            Expr::Const(v) => Some(*v),
+            Expr::Var(name) => env(name),
+            Expr::Load(_) => None,
+            Expr::Add(a, b) => a.eval(env)?.checked_add(b.eval(env)?),
+            Expr::Mul(a, b) => a.eval(env)?.checked_mul(b.eval(env)?),
+            Expr::Lt(a, b) => Some((a.eval(env)? < b.eval(env)?).into()),
+            Expr::Eq(a, b) => Some((a.eval(env)? == b.eval(env)?).into()),
+        }
+    }
+
+    /// The expression as a sum of variables times constants, if it is one.
+    pub fn linear(&self) -> Option<Linear> {
+        match self {
+            Expr::Const(v) => Some(Linear {
+                coeffs: BTreeMap::new(),
+                constant: *v,
+            }),
+            Expr::Var(name) => Some(Linear {
+                coeffs: vec![(name.clone(), 1)].into_iter().collect(),
+                constant: 0,
+            }),
+            Expr::Add(a, b) => {
+                let (mut a, b) = (a.linear()?, b.linear()?);
+                for (name, c) in b.coeffs {
+                    let sum = a.coeffs.get(&name).copied().unwrap_or_default();
+                    let sum = sum.checked_add(c)?;
+                    if sum == 0 {
+                        a.coeffs.remove(&name);
+                    } else {
+                        a.coeffs.insert(name, sum);
+                    }
+                }
+                a.constant = a.constant.checked_add(b.constant)?;
+                Some(a)
+            }
+            Expr::Mul(a, b) => {
+                let (a, b) = (a.linear()?, b.linear()?);
+                let (scale, mut linear) = if a.coeffs.is_empty() {
+                    (a.constant, b)
+                } else if b.coeffs.is_empty() {
+                    (b.constant, a)
+                } else {
+                    return None;
+                };
+                if scale == 0 {
+                    linear.coeffs.clear();
+                }
+                for c in linear.coeffs.values_mut() {
+                    *c = c.checked_mul(scale)?;
+                }
+                linear.constant = linear.constant.checked_mul(scale)?;
+                Some(linear)
+            }
+            Expr::Load(_) | Expr::Lt(..) | Expr::Eq(..) => None,
+        }
+    }
+
+    /// Binding strength, for leaving out parentheses.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Lt(..) | Expr::Eq(..) => 0,
+            Expr::Add(..) => 1,
+            Expr::Mul(..) => 2,
+            Expr::Const(_) | Expr::Var(_) | Expr::Load(_) => 3,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (a, op, b) = match self {
+            Expr::Const(v) => return write!(f, "{}", v),
+            Expr::Var(name) => return write!(f, "{}", name),
+            Expr::Load(addr) => return write!(f, "[{}]", addr),
+            Expr::Add(a, b) => (a, "+", b),
+            Expr::Mul(a, b) => (a, "*", b),
+            Expr::Lt(a, b) => (a, "<", b),
+            Expr::Eq(a, b) => (a, "==", b),
+        };
+        let operand = |f: &mut fmt::Formatter<'_>, e: &Expr| {
+            // comparisons do not chain
+            if e.precedence() < self.precedence()
+                || (e.precedence() == 0 && self.precedence() == 0)
+            {
+                write!(f, "({})", e)
+            } else {
+                write!(f, "{}", e)
+            }
+        };
+        operand(f, a)?;
+        write!(f, " {} ", op)?;
+        operand(f, b)
+    }
+}
+
+/// The terms of a sum with their coefficients, and its constant. Terms
+/// other than sums and multiples are kept whole.
+fn sum(e: &Term) -> Option<(Vec<(Term, Value)>, Value)> {
+    match &**e {
+        Expr::Const(v) => Some((Vec::new(), *v)),
+        Expr::Add(a, b) => {
+            let ((mut terms, x), (more, y)) = (sum(a)?, sum(b)?);
+            for (term, c) in more {
+                match terms.iter_mut().find(|(t, _)| *t == term) {
+                    Some((_, sum)) => *sum = sum.checked_add(c)?,
+                    None => terms.push((term, c)),
+                }
+            }
+            terms.retain(|&(_, c)| c != 0);
+            Some((terms, x.checked_add(y)?))
+        }
+        Expr::Mul(a, b) if a.constant().is_some() || b.constant().is_some() => {
+            let (scale, e) = match a.constant() {
+                Some(scale) => (scale, b),
+                None => (b.constant()?, a),
+            };
+            let (mut terms, v) = sum(e)?;
+            for (_, c) in &mut terms {
+                *c = c.checked_mul(scale)?;
+            }
+            terms.retain(|&(_, c)| c != 0);
+            Some((terms, v.checked_mul(scale)?))
+        }
+        _ => Some((vec![(e.clone(), 1)], 0)),
+    }
+}
+
+/// Rewrites sums and multiples as a flat sum of distinct terms times their
+/// coefficients, so that terms cancel out. Left as is if that overflows.
+fn normalize(e: Term) -> Term {
+    let (terms, v) = match sum(&e) {
+        Some(sum) => sum,
+        None => return e,
+    };
+    let mut terms = terms.into_iter().map(|(term, c)| {
+        if c == 1 {
+            term
+        } else {
+            Rc::new(Expr::Mul(Rc::new(Expr::Const(c)), term))
+        }
+    });
+    let first = match terms.next() {
+        Some(first) => first,
+        None => return Rc::new(Expr::Const(v)),
+    };
+    let e = terms.fold(first, |acc, term| Rc::new(Expr::Add(acc, term)));
+    if v == 0 {
+        e
+    } else {
+        Rc::new(Expr::Add(e, Rc::new(Expr::Const(v))))
+    }
+}
+
+/// The range, inclusive, that a comparison of a term plus or minus a
+/// constant with a constant confines the term to.
+fn bound(cond: &Expr, nonzero: bool) -> Option<(Term, Value, Value)> {
+    let (a, b, lt) = match cond {
+        Expr::Lt(a, b) => (a, b, true),
+        Expr::Eq(a, b) => (a, b, false),
+        _ => return None,
+    };
+    // compares k * term + v with zero
+    let minus_b = Rc::new(Expr::Mul(Rc::new(Expr::Const(-1)), b.clone()));
+    let (terms, v) = sum(&Rc::new(Expr::Add(a.clone(), minus_b)))?;
+    let (term, k) = match terms.as_slice() {
+        [(term, k)] if k.abs() == 1 => (term.clone(), *k),
+        _ => return None,
+    };
+    let (lo, hi) = match (lt, nonzero, k) {
+        (true, true, 1) => (Value::MIN, v.checked_neg()?.checked_sub(1)?),
+        (true, true, _) => (v.checked_add(1)?, Value::MAX),
+        (true, false, 1) => (v.checked_neg()?, Value::MAX),
+        (true, false, _) => (Value::MIN, v),
+        (false, true, _) => {
+            let v = v.checked_mul(-k)?;
+            (v, v)
+        }
+        (false, false, _) => return None,
+    };
+    Some((term, lo, hi))
+}
+
+/// Whether `cond` is non-zero on a path with `conditions`, if that follows
+/// from them. Unknown values are assumed not to overflow.
+fn decide(conditions: &[(Term, bool)], cond: &Term) -> Option<bool> {
+    if let Some(c) = cond.constant() {
+        return Some(c != 0);
+    }
+    if let Some(&(_, nonzero)) = conditions.iter().find(|(c, _)| c == cond) {
+        return Some(nonzero);
+    }
+    let feasible = |nonzero| {
+        let (term, mut lo, mut hi) = match bound(cond, nonzero) {
+            Some(bound) => bound,
+            None => return true,
+        };
+        for (c, nonzero) in conditions {
+            if let Some((t, l, h)) = bound(c, *nonzero) {
+                if t == term {
+                    lo = lo.max(l);
+                    hi = hi.min(h);
+                }
+            }
+        }
+        lo <= hi
+    };
+    match (feasible(true), feasible(false)) {
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// A sum of variables times non-zero coefficients plus a constant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linear {
+    pub coeffs: BTreeMap<String, Value>,
+    pub constant: Value,
+}
+
+impl Linear {
+    /// All values of the variables in `bounds` within their ranges for
+    /// which the sum is `target`, in the order of `bounds`. The last
+    /// variable the sum depends on is solved for, the others are
+    /// enumerated. Variables it does not depend on take the start of their
+    /// range. There are none if the sum depends on variables not bounded.
+    pub fn solutions(
+        &self,
+        target: Value,
+        bounds: &[(&str, Range<Value>)],
+    ) -> Vec<Vec<Value>> {
+        let mut solutions = Vec::new();
+        if self
+            .coeffs
+            .keys()
+            .any(|name| bounds.iter().all(|(b, _)| b != name))
+            || bounds.iter().any(|(_, range)| range.is_empty())
+        {
+            return solutions;
+        }
+        let solved = bounds
+            .iter()
+            .rposition(|(name, _)| self.coeffs.contains_key(*name));
+        let mut values: Vec<Value> =
+            bounds.iter().map(|(_, range)| range.start).collect();
+        self.enumerate(target, bounds, solved, 0, &mut values, &mut solutions);
+        solutions
+    }
+
+    fn enumerate(
+        &self,
+        target: Value,
+        bounds: &[(&str, Range<Value>)],
+        solved: Option<usize>,
+        idx: usize,
+        values: &mut Vec<Value>,
+        solutions: &mut Vec<Vec<Value>>,
+    ) {
+        if idx == bounds.len() {
+            let mut rest = target.checked_sub(self.constant);
+            for (i, (name, _)) in bounds.iter().enumerate() {
+                let c = self.coeffs.get(*name).copied().unwrap_or_default();
+                if Some(i) != solved {
+                    let term = c.checked_mul(values[i]);
+                    rest = rest.zip(term).and_then(|(r, t)| r.checked_sub(t));
+                }
+            }
+            let rest = match rest {
+                Some(rest) => rest,
+                None => return,
+            };
+            match solved {
+                None if rest == 0 => solutions.push(values.clone()),
+                None => (),
+                Some(i) => {
+                    let c = self.coeffs[bounds[i].0];
+                    // dividing the smallest value by -1 overflows
+                    let v = match (rest.checked_rem(c), rest.checked_div(c)) {
+                        (Some(0), Some(v)) => v,
+                        _ => return,
+                    };
+                    if bounds[i].1.contains(&v) {
+                        values[i] = v;
+                        solutions.push(values.clone());
+                    }
+                }
+            }
+            return;
+        }
+        let (name, range) = &bounds[idx];
+        if Some(idx) == solved || !self.coeffs.contains_key(*name) {
+            return self.enumerate(
+                target,
+                bounds,
+                solved,
+                idx + 1,
+                values,
+                solutions,
+            );
+        }
+        for v in range.clone() {
+            values[idx] = v;
+            self.enumerate(target, bounds, solved, idx + 1, values, solutions);
+        }
+    }
+}
+
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        let mut term = |f: &mut fmt::Formatter<'_>, c: Value, name| {
+            let sign = match (first, c < 0) {
+                (true, false) => "",
+                (true, true) => "-",
+                (false, false) => " + ",
+                (false, true) => " - ",
+            };
+            first = false;
+            match (c.unsigned_abs(), name) {
+                (1, Some(name)) => write!(f, "{}{}", sign, name),
+                (c, Some(name)) => write!(f, "{}{} * {}", sign, c, name),
+                (c, None) => write!(f, "{}{}", sign, c),
+            }
+        };
+        for (name, &c) in &self.coeffs {
+            term(f, c, Some(name))?;
+        }
+        if self.constant != 0 || self.coeffs.is_empty() {
+            term(f, self.constant, None)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a path ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum End {
+    Halted,
+    /// The program wants more input than there are variables for.
+    OutOfInput,
+    /// The instruction, an address it writes, its jump target or its
+    /// relative base offset at `pc` depends on a variable.
+    Unknown {
+        pc: usize,
+    },
+    /// The instruction at `pc` failed as it would on a machine.
+    Failed {
+        pc: usize,
+        error: String,
+    },
+    /// Too many instructions or paths.
+    Limit,
+}
+
+/// One way through the program.
+#[derive(Debug, Clone)]
+pub struct Path {
+    /// The conditions of the jumps on unknown values along the path, each
+    /// with whether it was non-zero.
+    pub conditions: Vec<(Term, bool)>,
+    pub output: Vec<Term>,
+    /// The memory when the path ended.
+    pub mem: Vec<Term>,
+    pub end: End,
+}
+
+impl Path {
+    /// Whether the program takes this path with variables taken from
+    /// `env`, if that can be told.
+    pub fn admits(&self, env: &dyn Fn(&str) -> Option<Value>) -> Option<bool> {
+        for (cond, nonzero) in &self.conditions {
+            if (cond.eval(env)? != 0) != *nonzero {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |e: &Expr| match e.linear() {
+            Some(linear) => linear.to_string(),
+            None => e.to_string(),
+        };
+        writeln!(f, "{:?}", self.end)?;
+        for (cond, nonzero) in &self.conditions {
+            let not = if *nonzero { "" } else { "not " };
+            writeln!(f, "  if {}{}", not, cond)?;
+        }
+        for o in &self.output {
+            writeln!(f, "  out {}", show(o))?;
+        }
+        for (addr, cell) in self.mem.iter().enumerate() {
+            if cell.constant().is_none() {
+                writeln!(f, "  [{}] = {}", addr, show(cell))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    mem: Vec<Term>,
+    pc: usize,
+    rb: Value,
+    inputs: usize,
+    output: Vec<Term>,
+    conditions: Vec<(Term, bool)>,
+    steps: usize,
+}
+
+enum Addr {
+    Known(usize),
+    Unknown(Term),
+}
+
+/// A program with some of its cells and inputs unknown.
+pub struct Machine {
+    mem: Vec<Term>,
+    config: Config,
+    inputs: usize,
+}
+
+impl Machine {
+    pub fn new(mem: Vec<Value>, config: Config) -> Self {
+        Machine {
+            mem: mem.into_iter().map(|v| Rc::new(Expr::Const(v))).collect(),
+            config,
+            inputs: 0,
+        }
+    }
+
+    /// Makes the cell at `addr` a variable called `name`.
+    pub fn symbolize(&mut self, addr: usize, name: &str) -> crate::Result<()> {
+        let cell = self
+            .mem
+            .get_mut(addr)
+            .ok_or_else(|| crate::Error::boxed(Error::AddressOutOfRange))?;
+        *cell = Rc::new(Expr::Var(name.to_string()));
+        Ok(())
+    }
+
+    /// Lets the program read `n` inputs, called `in0`, `in1` and so on.
+    pub fn inputs(&mut self, n: usize) {
+        self.inputs = n;
+    }
+
+    /// Follows every path through the program, depth first with jumps not
+    /// taken first.
+    pub fn explore(&self) -> Vec<Path> {
+        let mut todo = vec![State {
+            mem: self.mem.clone(),
+            pc: 0,
+            rb: 0,
+            inputs: 0,
+            output: Vec::new(),
+            conditions: Vec::new(),
+            steps: 0,
+        }];
+        let mut paths = Vec::new();
+        while let Some(mut state) = todo.pop() {
+            let end = loop {
+                if paths.len() + todo.len() >= MAX_PATHS {
+                    break End::Limit;
+                }
+                match self.step(&mut state) {
+                    Ok(None) => (),
+                    Ok(Some(fork)) => todo.push(fork),
+                    Err(end) => break end,
+                }
+            };
+            paths.push(Path {
+                conditions: state.conditions,
+                output: state.output,
+                mem: state.mem,
+                end,
+            });
+        }
+        paths
+    }
+
+    fn fail(pc: usize, error: Box<dyn std::error::Error>) -> End {
+        End::Failed {
+            pc,
+            error: error.to_string(),
+        }
+    }
+
+    fn read(&self, s: &State, addr: usize) -> Result<Term, End> {
+        if let Some(cell) = s.mem.get(addr) {
+            return Ok(cell.clone());
+        }
+        let error = match self.config.memory {
+            Memory::Strict => Error::AddressOutOfRange,
+            Memory::Growing if addr < self.config.max_memory => {
+                return Ok(Rc::new(Expr::Const(0)))
+            }
+            Memory::Growing => Error::MemoryLimit,
+        };
+        Err(Machine::fail(s.pc, crate::Error::boxed(error)))
+    }
+
+    fn write(&self, s: &mut State, addr: usize, v: Term) -> Result<(), End> {
+        let fits = |v| i32::try_from(v).is_ok();
+        if self.config.width == Width::W32 && !v.constant().is_none_or(fits) {
+            let error = crate::Error::boxed(Error::ValueOutOfRange);
+            return Err(Machine::fail(s.pc, error));
+        }
+        if addr >= s.mem.len() {
+            self.read(s, addr)?;
+            s.mem.resize(addr + 1, Rc::new(Expr::Const(0)));
+        }
+        s.mem[addr] = v;
+        Ok(())
+    }
+
+    fn known(s: &State, v: &Expr) -> Result<Value, End> {
+        v.constant().ok_or(End::Unknown { pc: s.pc })
+    }
+
+    fn addr(&self, s: &State, m: &[Mode], n: usize) -> Result<Addr, End> {
+        let at = s.pc + n;
+        let v = match m[n - 1] {
+            Mode::Immediate => return Ok(Addr::Known(at)),
+            Mode::Position => self.read(s, at)?,
+            Mode::Relative => {
+                let offset = self.read(s, at)?;
+                self.apply(s, Op::Add, Rc::new(Expr::Const(s.rb)), offset)?
+            }
+        };
+        match v.constant() {
+            Some(v) => {
+                Ok(Addr::Known(address(v).map_err(|e| Machine::fail(s.pc, e))?))
+            }
+            None => Ok(Addr::Unknown(v)),
+        }
+    }
+
+    fn load(&self, s: &State, m: &[Mode], n: usize) -> Result<Term, End> {
+        match self.addr(s, m, n)? {
+            Addr::Known(addr) => self.read(s, addr),
+            Addr::Unknown(addr) => Ok(Rc::new(Expr::Load(addr))),
+        }
+    }
+
+    fn store(
+        &self,
+        s: &mut State,
+        m: &[Mode],
+        n: usize,
+        v: Term,
+    ) -> Result<(), End> {
+        match self.addr(s, m, n)? {
+            Addr::Known(addr) => self.write(s, addr, v),
+            Addr::Unknown(_) => Err(End::Unknown { pc: s.pc }),
+        }
+    }
+
+    /// Applies `op`, folding constants and the obvious identities.
+    fn apply(&self, s: &State, op: Op, a: Term, b: Term) -> Result<Term, End> {
+        let overflow = || {
+            let error = crate::Error::boxed(Error::Overflow { pc: s.pc });
+            Machine::fail(s.pc, error)
+        };
+        let arith = |checked: fn(Value, Value) -> Option<Value>,
+                     wrapping: fn(Value, Value) -> Value,
+                     x,
+                     y| match self.config.arith {
+            Arith::Checked => checked(x, y).ok_or_else(&overflow),
+            Arith::Wrapping => Ok(wrapping(x, y)),
+        };
+        let v = match (op, a.constant(), b.constant()) {
+            (Op::Add, Some(x), Some(y)) => {
+                arith(Value::checked_add, Value::wrapping_add, x, y)?
+            }
+            (Op::Mul, Some(x), Some(y)) => {
+                arith(Value::checked_mul, Value::wrapping_mul, x, y)?
+            }
+            (Op::Lt, Some(x), Some(y)) => (x < y).into(),
+            (Op::Eq, Some(x), Some(y)) => (x == y).into(),
+            (Op::Eq, ..) if a == b => 1,
+            (Op::Add, ..) => return Ok(normalize(Rc::new(Expr::Add(a, b)))),
+            (Op::Mul, ..) => return Ok(normalize(Rc::new(Expr::Mul(a, b)))),
+            (Op::Lt, ..) => return Ok(Rc::new(Expr::Lt(a, b))),
+            (Op::Eq, ..) => return Ok(Rc::new(Expr::Eq(a, b))),
+        };
+        Ok(Rc::new(Expr::Const(v)))
+    }
+
+    /// Executes a single instruction. Returns the other side of a jump on
+    /// an unknown condition, with the jump taken.
+    fn step(&self, s: &mut State) -> Result<Option<State>, End> {
+        s.steps += 1;
+        if s.steps > MAX_STEPS {
+            return Err(End::Limit);
+        }
+        let op = self.read(s, s.pc)?;
+        let op = Machine::known(s, &op)?;
+        let insn = Insn::decode(op)
+            .and_then(|insn| {
+                if insn.isa() > self.config.isa {
+                    Err(crate::Error::boxed(Error::IllegalInstruction))
+                } else {
+                    Ok(insn)
+                }
+            })
+            .map_err(|e| Machine::fail(s.pc, e))?;
+
+        let (m, op) = match insn {
+            Insn::Add(m) => (m, Op::Add),
+            Insn::Mul(m) => (m, Op::Mul),
+            Insn::Lt(m) => (m, Op::Lt),
+            Insn::Equ(m) => (m, Op::Eq),
+            Insn::In(m) => {
+                if s.inputs == self.inputs {
+                    return Err(End::OutOfInput);
+                }
+                let v = Rc::new(Expr::Var(format!("in{}", s.inputs)));
+                s.inputs += 1;
+                self.store(s, &m, 1, v)?;
+                s.pc += 2;
+                return Ok(None);
+            }
+            Insn::Out(m) => {
+                let o = self.load(s, &m, 1)?;
+                s.output.push(o);
+                s.pc += 2;
+                return Ok(None);
+            }
+            Insn::Jit(m) | Insn::Jif(m) => {
+                let jit = matches!(insn, Insn::Jit(_));
+                let cond = self.load(s, &m, 1)?;
+                let known = decide(&s.conditions, &cond);
+                if known == Some(!jit) {
+                    s.pc += 3;
+                    return Ok(None);
+                }
+                let target = self.load(s, &m, 2)?;
+                let target = Machine::known(s, &target)?;
+                let target =
+                    address(target).map_err(|e| Machine::fail(s.pc, e))?;
+                if known.is_some() {
+                    s.pc = target;
+                    return Ok(None);
+                }
+                let mut taken = s.clone();
+                taken.conditions.push((cond.clone(), jit));
+                taken.pc = target;
+                s.conditions.push((cond, !jit));
+                s.pc += 3;
+                return Ok(Some(taken));
+            }
+            Insn::Rbo(m) => {
+                let offset = self.load(s, &m, 1)?;
+                let rb = Rc::new(Expr::Const(s.rb));
+                let rb = self.apply(s, Op::Add, rb, offset)?;
+                s.rb = Machine::known(s, &rb)?;
+                s.pc += 2;
+                return Ok(None);
+            }
+            Insn::Halt => return Err(End::Halted),
+        };
+        let (a, b) = (self.load(s, &m, 1)?, self.load(s, &m, 2)?);
+        let v = self.apply(s, op, a, b)?;
+        self.store(s, &m, 3, v)?;
+        s.pc += 4;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble_with_labels;
+
+    fn machine(src: &str, cells: &[&str]) -> Machine {
+        let (mem, labels) = assemble_with_labels(src).unwrap();
+        let mut machine = Machine::new(mem, Config::DAY09);
+        for (&addr, label) in &labels {
+            if cells.contains(&label.as_str()) {
+                machine.symbolize(addr, label).unwrap();
+            }
+        }
+        machine
+    }
+
+    #[test]
+    fn linear() {
+        let paths = machine(
+            "\
+        mul [a], #3, [r]
+        add [r], [b], [r]
+        add [r], #7, [r]
+        add [a], [b], [a]
+        hlt
+r:      .data 0
+a:      .data 0
+b:      .data 0
+",
+            &["a", "b"],
+        )
+        .explore();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].end, End::Halted);
+        let r = &paths[0].mem[17];
+        assert_eq!(r.to_string(), "3 * a + b + 7");
+        let linear = r.linear().unwrap();
+        assert_eq!(linear.to_string(), "3 * a + b + 7");
+        assert_eq!(
+            linear.solutions(40, &[("a", 0..10), ("b", 0..10)]),
+            vec![vec![8, 9], vec![9, 6]]
+        );
+        assert_eq!(
+            linear.solutions(7, &[("a", 0..2), ("b", 0..2), ("c", 5..9)]),
+            vec![vec![0, 0, 5]]
+        );
+        assert!(linear.solutions(7, &[("a", 0..10)]).is_empty());
+
+        let negated = Linear {
+            coeffs: vec![("x".to_string(), -1)].into_iter().collect(),
+            constant: 0,
+        };
+        assert!(negated
+            .solutions(Value::MIN, &[("x", Value::MAX - 1..Value::MAX)])
+            .is_empty());
+        assert_eq!(negated.solutions(-5, &[("x", 0..10)]), vec![vec![5]]);
+        assert_eq!(
+            paths[0].to_string(),
+            "Halted\n  [17] = 3 * a + b + 7\n  [18] = a + b\n  [19] = b\n"
+        );
+    }
+
+    #[test]
+    fn paths() {
+        let mut machine = machine(
+            "\
+        in [x]
+        lt [x], #10, [t]
+        jit [t], #small
+        jif [t], #big
+        hlt
+small:  mul [x], #2, [x]
+        jif [t], #big
+        out [x]
+        hlt
+big:    out [x]
+        hlt
+x:      .data 0
+t:      .data 0
+",
+            &[],
+        );
+        machine.inputs(1);
+        let paths = machine.explore();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(
+            paths[0].to_string(),
+            "Halted\n  if not in0 < 10\n  out in0\n  [26] = in0\n  \
+             [27] = in0 < 10\n"
+        );
+        assert_eq!(
+            paths[1].to_string(),
+            "Halted\n  if in0 < 10\n  out 2 * in0\n  [26] = 2 * in0\n  \
+             [27] = in0 < 10\n"
+        );
+        let env =
+            |v: Value| move |name: &str| Some(v).filter(|_| name == "in0");
+        assert_eq!(paths[1].admits(&env(3)), Some(true));
+        assert_eq!(paths[1].admits(&env(10)), Some(false));
+        assert_eq!(paths[1].output[0].eval(&env(3)), Some(6));
+
+        machine.inputs(0);
+        assert_eq!(machine.explore()[0].end, End::OutOfInput);
+    }
+
+    #[test]
+    fn unknown_addresses() {
+        let src = "\
+        add [p], #0, [q]
+        jit #1, [p]
+p:      .data 0
+q:      .data 0
+";
+        let paths = machine(src, &["p"]).explore();
+        assert_eq!(paths[0].end, End::Unknown { pc: 4 });
+
+        let mut mem = vec![1, 5, 6, 0, 99, 0, 0];
+        let mut machine = Machine::new(mem.clone(), Config::DAY02);
+        machine.symbolize(1, "noun").unwrap();
+        assert_eq!(machine.explore()[0].mem[0].to_string(), "[noun]");
+        mem[3] = 9;
+        let mut machine = Machine::new(mem, Config::DAY02);
+        machine.symbolize(1, "noun").unwrap();
+        assert_eq!(
+            machine.explore()[0].end,
+            End::Failed {
+                pc: 0,
+                error: "AddressOutOfRange".to_string()
+            }
+        );
+    }
+}
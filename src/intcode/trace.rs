@@ -0,0 +1,607 @@
+use super::pages::Pages;
+use super::{Insn, Value};
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Bumped whenever the file format changes.
+const VERSION: Value = 1;
+
+enum Error {
+    Malformed { line: usize },
+    UnsupportedVersion(Value),
+    Empty,
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed { line } => write!(f, "Malformed line {}", line),
+            Error::UnsupportedVersion(v) => {
+                write!(f, "UnsupportedVersion {}", v)
+            }
+            Error::Empty => write!(f, "Empty"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemWrite {
+    pub addr: usize,
+    pub old: Value,
+    pub new: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Io {
+    Input(Value),
+    Output(Value),
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub pc: usize,
+    pub rb: Value,
+    pub insn: Insn,
+    /// Values of the operands read, in order.
+    pub operands: Vec<Value>,
+    pub writes: Vec<MemWrite>,
+    pub io: Option<Io>,
+}
+
+impl Record {
+    /// The `pc` and `rb` after this instruction, or `None` if the record
+    /// lacks the operands to tell.
+    fn next(&self) -> Option<(usize, Value)> {
+        let operand = |n: usize| self.operands.get(n).copied();
+        let fallthrough = self.pc.checked_add(self.insn.len());
+        let jump = |taken: bool| {
+            if taken {
+                usize::try_from(operand(1)?).ok()
+            } else {
+                fallthrough
+            }
+        };
+        match self.insn {
+            Insn::Jit(_) => Some((jump(operand(0)? != 0)?, self.rb)),
+            Insn::Jif(_) => Some((jump(operand(0)? == 0)?, self.rb)),
+            // machines with checked arithmetic fail rather than wrap
+            Insn::Rbo(_) => {
+                Some((fallthrough?, self.rb.wrapping_add(operand(0)?)))
+            }
+            Insn::Halt => Some((self.pc, self.rb)),
+            _ => Some((fallthrough?, self.rb)),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}: {} {:?}",
+            self.pc,
+            self.insn.mnemonic(),
+            self.operands
+        )?;
+        for w in &self.writes {
+            write!(f, " [{}] {} -> {}", w.addr, w.old, w.new)?;
+        }
+        match self.io {
+            Some(Io::Input(v)) => write!(f, " in {}", v),
+            Some(Io::Output(v)) => write!(f, " out {}", v),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The instructions executed by an `Iss` since tracing was started, along
+/// with the memory they started from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub mem: Vec<Value>,
+    pub records: Vec<Record>,
+}
+
+impl Trace {
+    pub(super) fn new(mem: Vec<Value>) -> Self {
+        Trace {
+            mem,
+            records: Vec::new(),
+        }
+    }
+
+    /// Writes the trace as JSON lines. The first line holds the format version
+    /// and the initial memory, each following line one record, wrapped here:
+    ///
+    /// ```text
+    /// {"version":1,"mem":[1101,2,3,5,99,0]}
+    /// {"pc":0,"rb":0,"op":1101,"insn":"add","operands":[2,3],
+    ///  "writes":[[5,0,5]]}
+    /// {"pc":4,"rb":0,"op":99,"insn":"hlt","operands":[],"writes":[]}
+    /// ```
+    ///
+    /// Writes are `[addr, old, new]`. Records of `in` and `out` carry an
+    /// additional `"in"` or `"out"` field with the value transferred.
+    pub fn write(&self, mut w: impl Write) -> crate::Result<()> {
+        writeln!(w, "{{\"version\":{},\"mem\":{}}}", VERSION, list(&self.mem))?;
+        for r in &self.records {
+            let writes: Vec<String> = r
+                .writes
+                .iter()
+                .map(|w| format!("[{},{},{}]", w.addr, w.old, w.new))
+                .collect();
+            write!(
+                w,
+                "{{\"pc\":{},\"rb\":{},\"op\":{},\"insn\":\"{}\",\
+                 \"operands\":{},\"writes\":[{}]",
+                r.pc,
+                r.rb,
+                r.insn.encode(),
+                r.insn.mnemonic(),
+                list(&r.operands),
+                writes.join(",")
+            )?;
+            match r.io {
+                Some(Io::Input(v)) => write!(w, ",\"in\":{}", v)?,
+                Some(Io::Output(v)) => write!(w, ",\"out\":{}", v)?,
+                None => (),
+            }
+            writeln!(w, "}}")?;
+        }
+        Ok(())
+    }
+
+    /// Reads a trace written by `write`.
+    pub fn read(r: impl BufRead) -> crate::Result<Self> {
+        let mut lines = r.lines().enumerate();
+        let (_, header) = lines
+            .next()
+            .ok_or_else(|| crate::Error::boxed(Error::Empty))?;
+        let header = Json::parse(&header?, 1)?;
+        let version = header.field("version", 1)?.num(1)?;
+        if version != VERSION {
+            return Err(crate::Error::boxed(Error::UnsupportedVersion(
+                version,
+            )));
+        }
+        let mut trace = Trace::new(header.field("mem", 1)?.nums(1)?);
+
+        for (idx, line) in lines {
+            let line_no = idx + 1;
+            let json = Json::parse(&line?, line_no)?;
+            trace.records.push(Record::from_json(&json, line_no)?);
+        }
+        Ok(trace)
+    }
+}
+
+fn list(values: &[Value]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+impl Record {
+    fn from_json(json: &Json, line: usize) -> crate::Result<Self> {
+        let addr = |v: Value| {
+            usize::try_from(v)
+                .map_err(|_| crate::Error::boxed(Error::Malformed { line }))
+        };
+
+        let writes = match json.field("writes", line)? {
+            Json::Arr(writes) => writes
+                .iter()
+                .map(|w| match w.nums(line)?.as_slice() {
+                    &[a, old, new] => Ok(MemWrite {
+                        addr: addr(a)?,
+                        old,
+                        new,
+                    }),
+                    _ => {
+                        Err(crate::Error::boxed(Error::Malformed { line })
+                            .into())
+                    }
+                })
+                .collect::<crate::Result<_>>()?,
+            _ => return Err(crate::Error::boxed(Error::Malformed { line })),
+        };
+        let io = if let Ok(v) = json.field("in", line) {
+            Some(Io::Input(v.num(line)?))
+        } else if let Ok(v) = json.field("out", line) {
+            Some(Io::Output(v.num(line)?))
+        } else {
+            None
+        };
+
+        let record = Record {
+            pc: addr(json.field("pc", line)?.num(line)?)?,
+            rb: json.field("rb", line)?.num(line)?,
+            insn: Insn::decode(json.field("op", line)?.num(line)?)?,
+            operands: json.field("operands", line)?.nums(line)?,
+            writes,
+            io,
+        };
+        if record.next().is_none() {
+            return Err(crate::Error::boxed(Error::Malformed { line }));
+        }
+        Ok(record)
+    }
+}
+
+/// The subset of JSON the trace format uses.
+enum Json {
+    Num(Value),
+    Str,
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(s: &str, line: usize) -> crate::Result<Self> {
+        let mut chars = s.chars().peekable();
+        match Json::value(&mut chars) {
+            Some(json) if chars.all(char::is_whitespace) => Ok(json),
+            _ => Err(crate::Error::boxed(Error::Malformed { line })),
+        }
+    }
+
+    fn skip_ws(chars: &mut Peekable<Chars>) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+
+    fn string(chars: &mut Peekable<Chars>) -> Option<String> {
+        let mut s = String::new();
+        loop {
+            match chars.next()? {
+                '"' => return Some(s),
+                '\\' => s.push(chars.next()?),
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn value(chars: &mut Peekable<Chars>) -> Option<Json> {
+        Json::skip_ws(chars);
+        match chars.next()? {
+            '"' => Json::string(chars).map(|_| Json::Str),
+            '[' => {
+                let mut items = Vec::new();
+                Json::skip_ws(chars);
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Some(Json::Arr(items));
+                }
+                loop {
+                    items.push(Json::value(chars)?);
+                    Json::skip_ws(chars);
+                    match chars.next()? {
+                        ',' => (),
+                        ']' => return Some(Json::Arr(items)),
+                        _ => return None,
+                    }
+                }
+            }
+            '{' => {
+                let mut fields = Vec::new();
+                loop {
+                    Json::skip_ws(chars);
+                    match chars.next()? {
+                        '"' => (),
+                        '}' if fields.is_empty() => {
+                            return Some(Json::Obj(fields))
+                        }
+                        _ => return None,
+                    }
+                    let name = Json::string(chars)?;
+                    Json::skip_ws(chars);
+                    if chars.next()? != ':' {
+                        return None;
+                    }
+                    fields.push((name, Json::value(chars)?));
+                    Json::skip_ws(chars);
+                    match chars.next()? {
+                        ',' => (),
+                        '}' => return Some(Json::Obj(fields)),
+                        _ => return None,
+                    }
+                }
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut num = c.to_string();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit())
+                {
+                    num.push(c);
+                    chars.next();
+                }
+                num.parse().ok().map(Json::Num)
+            }
+            _ => None,
+        }
+    }
+
+    fn field(&self, name: &str, line: usize) -> crate::Result<&Json> {
+        let fields = match self {
+            Json::Obj(fields) => fields,
+            _ => return Err(crate::Error::boxed(Error::Malformed { line })),
+        };
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+            .ok_or_else(|| {
+                crate::Error::boxed(Error::Malformed { line }).into()
+            })
+    }
+
+    fn num(&self, line: usize) -> crate::Result<Value> {
+        match self {
+            Json::Num(v) => Ok(*v),
+            _ => Err(crate::Error::boxed(Error::Malformed { line })),
+        }
+    }
+
+    fn nums(&self, line: usize) -> crate::Result<Vec<Value>> {
+        match self {
+            Json::Arr(items) => items.iter().map(|v| v.num(line)).collect(),
+            _ => Err(crate::Error::boxed(Error::Malformed { line })),
+        }
+    }
+}
+
+/// Steps through a trace in either direction without executing anything.
+pub struct Replayer<'a> {
+    trace: &'a Trace,
+    mem: Pages,
+    pos: usize,
+}
+
+impl<'a> Replayer<'a> {
+    pub fn new(trace: &'a Trace) -> Self {
+        Replayer {
+            trace,
+            mem: trace.mem.clone().into(),
+            pos: 0,
+        }
+    }
+
+    /// Number of records replayed so far.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn mem(&self) -> &Pages {
+        &self.mem
+    }
+
+    /// The `pc` and `rb` at the current position. At the end of the trace,
+    /// `None` if the last record lacks the operands to tell.
+    pub fn regs(&self) -> Option<(usize, Value)> {
+        match self.trace.records.get(self.pos) {
+            Some(r) => Some((r.pc, r.rb)),
+            None if self.pos > 0 => self.trace.records[self.pos - 1].next(),
+            None => Some((0, 0)),
+        }
+    }
+
+    /// Replays the next record and returns it.
+    pub fn forward(&mut self) -> Option<&'a Record> {
+        let record = self.trace.records.get(self.pos)?;
+        for w in &record.writes {
+            *self.mem.cell_mut(w.addr) = w.new;
+        }
+        self.pos += 1;
+        Some(record)
+    }
+
+    /// Undoes the last replayed record and returns it.
+    pub fn back(&mut self) -> Option<&'a Record> {
+        self.pos = self.pos.checked_sub(1)?;
+        let record = &self.trace.records[self.pos];
+        for w in record.writes.iter().rev() {
+            *self.mem.cell_mut(w.addr) = w.old;
+        }
+        Some(record)
+    }
+}
+
+/// Index of the first record in which two traces differ, if any. A trace
+/// that is a prefix of the other diverges where it ends.
+pub fn diverge(a: &Trace, b: &Trace) -> Option<usize> {
+    if a.mem != b.mem {
+        return Some(0);
+    }
+    a.records
+        .iter()
+        .zip(&b.records)
+        .position(|(a, b)| a != b)
+        .or_else(|| {
+            if a.records.len() != b.records.len() {
+                Some(a.records.len().min(b.records.len()))
+            } else {
+                None
+            }
+        })
+}
+
+/// Reports where two traces diverge, with up to `context` of the preceding
+/// common records and the memory cells that differ at that point.
+pub fn diff(
+    a: &Trace,
+    b: &Trace,
+    context: usize,
+    out: &mut impl Write,
+) -> crate::Result<()> {
+    let pos = match diverge(a, b) {
+        Some(pos) => pos,
+        None => {
+            writeln!(out, "traces are identical")?;
+            return Ok(());
+        }
+    };
+
+    let (mut replay_a, mut replay_b) = (Replayer::new(a), Replayer::new(b));
+    while replay_a.pos() < pos {
+        replay_a.forward();
+        replay_b.forward();
+    }
+    let mut common = Vec::new();
+    while common.len() < context {
+        match (replay_a.back(), replay_b.back()) {
+            (Some(r), Some(_)) => common.push(r),
+            _ => break,
+        }
+    }
+    writeln!(out, "diverging after {} instructions", pos)?;
+    for r in common.iter().rev() {
+        writeln!(out, "  {}", r)?;
+        replay_a.forward();
+        replay_b.forward();
+    }
+
+    if let Some((pc, rb)) = replay_a.regs() {
+        writeln!(out, "at pc {}, rb {}", pc, rb)?;
+    }
+    let show =
+        |r: Option<&Record>| r.map_or("<end>".to_string(), |r| r.to_string());
+    writeln!(out, "< {}", show(a.records.get(pos)))?;
+    writeln!(out, "> {}", show(b.records.get(pos)))?;
+
+    let (ma, mb) = (replay_a.mem(), replay_b.mem());
+    let dense = ma.len().max(mb.len());
+    let far: BTreeSet<usize> = ma
+        .far()
+        .chain(mb.far())
+        .map(|(addr, _)| addr)
+        .filter(|&addr| addr >= dense)
+        .collect();
+    for addr in (0..dense).chain(far) {
+        let (va, vb) = (ma.get(addr), mb.get(addr));
+        if va != vb {
+            let show =
+                |v: Option<Value>| v.map_or("-".to_string(), |v| v.to_string());
+            writeln!(out, "  [{}] {} / {}", addr, show(va), show(vb))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Iss;
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    // sums inputs until it reads a 0
+    const SUM: &str = "\
+loop:   in [x]
+        jif [x], #done
+        add [x], [sum], [sum]
+        jit #1, #loop
+done:   out [sum]
+        hlt
+x:      .data 0
+sum:    .data 0
+";
+
+    fn record(input: &[Value]) -> Trace {
+        let mut iss = Iss::with_input(assemble(SUM).unwrap(), input.to_vec());
+        iss.start_trace();
+        iss.run_continuous().unwrap();
+        iss.take_trace().unwrap()
+    }
+
+    #[test]
+    fn records() {
+        let trace = record(&[5, 0]);
+        assert_eq!(trace.records.len(), 8);
+        assert_eq!(
+            trace.records[2],
+            Record {
+                pc: 5,
+                rb: 0,
+                insn: Insn::decode(1).unwrap(),
+                operands: vec![5, 0],
+                writes: vec![MemWrite {
+                    addr: 16,
+                    old: 0,
+                    new: 5
+                }],
+                io: None,
+            }
+        );
+        assert_eq!(trace.records[0].io, Some(Io::Input(5)));
+        assert_eq!(trace.records[6].io, Some(Io::Output(5)));
+    }
+
+    #[test]
+    fn round_trip() {
+        let trace = record(&[5, -7, 0]);
+        let mut file = Vec::new();
+        trace.write(&mut file).unwrap();
+        assert_eq!(Trace::read(file.as_slice()).unwrap(), trace);
+
+        assert!(Trace::read(&b""[..]).is_err());
+        assert!(Trace::read(&b"{\"version\":0,\"mem\":[]}"[..]).is_err());
+        assert!(Trace::read(&b"{\"version\":1,\"mem\":[1,]}"[..]).is_err());
+        // a taken jump needs its target
+        let jump = |operands: &str| {
+            let file = format!(
+                "{{\"version\":1,\"mem\":[]}}\n\
+                 {{\"pc\":0,\"rb\":0,\"op\":1105,\"operands\":{},\
+                 \"writes\":[]}}",
+                operands
+            );
+            Trace::read(file.as_bytes()).map_err(|e| e.to_string())
+        };
+        assert!(jump("[1,7]").is_ok());
+        assert_eq!(jump("[1]").unwrap_err(), "Malformed line 2");
+        assert_eq!(jump("[1,-7]").unwrap_err(), "Malformed line 2");
+    }
+
+    #[test]
+    fn replay() {
+        let trace = record(&[5, 6, 0]);
+        let mut replayer = Replayer::new(&trace);
+        while replayer.forward().is_some() {}
+        assert_eq!(replayer.regs(), Some((14, 0)));
+        assert_eq!(replayer.mem().to_vec()[15..], [0, 11]);
+
+        for _ in 0..6 {
+            replayer.back();
+        }
+        assert_eq!(replayer.pos(), 6);
+        assert_eq!(replayer.regs(), Some((5, 0)));
+        assert_eq!(replayer.mem().to_vec()[15..], [6, 5]);
+
+        while replayer.back().is_some() {}
+        assert_eq!(replayer.mem().to_vec(), trace.mem);
+    }
+
+    #[test]
+    fn diverging_runs() {
+        let a = record(&[5, 6, 0]);
+        let b = record(&[5, 7, 0]);
+        assert_eq!(diverge(&a, &a), None);
+        assert_eq!(diverge(&a, &b), Some(4));
+        assert_eq!(diverge(&a, &record(&[5, 0])), Some(4));
+
+        let mut out = Vec::new();
+        diff(&a, &b, 2, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "diverging after 4 instructions\n\
+             \x20 0005: add [5, 0] [16] 0 -> 5\n\
+             \x20 0009: jit [1, 0]\n\
+             at pc 0, rb 0\n\
+             < 0000: in [] [15] 5 -> 6 in 6\n\
+             > 0000: in [] [15] 5 -> 7 in 7\n"
+        );
+    }
+}
@@ -23,34 +23,61 @@ mod day22;
 mod day23;
 mod day24;
 mod day25;
+mod intcode;
 
-use std::{env, error, fmt, fs, result};
+use std::{env, error, fmt, fs, io, result};
 
 #[derive(Debug)]
 struct UsageError;
 
 type Result<T> = result::Result<T, Box<dyn error::Error>>;
 
-#[derive(Debug)]
-struct Error<T> {
+pub struct Error<T> {
     err: T,
+    source: Option<Box<dyn error::Error>>,
 }
 
 impl<T> Error<T> {
     fn boxed(err: T) -> Box<Self> {
-        Box::new(Self { err })
+        Box::new(Self { err, source: None })
+    }
+
+    /// Like `boxed`, for an error caused by `source`.
+    fn caused_by(err: T, source: Box<dyn error::Error>) -> Box<Self> {
+        Box::new(Self {
+            err,
+            source: Some(source),
+        })
     }
 }
 
 impl<T: fmt::Debug> fmt::Display for Error<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self.err)
+        write!(f, "{:?}", self.err)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows the error in detail along with the whole chain of sources, as
+/// that is what `main` reports.
+impl<T: fmt::Debug> fmt::Debug for Error<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#?}", self.err)?;
+        let mut source = error::Error::source(self);
+        while let Some(err) = source {
+            write!(f, "\ncaused by: {}", err)?;
+            source = err.source();
+        }
+        Ok(())
     }
 }
 
 impl<T: fmt::Debug> error::Error for Error<T> {
-    fn cause(&self) -> Option<&dyn error::Error> {
-        Some(self)
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.as_deref()
     }
 }
 
@@ -68,12 +95,271 @@ fn time<F: Fn(A) -> B, A, B>(f: F, a: A) -> B {
     res
 }
 
-fn usage() -> Result<()> {
+/// Times part 1 of days 15 and 19, which copy machines a lot, once forking
+/// them and once copying all of their memory.
+fn bench_fork(runs: u32) -> Result<()> {
+    type Part1 = fn(&str, intcode::Fork) -> Result<intcode::Value>;
+    let days: [(u32, Part1); 2] =
+        [(15, day15::part1_with), (19, day19::part1_with)];
+    let copy: intcode::Fork = |iss| {
+        let mut iss = iss.fork();
+        iss.unshare();
+        iss
+    };
+
+    for (day, part1) in days.iter() {
+        let input = read_file(Some(format!("input/day{:02}", day)))?;
+        let fork: intcode::Fork = intcode::Iss::fork;
+        for (name, f) in &[("fork", fork), ("copy", copy)] {
+            let now = std::time::Instant::now();
+            for _ in 0..runs {
+                part1(input.trim(), *f)?;
+            }
+            println!("day{} {}: {:?} per run", day, name, now.elapsed() / runs);
+        }
+    }
+    Ok(())
+}
+
+fn usage<T>() -> Result<T> {
     eprintln!("usage: aoc2019 <day> [<input>]");
+    eprintln!("       aoc2019 asm <source>");
+    eprintln!("       aoc2019 disasm <program>");
+    eprintln!("       aoc2019 cfg <program>");
+    eprintln!("       aoc2019 decompile <program>");
+    eprintln!("       aoc2019 run <program> [<max-steps>]");
+    eprintln!("       aoc2019 run-big <program> [<input>..]");
+    eprintln!("       aoc2019 play <program> [<transcript>]");
+    eprintln!("       aoc2019 debug <program>");
+    eprintln!("       aoc2019 trace <program> [<input>..]");
+    eprintln!("       aoc2019 trace-diff <trace> <trace>");
+    eprintln!("       aoc2019 profile <program> [report | folded] [<input>..]");
+    eprintln!("       aoc2019 coverage <source> [text | lcov] [<input>..]");
+    eprintln!("       aoc2019 symbolic <program> <inputs> [<address>..]");
+    eprintln!("       aoc2019 fuzz [<seed>] [<runs>]");
+    eprintln!("       aoc2019 netcap <program> [summary | <address>]");
+    eprintln!("       aoc2019 bench-fork [<runs>]");
     Err(Error::boxed(UsageError {}))
 }
 
+fn read_file(path: Option<String>) -> Result<String> {
+    let path = if let Some(path) = path {
+        path
+    } else {
+        eprintln!("Not enough arguments");
+        return usage();
+    };
+
+    if let Ok(content) = fs::read_to_string(&path) {
+        Ok(content)
+    } else {
+        eprintln!("No such file: '{}'", &path);
+        usage()
+    }
+}
+
 fn main() -> Result<()> {
+    match env::args().nth(1).as_deref() {
+        Some("asm") => {
+            let mem = intcode::asm::assemble(&read_file(env::args().nth(2))?)?;
+            let mem: Vec<String> = mem.iter().map(|v| v.to_string()).collect();
+            println!("{}", mem.join(","));
+            return Ok(());
+        }
+        Some("disasm") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            print!("{}", intcode::disasm::disassemble(&mem));
+            return Ok(());
+        }
+        Some("cfg") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            print!("{}", intcode::cfg::Cfg::build(&mem).to_dot());
+            return Ok(());
+        }
+        Some("decompile") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            let cfg = intcode::cfg::Cfg::build(&mem);
+            print!("{}", intcode::decompile::decompile(&cfg));
+            return Ok(());
+        }
+        Some("run") => {
+            use intcode::{Budget, StopReason};
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            let mut iss = intcode::Iss::new(mem);
+            iss.set_budget(Budget {
+                steps: env::args().nth(3).map(|s| s.parse()).transpose()?,
+                ..Budget::default()
+            });
+            iss.detect_cycles(true);
+            let stdin = io::stdin();
+            let mut ascii =
+                intcode::device::Ascii::new(stdin.lock(), io::stdout());
+            match iss.drive(&mut ascii)? {
+                StopReason::BudgetExhausted => {
+                    eprintln!("out of steps at {}", iss.pc())
+                }
+                StopReason::InfiniteLoop { pc, period } => eprintln!(
+                    "infinite loop at {} every {} instructions",
+                    pc, period
+                ),
+                _ => (),
+            }
+            return Ok(());
+        }
+        Some("run-big") => {
+            use intcode::big::{Big, Machine};
+            let mem = intcode::big::parse(&read_file(env::args().nth(2))?)?;
+            let mut machine: Machine<Big> = Machine::new(mem);
+            for i in env::args().skip(3) {
+                machine.feed_input(i.parse()?);
+            }
+            while let intcode::Event::Output(o) = machine.run()? {
+                println!("{}", o);
+            }
+            return Ok(());
+        }
+        Some("play") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            let mut console =
+                intcode::ascii::AsciiConsole::new(intcode::Iss::new(mem));
+            if let Some(path) = env::args().nth(3) {
+                console = console.with_transcript(fs::File::create(path)?);
+            }
+            let stdin = io::stdin();
+            return console.play(stdin.lock(), io::stdout());
+        }
+        Some("debug") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            let iss = intcode::Iss::new(mem);
+            let mut dbg = intcode::debugger::Debugger::new(iss);
+            return dbg.repl(io::stdin().lock(), io::stdout());
+        }
+        Some("trace") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            let input = env::args()
+                .skip(3)
+                .map(|v| v.parse())
+                .collect::<result::Result<_, _>>()?;
+            let mut iss = intcode::Iss::with_input(mem, input);
+            iss.start_trace();
+            iss.run_continuous()?;
+            if let Some(trace) = iss.take_trace() {
+                trace.write(io::BufWriter::new(io::stdout().lock()))?;
+            }
+            return Ok(());
+        }
+        Some("trace-diff") => {
+            let a = read_file(env::args().nth(2))?;
+            let b = read_file(env::args().nth(3))?;
+            let a = intcode::trace::Trace::read(a.as_bytes())?;
+            let b = intcode::trace::Trace::read(b.as_bytes())?;
+            return intcode::trace::diff(&a, &b, 5, &mut io::stdout());
+        }
+        Some("profile") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            let folded = match env::args().nth(3).as_deref() {
+                None | Some("report") => false,
+                Some("folded") => true,
+                Some(_) => return usage(),
+            };
+            let input = env::args()
+                .skip(4)
+                .map(|v| v.parse())
+                .collect::<result::Result<_, _>>()?;
+            let mut iss = intcode::Iss::with_input(mem, input);
+            iss.start_profile();
+            iss.run_continuous()?;
+            if let Some(profile) = iss.take_profile() {
+                let mut out = io::BufWriter::new(io::stdout().lock());
+                if folded {
+                    profile.write_folded(&mut out)?;
+                } else {
+                    profile.write_report(&mut out)?;
+                }
+            }
+            return Ok(());
+        }
+        Some("coverage") => {
+            let path = env::args().nth(2);
+            let src = read_file(path.clone())?;
+            let (mem, labels) = intcode::asm::assemble_with_labels(&src)?;
+            let lcov = match env::args().nth(3).as_deref() {
+                None | Some("text") => false,
+                Some("lcov") => true,
+                Some(_) => return usage(),
+            };
+            let input = env::args()
+                .skip(4)
+                .map(|v| v.parse())
+                .collect::<result::Result<_, _>>()?;
+            let mut iss = intcode::Iss::with_input(mem, input);
+            iss.start_coverage();
+            iss.run_continuous()?;
+            if let Some(coverage) = iss.take_coverage() {
+                let mut out = io::BufWriter::new(io::stdout().lock());
+                if lcov {
+                    let source = path.unwrap_or_default();
+                    coverage.write_lcov(&source, &labels, &mut out)?;
+                } else {
+                    coverage.write_text(&labels, &mut out)?;
+                }
+            }
+            return Ok(());
+        }
+        Some("symbolic") => {
+            let mem = intcode::parse(&read_file(env::args().nth(2))?)?;
+            let config = intcode::Config::default();
+            let mut machine = intcode::symbolic::Machine::new(mem, config);
+            match env::args().nth(3).map(|n| n.parse()) {
+                Some(n) => machine.inputs(n?),
+                None => return usage(),
+            }
+            for addr in env::args().skip(4) {
+                let name = format!("m{}", addr);
+                machine.symbolize(addr.parse()?, &name)?;
+            }
+            for (idx, path) in machine.explore().iter().enumerate() {
+                print!("path {}: {}", idx, path);
+            }
+            return Ok(());
+        }
+        Some("fuzz") => {
+            let seed = env::args().nth(2).map_or(Ok(0), |s| s.parse())?;
+            let runs = env::args().nth(3).map_or(Ok(1000), |s| s.parse())?;
+            match intcode::fuzz::fuzz(seed, runs) {
+                Some((program, mismatch)) => {
+                    print!("{}\n{}", program, mismatch)
+                }
+                None => println!("{} programs agree", runs),
+            }
+            return Ok(());
+        }
+        Some("bench-fork") => {
+            let runs = match env::args().nth(2) {
+                Some(runs) => runs.parse()?,
+                None => 10,
+            };
+            return bench_fork(runs);
+        }
+        Some("netcap") => {
+            let input = read_file(env::args().nth(2))?;
+            let mut capture = intcode::network::Capture::default();
+            let res = day23::capture(input.trim(), &mut capture);
+            let mut out = io::BufWriter::new(io::stdout().lock());
+            match env::args().nth(3).as_deref() {
+                Some("summary") => capture.summary(&mut out)?,
+                Some(address) => {
+                    capture.write(Some(address.parse()?), &mut out)?
+                }
+                None => capture.write(None, &mut out)?,
+            }
+            drop(out);
+            println!("# nat stopped with {}", res?);
+            return Ok(());
+        }
+        _ => (),
+    }
+
     let (day, input) = {
         let mut args = env::args().skip(1);
         let d = if let Some(d) = args.next() {